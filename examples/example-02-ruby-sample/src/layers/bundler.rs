@@ -1,41 +1,40 @@
 use std::collections::HashMap;
-use std::fs;
 use std::path::Path;
 use std::process::Command;
 
 use anyhow::Error;
 use libcnb::{BuildContext, GenericPlatform};
 use libcnb::data::layer_content_metadata::LayerContentMetadata;
-use libcnb::layer_lifecycle::{LayerLifecycle, ValidateResult};
+use libcnb::layer::{Layer, ValidateResult};
+use libcnb::layer_validation::{CacheKeys, FileChecksum, LayerValidator};
 use serde::Deserialize;
 use serde::Serialize;
-use sha2::Digest;
 
 use crate::RubyBuildpackMetadata;
 
-pub struct BundlerLayerLifecycle {
+pub struct BundlerLayer {
     pub ruby_env: HashMap<String, String>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct BundlerLayerMetadata {
-    checksum: String,
+    cache_keys: CacheKeys,
 }
 
-impl LayerLifecycle<GenericPlatform, RubyBuildpackMetadata, BundlerLayerMetadata, Option<()>, anyhow::Error> for BundlerLayerLifecycle {
-    fn validate(&self, layer_path: &Path, layer_content_metadata: &LayerContentMetadata<BundlerLayerMetadata>, build_context: &BuildContext<GenericPlatform, RubyBuildpackMetadata>) -> ValidateResult {
-        let checksum_matches = sha256_checksum(build_context.app_dir.join("Gemfile.lock"))
-            .map(|local_checksum| local_checksum == layer_content_metadata.metadata.checksum)
-            .unwrap_or(false);
+fn gemfile_lock_validator() -> impl LayerValidator {
+    FileChecksum::new("Gemfile.lock").on_change(ValidateResult::UpdateLayer)
+}
 
-        if checksum_matches {
-            ValidateResult::KeepLayer
-        } else {
-            ValidateResult::UpdateLayer
-        }
+impl Layer<GenericPlatform, RubyBuildpackMetadata> for BundlerLayer {
+    type Metadata = BundlerLayerMetadata;
+    type Output = ();
+    type Error = anyhow::Error;
+
+    fn validate(&self, layer_path: &Path, layer_content_metadata: &LayerContentMetadata<BundlerLayerMetadata>, build_context: &BuildContext<GenericPlatform, RubyBuildpackMetadata>) -> ValidateResult {
+        gemfile_lock_validator().validate(&layer_content_metadata.metadata.cache_keys, build_context)
     }
 
-    fn update(&self, layer_path: &Path, layer_content_metadata: LayerContentMetadata<BundlerLayerMetadata>, build_context: &BuildContext<GenericPlatform, RubyBuildpackMetadata>) -> Result<LayerContentMetadata<BundlerLayerMetadata>, Error> {
+    fn update(&self, layer_path: &Path, mut layer_content_metadata: LayerContentMetadata<BundlerLayerMetadata>, build_context: &BuildContext<GenericPlatform, RubyBuildpackMetadata>) -> Result<LayerContentMetadata<BundlerLayerMetadata>, Error> {
         println!("---> Reusing gems");
         Command::new("bundle")
             .args(&[
@@ -59,7 +58,8 @@ impl LayerLifecycle<GenericPlatform, RubyBuildpackMetadata, BundlerLayerMetadata
             .spawn()?
             .wait()?;
 
-        Ok(layer_content_metadata)
+        let cache_keys = gemfile_lock_validator().updated_cache_keys(&layer_content_metadata.metadata.cache_keys, build_context)?;
+        Ok(layer_content_metadata.metadata(BundlerLayerMetadata { cache_keys }))
     }
 
     fn create(&self, layer_path: &Path, build_context: &BuildContext<GenericPlatform, RubyBuildpackMetadata>) -> Result<LayerContentMetadata<BundlerLayerMetadata>, Error> {
@@ -81,13 +81,11 @@ impl LayerLifecycle<GenericPlatform, RubyBuildpackMetadata, BundlerLayerMetadata
         }
 
         Ok(LayerContentMetadata::default().launch(true).cache(true).metadata(BundlerLayerMetadata {
-            checksum: sha256_checksum(build_context.app_dir.join("Gemfile.lock"))?
+            cache_keys: gemfile_lock_validator().cache_keys(build_context)?
         }))
     }
-}
 
-fn sha256_checksum(path: impl AsRef<Path>) -> anyhow::Result<String> {
-    Ok(fs::read(path)
-        .map(|bytes| sha2::Sha256::digest(bytes.as_ref()))
-        .map(|bytes| format!("{:x}", bytes))?)
+    fn output(&self, layer_path: &Path, layer_content_metadata: LayerContentMetadata<BundlerLayerMetadata>) -> Result<(), Error> {
+        Ok(())
+    }
 }
@@ -1,32 +1,30 @@
 use std::collections::HashMap;
-use std::fs;
-use std::io;
 use std::path::Path;
 
 use anyhow::Error;
-use flate2::read::GzDecoder;
 use libcnb::{BuildContext, GenericMetadata, GenericPlatform};
 use libcnb::data::layer_content_metadata::LayerContentMetadata;
-use libcnb::layer_lifecycle::LayerLifecycle;
+use libcnb::layer::Layer;
+use libcnb::transfer::Download;
 use serde::{Deserialize, Serialize};
-use tar::Archive;
-use tempfile::NamedTempFile;
 use std::env;
 
 use crate::RubyBuildpackMetadata;
 
-pub struct RubyLayerLifecycle;
+pub struct RubyLayer;
+
+impl Layer<GenericPlatform, RubyBuildpackMetadata> for RubyLayer {
+    type Metadata = GenericMetadata;
+    type Output = HashMap<String, String>;
+    type Error = anyhow::Error;
 
-impl LayerLifecycle<GenericPlatform, RubyBuildpackMetadata, GenericMetadata, HashMap<String, String>, anyhow::Error> for RubyLayerLifecycle {
     fn create(&self, layer_path: &Path, build_context: &BuildContext<GenericPlatform, RubyBuildpackMetadata>) -> Result<LayerContentMetadata<GenericMetadata>, anyhow::Error> {
-        let ruby_tgz = NamedTempFile::new()?;
-        download(&build_context.buildpack_descriptor.metadata.ruby_url, ruby_tgz.path())?;
-        untar(ruby_tgz.path(), &layer_path)?;
+        Download::new(&build_context.buildpack_descriptor.metadata.ruby_url).extract_to(layer_path)?;
 
         Ok(LayerContentMetadata::default().launch(true))
     }
 
-    fn layer_lifecycle_data(&self, layer_path: &Path, layer_content_metadata: LayerContentMetadata<GenericMetadata>) -> Result<HashMap<String, String>, Error> {
+    fn output(&self, layer_path: &Path, layer_content_metadata: LayerContentMetadata<GenericMetadata>) -> Result<HashMap<String, String>, Error> {
         let mut ruby_env: HashMap<String, String> = HashMap::new();
         let ruby_bin_path = format!(
             "{}/.gem/ruby/2.6.6/bin",
@@ -59,21 +57,3 @@ impl LayerLifecycle<GenericPlatform, RubyBuildpackMetadata, GenericMetadata, Has
         Ok(ruby_env)
     }
 }
-
-fn download(uri: impl AsRef<str>, dst: impl AsRef<Path>) -> anyhow::Result<()> {
-    let response = reqwest::blocking::get(uri.as_ref())?;
-    let mut content = io::Cursor::new(response.bytes()?);
-    let mut file = fs::File::create(dst.as_ref())?;
-    io::copy(&mut content, &mut file)?;
-
-    Ok(())
-}
-
-fn untar(file: impl AsRef<Path>, dst: impl AsRef<Path>) -> anyhow::Result<()> {
-    let tar_gz = fs::File::open(file.as_ref())?;
-    let tar = GzDecoder::new(tar_gz);
-    let mut archive = Archive::new(tar);
-    archive.unpack(dst.as_ref())?;
-
-    Ok(())
-}
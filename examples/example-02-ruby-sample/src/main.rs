@@ -5,12 +5,12 @@ use anyhow::Error;
 use libcnb::{BuildContext, cnb_runtime, DetectContext, DetectOutcome, GenericErrorHandler, GenericPlatform};
 use libcnb::data::build_plan::BuildPlan;
 use libcnb::data;
-use libcnb::layer_lifecycle::execute_layer_lifecycle;
+use libcnb::layer_graph::LayerGraph;
 use serde::Deserialize;
 
-use crate::layers::bundler::BundlerLayerLifecycle;
+use crate::layers::bundler::BundlerLayer;
 use crate::layers::ruby;
-use crate::layers::ruby::RubyLayerLifecycle;
+use crate::layers::ruby::RubyLayer;
 
 mod layers;
 
@@ -32,11 +32,19 @@ fn build(context: BuildContext<GenericPlatform, RubyBuildpackMetadata>) -> libcn
     println!("---> Ruby Buildpack");
     println!("---> Download and extracting Ruby");
 
-    let ruby_env = execute_layer_lifecycle("ruby", RubyLayerLifecycle, &context)?;
+    LayerGraph::new()
+        .layer("ruby", &[], |_| Ok(RubyLayer))
+        .layer("bundler", &["ruby"], |dependencies| {
+            let ruby_env = dependencies
+                .output::<HashMap<String, String>>("ruby")
+                .cloned()
+                .unwrap_or_default();
 
-    println!("---> Installing bundler");
-    install_bundler(&ruby_env)?;
-    execute_layer_lifecycle("bundler", BundlerLayerLifecycle { ruby_env }, &context)?;
+            println!("---> Installing bundler");
+            install_bundler(&ruby_env)?;
+            Ok(BundlerLayer { ruby_env })
+        })
+        .execute(&context)?;
 
     write_launch(&context);
     Ok(())
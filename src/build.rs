@@ -1,26 +1,61 @@
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, sync::Mutex};
 
+use semver::VersionReq;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 use crate::{
     data::{
-        buildpack::BuildpackToml, buildpack_plan::BuildpackPlan, launch::Launch,
-        layer_content_metadata::LayerContentMetadata,
+        buildpack::BuildpackToml,
+        buildpack_plan::BuildpackPlan,
+        dependency::{Dependency, DependencyError, DependencyManifest},
+        launch::Launch,
+        layer_content_metadata::{LayerContentMetadata, LayerContentTypeTable},
+        project::ProjectToml,
     },
+    layer_pruning::LayerUsage,
+    mode::LifecycleMode,
     platform::Platform,
+    target::Target,
     toml_file::{read_toml_file, write_toml_file, TomlFileError},
+    transfer::{ClientConfig, Download, DownloadCache, TransferClient, TransferError},
 };
 
+/// Prints a warning if the given layer types have effects that are usually unintended, at most
+/// once per layer and build.
+pub(crate) fn warn_about_layer_types(
+    layer_usage: &LayerUsage,
+    layer_name: &str,
+    types: &LayerContentTypeTable,
+) {
+    if let Some(warning) = types.warning() {
+        if layer_usage.mark_warned(layer_name) {
+            eprintln!("Warning: Layer '{}': {}", layer_name, warning);
+        }
+    }
+}
+
 /// Context for a buildpack's build phase execution.
 pub struct BuildContext<P: Platform, BM> {
     pub layers_dir: PathBuf,
     pub app_dir: PathBuf,
     pub buildpack_dir: PathBuf,
     pub stack_id: String,
+    pub target: Target,
     pub platform: P,
+    pub lifecycle_mode: Option<LifecycleMode>,
     pub buildpack_plan: BuildpackPlan,
     pub buildpack_descriptor: BuildpackToml<BM>,
+    pub project_descriptor: Option<ProjectToml>,
+
+    /// Layers touched during this build, see [`layer_pruning`](crate::layer_pruning).
+    pub layer_usage: LayerUsage,
+
+    /// Downloads cached across builds, see [`BuildContext::download`].
+    pub download_cache: DownloadCache,
+
+    /// The client returned by [`BuildContext::transfer_client`], created on first use.
+    pub(crate) transfer_client: Mutex<Option<TransferClient>>,
 }
 
 impl<P: Platform, BM> BuildContext<P, BM> {
@@ -28,6 +63,92 @@ impl<P: Platform, BM> BuildContext<P, BM> {
         self.layers_dir.join(layer_name.as_ref())
     }
 
+    /// The client for transfers during this build, configured with
+    /// [`ClientConfig::from_build_context`].
+    ///
+    /// The client is created on the first call and shared by all later calls.
+    pub fn transfer_client(&self) -> Result<TransferClient, TransferError> {
+        let mut transfer_client = self
+            .transfer_client
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        match &*transfer_client {
+            Some(client) => Ok(client.clone()),
+            None => {
+                let client = ClientConfig::from_build_context(self)?.build()?;
+                *transfer_client = Some(client.clone());
+                Ok(client)
+            }
+        }
+    }
+
+    /// Creates a [`Download`] that uses the [`BuildContext::transfer_client`] and the
+    /// [`BuildContext::download_cache`].
+    pub fn download(&self, url: impl Into<String>) -> Result<Download, TransferError> {
+        Ok(Download::new(url)
+            .client(self.transfer_client()?)
+            .cache(self.download_cache.clone()))
+    }
+
+    /// Resolves a dependency for the stack and target of this build, see
+    /// [`DependencyManifest::resolve`].
+    ///
+    /// Prints a warning if the resolved dependency is deprecated.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use libcnb::data::dependency::DependencyManifest;
+    /// use libcnb::{Error, GenericBuildContext, Result};
+    /// use semver::VersionReq;
+    ///
+    /// fn build(context: GenericBuildContext) -> Result<(), anyhow::Error> {
+    ///     let manifest =
+    ///         DependencyManifest::from_generic_metadata(&context.buildpack_descriptor.metadata)
+    ///             .map_err(|error| Error::BuildpackError(error.into()))?;
+    ///
+    ///     let node = context
+    ///         .resolve_dependency(&manifest, "node", &VersionReq::parse("^18").unwrap())
+    ///         .map_err(|error| Error::BuildpackError(error.into()))?;
+    ///
+    ///     context
+    ///         .download_dependency(node)
+    ///         .map_err(|error| Error::BuildpackError(error.into()))?
+    ///         .prefix(format!("node-v{}-linux-x64", node.version))
+    ///         .extract_to(context.layer_path("node"))
+    ///         .map_err(|error| Error::BuildpackError(error.into()))?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn resolve_dependency<'a>(
+        &self,
+        manifest: &'a DependencyManifest,
+        id: &str,
+        requirement: &VersionReq,
+    ) -> Result<&'a Dependency, DependencyError> {
+        let dependency = manifest.resolve(id, requirement, &self.stack_id, &self.target)?;
+
+        if let Some(deprecation_date) = &dependency.deprecation_date {
+            if dependency.is_deprecated() {
+                eprintln!(
+                    "Warning: {} {} is deprecated since {}, consider upgrading",
+                    dependency.id, dependency.version, deprecation_date
+                );
+            }
+        }
+
+        Ok(dependency)
+    }
+
+    /// Creates a [`Download`] of the dependency that verifies its checksum, see
+    /// [`BuildContext::download`].
+    pub fn download_dependency(&self, dependency: &Dependency) -> Result<Download, TransferError> {
+        Ok(self
+            .download(&dependency.uri)?
+            .checksum(dependency.checksum.clone()))
+    }
+
     pub fn layer_content_metadata_path(&self, layer_name: impl AsRef<str>) -> PathBuf {
         self.layers_dir
             .join(format!("{}.toml", layer_name.as_ref()))
@@ -51,6 +172,13 @@ impl<P: Platform, BM> BuildContext<P, BM> {
         layer_name: impl AsRef<str>,
         layer_content_metadata: &LayerContentMetadata<M>,
     ) -> Result<(), TomlFileError> {
+        self.layer_usage.touch(&layer_name);
+        warn_about_layer_types(
+            &self.layer_usage,
+            layer_name.as_ref(),
+            &layer_content_metadata.types,
+        );
+
         write_toml_file(
             layer_content_metadata,
             self.layer_content_metadata_path(layer_name),
@@ -58,6 +186,8 @@ impl<P: Platform, BM> BuildContext<P, BM> {
     }
 
     pub fn delete_layer(&self, layer_name: impl AsRef<str>) -> Result<(), std::io::Error> {
+        self.layer_usage.touch(&layer_name);
+
         // Do not fail if the metadata file does not exist
         match fs::remove_file(self.layer_content_metadata_path(&layer_name)) {
             Err(io_error) => match io_error.kind() {
@@ -108,3 +238,32 @@ impl<P: Platform, BM> BuildContext<P, BM> {
         write_toml_file(&data, self.layers_dir.join("launch.toml"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{build_context, tar_xz};
+
+    #[test]
+    fn download_resolves_bundled_dependencies() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let dependency = context
+            .buildpack_dir
+            .join("dependencies/nodejs.org/dist/node.tar.xz");
+        std::fs::create_dir_all(dependency.parent().unwrap()).unwrap();
+        std::fs::write(&dependency, tar_xz(&[("node/README", "Node.js")])).unwrap();
+
+        context
+            .download("https://nodejs.org/dist/node.tar.xz")
+            .unwrap()
+            .prefix("node")
+            .extract_to(context.layer_path("node"))
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(context.layer_path("node").join("README")).unwrap(),
+            "Node.js"
+        );
+    }
+}
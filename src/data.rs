@@ -1,11 +1,19 @@
 //! Low-level representations for Cloud Native Buildpack data types.
 
+pub mod analyzed;
 pub mod bom;
 pub mod build;
 pub mod build_plan;
 pub mod buildpack;
 pub mod buildpack_plan;
 pub mod defaults;
+pub mod dependency;
+pub mod group;
 pub mod launch;
 pub mod layer_content_metadata;
+pub mod lifecycle_metadata;
+pub mod order;
+pub mod plan;
+pub mod project;
 pub mod store;
+pub mod versioned_metadata;
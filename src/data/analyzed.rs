@@ -0,0 +1,204 @@
+use crate::data::bom;
+use crate::data::buildpack::BuildpackId;
+use crate::data::layer_content_metadata::LayerContentTypeTable;
+use crate::data::store::Store;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Data structure for the analysis file (analyzed.toml) written by the lifecycle analyzer.
+///
+/// Contains the reference and layer metadata of the previous image, if one exists.
+///
+/// See [Platform specification](https://github.com/buildpacks/spec/blob/main/platform.md#analyzedtoml-toml)
+///
+/// # Examples
+/// ```
+/// use libcnb::data::analyzed::AnalyzedToml;
+///
+///         let raw = r#"
+/// [image]
+/// reference = "registry.example.com/app@sha256:abc"
+///
+/// [metadata.launcher]
+/// sha = "sha256:123"
+///
+/// [[metadata.buildpacks]]
+/// key = "foo/bar"
+/// version = "0.0.1"
+///
+/// [metadata.buildpacks.layers.ruby]
+/// sha = "sha256:456"
+/// launch = true
+/// "#;
+///
+///         let result = toml::from_str::<AnalyzedToml>(raw).unwrap();
+///         let buildpack = &result.metadata.buildpacks[0];
+///         assert!(buildpack.layers.get("ruby").unwrap().types.launch);
+/// ```
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct AnalyzedToml {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<ImageIdentifier>,
+    #[serde(default)]
+    pub metadata: LayersMetadata,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ImageIdentifier {
+    pub reference: String,
+}
+
+/// Metadata of all layers of an app image, as stored in the `io.buildpacks.lifecycle.metadata`
+/// label of the image.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct LayersMetadata {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub app: Vec<LayerMetadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<LayerMetadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub launcher: Option<LayerMetadata>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buildpacks: Vec<BuildpackLayersMetadata>,
+    #[serde(default, rename = "runImage", skip_serializing_if = "Option::is_none")]
+    pub run_image: Option<RunImageMetadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack: Option<StackMetadata>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bom: bom::Bom,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LayerMetadata {
+    pub sha: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct BuildpackLayersMetadata {
+    pub key: BuildpackId,
+    pub version: String,
+    #[serde(default)]
+    pub layers: HashMap<String, BuildpackLayerMetadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<Store>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct BuildpackLayerMetadata {
+    pub sha: String,
+    #[serde(flatten)]
+    pub types: LayerContentTypeTable,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<toml::Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RunImageMetadata {
+    #[serde(rename = "topLayer")]
+    pub top_layer: String,
+    pub reference: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct StackMetadata {
+    #[serde(rename = "runImage")]
+    pub run_image: StackRunImageMetadata,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct StackRunImageMetadata {
+    pub image: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_empty() {
+        let result = toml::from_str::<AnalyzedToml>("").unwrap();
+        assert!(result.image.is_none());
+        assert!(result.metadata.buildpacks.is_empty());
+    }
+
+    #[test]
+    fn it_round_trips() {
+        let raw = r#"
+[image]
+reference = "registry.example.com/app@sha256:abc"
+
+[[metadata.app]]
+sha = "sha256:app"
+
+[metadata.config]
+sha = "sha256:config"
+
+[metadata.launcher]
+sha = "sha256:launcher"
+
+[[metadata.buildpacks]]
+key = "foo/bar"
+version = "0.0.1"
+
+[metadata.buildpacks.layers.ruby]
+sha = "sha256:ruby"
+launch = true
+cache = true
+
+[metadata.buildpacks.layers.ruby.data]
+version = "3.0.2"
+
+[metadata.runImage]
+topLayer = "sha256:top"
+reference = "registry.example.com/run@sha256:def"
+
+[metadata.stack.runImage]
+image = "registry.example.com/run"
+mirrors = ["mirror.example.com/run"]
+"#;
+
+        let analyzed_toml = toml::from_str::<AnalyzedToml>(raw).unwrap();
+        let serialized = toml::to_string(&analyzed_toml).unwrap();
+        let result = toml::from_str::<AnalyzedToml>(&serialized).unwrap();
+
+        assert_eq!(
+            result.image.unwrap().reference,
+            "registry.example.com/app@sha256:abc"
+        );
+        assert_eq!(result.metadata.app[0].sha, "sha256:app");
+        assert_eq!(result.metadata.run_image.unwrap().top_layer, "sha256:top");
+        assert_eq!(
+            result.metadata.stack.unwrap().run_image.mirrors,
+            vec!["mirror.example.com/run"]
+        );
+
+        let ruby_layer = result.metadata.buildpacks[0].layers.get("ruby").unwrap();
+        assert!(ruby_layer.types.launch);
+        assert!(ruby_layer.types.cache);
+        assert!(!ruby_layer.types.build);
+        assert_eq!(
+            ruby_layer
+                .data
+                .as_ref()
+                .unwrap()
+                .get("version")
+                .unwrap()
+                .as_str(),
+            Some("3.0.2")
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_buildpack_ids() {
+        let raw = r#"
+[[metadata.buildpacks]]
+key = "config"
+version = "0.0.1"
+"#;
+
+        let result = toml::from_str::<AnalyzedToml>(raw);
+        assert!(result.is_err());
+    }
+}
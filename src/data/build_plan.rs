@@ -1,4 +1,4 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use toml::value::Table;
 
@@ -109,10 +109,11 @@ impl Provide {
     }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Require {
-    name: String,
-    metadata: Table,
+    pub name: String,
+    #[serde(default)]
+    pub metadata: Table,
 }
 
 impl Require {
@@ -2,7 +2,7 @@ use crate::data::defaults;
 use lazy_static::lazy_static;
 use regex::Regex;
 use semver::Version;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize, Serializer};
 use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
 use std::{fmt, str::FromStr};
@@ -40,8 +40,11 @@ pub struct BuildpackToml<BM> {
     // MUST be in form <major>.<minor> or <major>, where <major> is equivalent to <major>.0.
     pub api: BuildpackApi,
     pub buildpack: Buildpack,
+    #[serde(default)]
     pub stacks: Vec<Stack>,
     #[serde(default)]
+    pub targets: Vec<Target>,
+    #[serde(default)]
     pub order: Vec<Order>,
     pub metadata: BM,
 }
@@ -90,22 +93,73 @@ impl TryFrom<StackUnchecked> for Stack {
     }
 }
 
-#[derive(Deserialize, Debug)]
+/// buildpack.toml Target. Declares an os/arch/distro combination the buildpack supports.
+///
+/// Fields that are not set match any value. Use [`Target::matches`] to check a declaration against
+/// the [`crate::Target`] of the current build.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, Eq, PartialEq)]
+pub struct Target {
+    pub os: Option<String>,
+    pub arch: Option<String>,
+    pub variant: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub distros: Vec<Distro>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct Distro {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl Target {
+    /// Checks if this declaration matches the given target of a build.
+    pub fn matches(&self, target: &crate::Target) -> bool {
+        fn field_matches(declared: Option<&String>, actual: Option<&str>) -> bool {
+            match declared {
+                None => true,
+                Some(declared) => Some(declared.as_str()) == actual,
+            }
+        }
+
+        field_matches(self.os.as_ref(), Some(&target.os))
+            && field_matches(self.arch.as_ref(), Some(&target.arch))
+            && field_matches(self.variant.as_ref(), target.arch_variant.as_deref())
+            && (self.distros.is_empty()
+                || self.distros.iter().any(|distro| {
+                    Some(distro.name.as_str()) == target.distro_name.as_deref()
+                        && field_matches(distro.version.as_ref(), target.distro_version.as_deref())
+                }))
+    }
+
+    /// The number of constraints of this declaration. More specific declarations are preferred
+    /// when selecting between multiple matching ones.
+    pub fn specificity(&self) -> usize {
+        [&self.os, &self.arch, &self.variant]
+            .iter()
+            .filter(|field| field.is_some())
+            .count()
+            + usize::from(!self.distros.is_empty())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Order {
-    // TODO fix this.  Use it or lose it.
-    #[allow(dead_code)]
-    group: Vec<Group>,
+    pub group: Vec<Group>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Group {
     pub id: BuildpackId,
     pub version: Version,
     #[serde(default = "defaults::r#false")]
     pub optional: bool,
+    // Only written by the lifecycle in the platform `group.toml` and `metadata.toml` files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api: Option<BuildpackApi>,
 }
 
-#[derive(Deserialize, Debug, Eq, PartialEq)]
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone, Copy)]
 #[serde(try_from = "BuildpackApiUnchecked")]
 pub struct BuildpackApi {
     pub major: u32,
@@ -158,6 +212,12 @@ impl FromStr for BuildpackApi {
     }
 }
 
+impl Serialize for BuildpackApi {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
 impl Display for BuildpackApi {
     fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
         formatter.write_str(&format!("{}.{}", self.major, self.minor))
@@ -180,9 +240,18 @@ impl Display for BuildpackApi {
 /// let invalid = BuildpackId::from_str("!nvalid");
 /// assert!(invalid.is_err());
 /// ```
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq, Hash)]
+#[serde(try_from = "String")]
 pub struct BuildpackId(String);
 
+impl TryFrom<String> for BuildpackId {
+    type Error = BuildpackTomlError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        BuildpackId::from_str(value.as_str())
+    }
+}
+
 impl FromStr for BuildpackId {
     type Err = BuildpackTomlError;
 
@@ -206,6 +275,12 @@ impl BuildpackId {
     }
 }
 
+impl Display for BuildpackId {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        formatter.write_str(&self.0)
+    }
+}
+
 /// buildpack.toml Stack Id. This is a newtype wrapper around a String.
 /// It MUST only contain numbers, letters, and the characters ., /, and -.
 /// or be `*`.
@@ -409,6 +484,82 @@ checksum = "awesome"
         assert!(&result.is_err());
     }
 
+    #[test]
+    fn can_deserialize_buildpack_with_targets() {
+        let raw = r#"
+api = "0.6"
+
+[buildpack]
+id = "foo/bar"
+name = "Bar Buildpack"
+version = "0.0.1"
+
+[[targets]]
+os = "linux"
+arch = "amd64"
+
+[[targets]]
+os = "linux"
+arch = "arm64"
+variant = "v8"
+[[targets.distros]]
+name = "ubuntu"
+version = "22.04"
+"#;
+
+        let result = toml::from_str::<BuildpackToml<Option<toml::value::Table>>>(raw).unwrap();
+        assert!(result.stacks.is_empty());
+        assert_eq!(result.targets.len(), 2);
+        assert_eq!(result.targets[1].variant.as_deref(), Some("v8"));
+        assert_eq!(result.targets[1].distros[0].name, "ubuntu");
+    }
+
+    #[test]
+    fn target_matches() {
+        let target = crate::Target {
+            os: String::from("linux"),
+            arch: String::from("arm64"),
+            arch_variant: None,
+            distro_name: Some(String::from("ubuntu")),
+            distro_version: Some(String::from("22.04")),
+        };
+
+        assert!(Target::default().matches(&target));
+
+        let declaration = Target {
+            os: Some(String::from("linux")),
+            arch: Some(String::from("arm64")),
+            variant: None,
+            distros: vec![Distro {
+                name: String::from("ubuntu"),
+                version: None,
+            }],
+        };
+        assert!(declaration.matches(&target));
+        assert_eq!(declaration.specificity(), 3);
+
+        let declaration = Target {
+            arch: Some(String::from("amd64")),
+            ..Target::default()
+        };
+        assert!(!declaration.matches(&target));
+
+        let declaration = Target {
+            variant: Some(String::from("v8")),
+            ..Target::default()
+        };
+        assert!(!declaration.matches(&target));
+
+        let declaration = Target {
+            distros: vec![Distro {
+                name: String::from("ubuntu"),
+                version: Some(String::from("20.04")),
+            }],
+            ..Target::default()
+        };
+        assert!(!declaration.matches(&target));
+    }
+
     #[test]
     fn buildpack_api_display() {
         assert_eq!(BuildpackApi { major: 1, minor: 0 }.to_string(), "1.0");
@@ -0,0 +1,476 @@
+//! Dependencies a buildpack installs, declared in the `[metadata]` of buildpack.toml.
+//!
+//! ```toml
+//! [[metadata.dependencies]]
+//! id = "node"
+//! version = "18.12.1"
+//! uri = "https://nodejs.org/dist/v18.12.1/node-v18.12.1-linux-x64.tar.xz"
+//! sha256 = "4481a34bf32ddb9a9ff9540338539401320e8c3628af39929b4211ea3552a19e"
+//! stacks = ["heroku-20", "heroku-22"]
+//! targets = [{ os = "linux", arch = "amd64" }]
+//! licenses = [{ type = "MIT", uri = "https://github.com/nodejs/node/blob/main/LICENSE" }]
+//! deprecation_date = 2025-04-30
+//! ```
+//!
+//! Instead of `sha256`, a `checksum` in the form `<algorithm>:<hex digest>` can be given. Empty
+//! or missing `stacks` and `targets` match any stack or target, `"*"` matches any stack.
+
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+
+use crate::data::buildpack::Target;
+use crate::generic::GenericMetadata;
+use crate::transfer::{Checksum, ChecksumError};
+
+/// The dependencies of a buildpack.
+///
+/// Use it as the buildpack metadata type, as a field of it, or read it from generic metadata with
+/// [`DependencyManifest::from_generic_metadata`].
+///
+/// # Example
+/// ```
+/// use libcnb::data::dependency::DependencyManifest;
+/// use semver::VersionReq;
+///
+/// let manifest: DependencyManifest = toml::from_str(
+///     r#"
+/// [[dependencies]]
+/// id = "node"
+/// version = "16.18.1"
+/// uri = "https://nodejs.org/dist/v16.18.1/node-v16.18.1-linux-x64.tar.xz"
+/// sha256 = "de2c694e7081c37022817d27a65b02f69ecf4c49699d65585e8e24431b7bc920"
+///
+/// [[dependencies]]
+/// id = "node"
+/// version = "18.12.1"
+/// uri = "https://nodejs.org/dist/v18.12.1/node-v18.12.1-linux-x64.tar.xz"
+/// sha256 = "4481a34bf32ddb9a9ff9540338539401320e8c3628af39929b4211ea3552a19e"
+/// "#,
+/// )
+/// .unwrap();
+///
+/// let node = manifest
+///     .resolve(
+///         "node",
+///         &VersionReq::parse(">=16").unwrap(),
+///         "heroku-20",
+///         &libcnb::Target::from_stack_id("heroku-20"),
+///     )
+///     .unwrap();
+/// assert_eq!(node.version.to_string(), "18.12.1");
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct DependencyManifest {
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
+}
+
+impl DependencyManifest {
+    /// Reads the manifest from the `dependencies` of generic buildpack metadata.
+    pub fn from_generic_metadata(metadata: &GenericMetadata) -> Result<Self, DependencyError> {
+        match metadata {
+            Some(table) => toml::Value::Table(table.clone())
+                .try_into()
+                .map_err(DependencyError::InvalidManifest),
+            None => Ok(DependencyManifest::default()),
+        }
+    }
+
+    /// Finds the dependency with the highest version that matches the requirement and is
+    /// available for the given stack and target.
+    ///
+    /// When multiple dependencies with that version match, the one with the most specific
+    /// target declaration is returned, see [`crate::Target::select`].
+    pub fn resolve(
+        &self,
+        id: &str,
+        requirement: &VersionReq,
+        stack_id: &str,
+        target: &crate::Target,
+    ) -> Result<&Dependency, DependencyError> {
+        let mut resolved: Option<(&Dependency, usize)> = None;
+
+        for dependency in &self.dependencies {
+            if dependency.id != id
+                || !requirement.matches(&dependency.version)
+                || !dependency.supports_stack(stack_id)
+            {
+                continue;
+            }
+
+            if let Some(specificity) = dependency.target_specificity(target) {
+                let better = resolved.map_or(true, |(resolved, resolved_specificity)| {
+                    (&dependency.version, specificity) > (&resolved.version, resolved_specificity)
+                });
+
+                if better {
+                    resolved = Some((dependency, specificity));
+                }
+            }
+        }
+
+        resolved.map(|(dependency, _)| dependency).ok_or_else(|| {
+            DependencyError::NoMatchingDependency {
+                id: String::from(id),
+                requirement: requirement.clone(),
+                stack_id: String::from(stack_id),
+                available: self.available_versions(id),
+            }
+        })
+    }
+
+    /// All versions of a dependency in the manifest, regardless of stack and target.
+    fn available_versions(&self, id: &str) -> Vec<Version> {
+        let mut versions: Vec<Version> = self
+            .dependencies
+            .iter()
+            .filter(|dependency| dependency.id == id)
+            .map(|dependency| dependency.version.clone())
+            .collect();
+
+        versions.sort();
+        versions.dedup();
+        versions
+    }
+}
+
+/// A single artifact of a dependency.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "DependencyUnchecked")]
+pub struct Dependency {
+    pub id: String,
+    pub version: Version,
+    pub uri: String,
+    pub checksum: Checksum,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stacks: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<Target>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub licenses: Vec<License>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecation_date: Option<toml::value::Datetime>,
+}
+
+// Used as a "shadow" struct to store
+// potentially invalid `Dependency` data when deserializing
+// https://dev.to/equalma/validate-fields-and-types-in-serde-with-tryfrom-c2n
+#[derive(Deserialize)]
+struct DependencyUnchecked {
+    id: String,
+    version: Version,
+    uri: String,
+    checksum: Option<Checksum>,
+    sha256: Option<String>,
+    #[serde(default)]
+    stacks: Vec<String>,
+    #[serde(default)]
+    targets: Vec<Target>,
+    #[serde(default)]
+    licenses: Vec<License>,
+    deprecation_date: Option<toml::value::Datetime>,
+}
+
+impl TryFrom<DependencyUnchecked> for Dependency {
+    type Error = DependencyError;
+
+    fn try_from(value: DependencyUnchecked) -> Result<Self, Self::Error> {
+        let checksum = match (value.checksum, value.sha256) {
+            (Some(checksum), None) => checksum,
+            (None, Some(sha256)) => Checksum::from_str(&format!("sha256:{}", sha256))?,
+            _ => return Err(DependencyError::AmbiguousChecksum(value.id, value.version)),
+        };
+
+        Ok(Dependency {
+            id: value.id,
+            version: value.version,
+            uri: value.uri,
+            checksum,
+            stacks: value.stacks,
+            targets: value.targets,
+            licenses: value.licenses,
+            deprecation_date: value.deprecation_date,
+        })
+    }
+}
+
+impl Dependency {
+    pub fn supports_stack(&self, stack_id: &str) -> bool {
+        self.stacks.is_empty() || self.stacks.iter().any(|id| id == "*" || id == stack_id)
+    }
+
+    /// Checks whether the dependency is deprecated on the given day, in the form `YYYY-MM-DD`.
+    pub fn is_deprecated_on(&self, date: &str) -> bool {
+        self.deprecation_date
+            .as_ref()
+            .map_or(false, |deprecation_date| {
+                deprecation_date.to_string().get(..10).unwrap_or_default() <= date
+            })
+    }
+
+    /// Checks whether the dependency is deprecated today (UTC).
+    pub fn is_deprecated(&self) -> bool {
+        self.is_deprecated_on(&today())
+    }
+
+    /// The specificity of the most specific matching target declaration, `None` if no
+    /// declaration matches.
+    fn target_specificity(&self, target: &crate::Target) -> Option<usize> {
+        if self.targets.is_empty() {
+            Some(0)
+        } else {
+            target
+                .select(&self.targets, |declaration| declaration)
+                .map(Target::specificity)
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct License {
+    #[serde(rename = "type")]
+    pub r#type: Option<String>,
+    pub uri: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DependencyError {
+    #[error("Dependency {0} {1} MUST have either a `checksum` or a `sha256`, but not both")]
+    AmbiguousChecksum(String, Version),
+
+    #[error("Invalid dependency checksum: {0}")]
+    InvalidChecksum(#[from] ChecksumError),
+
+    #[error("Invalid dependency manifest: {0}")]
+    InvalidManifest(toml::de::Error),
+
+    #[error("No version of {id} matches {requirement} on stack {stack_id}, available versions: {}", format_versions(.available))]
+    NoMatchingDependency {
+        id: String,
+        requirement: VersionReq,
+        stack_id: String,
+        available: Vec<Version>,
+    },
+}
+
+fn format_versions(versions: &[Version]) -> String {
+    if versions.is_empty() {
+        String::from("none")
+    } else {
+        versions
+            .iter()
+            .map(Version::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// The current day (UTC) in the form `YYYY-MM-DD`.
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() / 86_400);
+
+    // Converts days since the Unix epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> DependencyManifest {
+        toml::from_str(
+            r#"
+[[dependencies]]
+id = "node"
+version = "18.12.1"
+uri = "https://example.com/node-18.12.1-amd64.tar.xz"
+sha256 = "4481a34bf32ddb9a9ff9540338539401320e8c3628af39929b4211ea3552a19e"
+stacks = ["heroku-20", "heroku-22"]
+targets = [{ os = "linux" }, { os = "linux", arch = "amd64" }]
+licenses = [{ type = "MIT" }]
+
+[[dependencies]]
+id = "node"
+version = "18.12.1"
+uri = "https://example.com/node-18.12.1.tar.xz"
+sha256 = "de2c694e7081c37022817d27a65b02f69ecf4c49699d65585e8e24431b7bc920"
+
+[[dependencies]]
+id = "node"
+version = "18.13.0"
+uri = "https://example.com/node-18.13.0-arm64.tar.xz"
+checksum = "sha256:c2eb2b5ed87a4fa0e33f0ffd6f0bbad1aa5bb5aca2e5c0d4e2e1cd2fc6d6fb2d"
+targets = [{ arch = "arm64" }]
+
+[[dependencies]]
+id = "node"
+version = "16.18.1"
+uri = "https://example.com/node-16.18.1.tar.xz"
+sha256 = "de2c694e7081c37022817d27a65b02f69ecf4c49699d65585e8e24431b7bc920"
+stacks = ["*"]
+deprecation_date = 2023-09-11
+
+[[dependencies]]
+id = "yarn"
+version = "1.22.19"
+uri = "https://example.com/yarn-1.22.19.tar.gz"
+sha256 = "732620bac8b1690d507274f025f3c6cfdc3627a84d9642e38a07452cc00e0f2e"
+"#,
+        )
+        .unwrap()
+    }
+
+    fn target(arch: &str) -> crate::Target {
+        crate::Target {
+            os: String::from("linux"),
+            arch: String::from(arch),
+            arch_variant: None,
+            distro_name: None,
+            distro_version: None,
+        }
+    }
+
+    #[test]
+    fn it_resolves_highest_matching_version() {
+        let manifest = manifest();
+
+        let node = manifest
+            .resolve(
+                "node",
+                &VersionReq::parse("^18").unwrap(),
+                "heroku-20",
+                &target("amd64"),
+            )
+            .unwrap();
+        assert_eq!(node.uri, "https://example.com/node-18.12.1-amd64.tar.xz");
+        assert_eq!(node.licenses[0].r#type.as_deref(), Some("MIT"));
+
+        let node = manifest
+            .resolve(
+                "node",
+                &VersionReq::parse("^18").unwrap(),
+                "heroku-20",
+                &target("arm64"),
+            )
+            .unwrap();
+        assert_eq!(node.uri, "https://example.com/node-18.13.0-arm64.tar.xz");
+
+        // The stack-specific artifact is not available on other stacks.
+        let node = manifest
+            .resolve(
+                "node",
+                &VersionReq::parse("=18.12.1").unwrap(),
+                "heroku-18",
+                &target("amd64"),
+            )
+            .unwrap();
+        assert_eq!(node.uri, "https://example.com/node-18.12.1.tar.xz");
+    }
+
+    #[test]
+    fn it_lists_available_versions_without_match() {
+        let error = manifest()
+            .resolve(
+                "node",
+                &VersionReq::parse("^20").unwrap(),
+                "heroku-22",
+                &target("amd64"),
+            )
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "No version of node matches ^20 on stack heroku-22, available versions: 16.18.1, 18.12.1, 18.13.0"
+        );
+    }
+
+    #[test]
+    fn it_detects_deprecated_dependencies() {
+        let manifest = manifest();
+        let node_16 = manifest
+            .resolve(
+                "node",
+                &VersionReq::parse("^16").unwrap(),
+                "heroku-22",
+                &target("amd64"),
+            )
+            .unwrap();
+
+        assert!(!node_16.is_deprecated_on("2023-09-10"));
+        assert!(node_16.is_deprecated_on("2023-09-11"));
+        assert!(node_16.is_deprecated());
+        assert!(!manifest.dependencies[0].is_deprecated());
+    }
+
+    #[test]
+    fn it_requires_exactly_one_checksum() {
+        let result = toml::from_str::<DependencyManifest>(
+            r#"
+[[dependencies]]
+id = "node"
+version = "18.12.1"
+uri = "https://example.com/node-18.12.1.tar.xz"
+"#,
+        );
+        assert!(result.is_err());
+
+        let result = toml::from_str::<DependencyManifest>(
+            r#"
+[[dependencies]]
+id = "node"
+version = "18.12.1"
+uri = "https://example.com/node-18.12.1.tar.xz"
+sha256 = "not hex"
+"#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_reads_generic_metadata() {
+        let metadata: GenericMetadata = Some(
+            toml::from_str(
+                r#"
+[[dependencies]]
+id = "yarn"
+version = "1.22.19"
+uri = "https://example.com/yarn-1.22.19.tar.gz"
+sha256 = "732620bac8b1690d507274f025f3c6cfdc3627a84d9642e38a07452cc00e0f2e"
+"#,
+            )
+            .unwrap(),
+        );
+
+        let manifest = DependencyManifest::from_generic_metadata(&metadata).unwrap();
+        assert_eq!(manifest.dependencies.len(), 1);
+        assert_eq!(
+            DependencyManifest::from_generic_metadata(&None).unwrap(),
+            DependencyManifest::default()
+        );
+    }
+
+    #[test]
+    fn it_formats_today() {
+        let today = today();
+        assert_eq!(today.len(), 10);
+        assert!(today.as_str() > "2021-12-01");
+    }
+}
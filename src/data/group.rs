@@ -0,0 +1,104 @@
+use crate::data::buildpack::Group;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use thiserror;
+
+/// Data structure for the group file (group.toml) written by the lifecycle after detection.
+///
+/// See [Platform specification](https://github.com/buildpacks/spec/blob/main/platform.md#grouptoml-toml)
+///
+/// # Examples
+/// ```
+/// use libcnb::data::group::GroupToml;
+///
+///         let raw = r#"
+/// [[group]]
+/// id = "foo/bar"
+/// version = "0.0.1"
+/// api = "0.6"
+/// "#;
+///
+///         let result = toml::from_str::<GroupToml>(raw).unwrap();
+///         assert_eq!(result.group[0].id.as_str(), "foo/bar");
+/// ```
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(try_from = "GroupTomlUnchecked")]
+pub struct GroupToml {
+    pub group: Vec<Group>,
+}
+
+// Used as a "shadow" struct to store
+// potentially invalid `GroupToml` data when deserializing
+// <https://dev.to/equalma/validate-fields-and-types-in-serde-with-tryfrom-c2n>
+#[derive(Deserialize)]
+struct GroupTomlUnchecked {
+    #[serde(default)]
+    group: Vec<Group>,
+}
+
+impl TryFrom<GroupTomlUnchecked> for GroupToml {
+    type Error = GroupTomlError;
+
+    fn try_from(value: GroupTomlUnchecked) -> Result<Self, Self::Error> {
+        let GroupTomlUnchecked { group } = value;
+
+        let mut seen = HashSet::new();
+        for entry in &group {
+            if !seen.insert(&entry.id) {
+                return Err(GroupTomlError::DuplicateBuildpack(entry.id.to_string()));
+            }
+        }
+
+        Ok(GroupToml { group })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum GroupTomlError {
+    #[error("Buildpack `{0}` MUST NOT appear more than once in a group.")]
+    DuplicateBuildpack(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips() {
+        let raw = r#"
+[[group]]
+id = "foo/bar"
+version = "0.0.1"
+api = "0.6"
+
+[[group]]
+id = "foo/baz"
+version = "0.0.2"
+"#;
+
+        let group_toml = toml::from_str::<GroupToml>(raw).unwrap();
+        let serialized = toml::to_string(&group_toml).unwrap();
+        let result = toml::from_str::<GroupToml>(&serialized).unwrap();
+
+        assert_eq!(result.group.len(), 2);
+        assert_eq!(result.group[0].api.unwrap().to_string(), "0.6");
+        assert_eq!(result.group[1].api, None);
+    }
+
+    #[test]
+    fn it_rejects_duplicate_buildpacks() {
+        let raw = r#"
+[[group]]
+id = "foo/bar"
+version = "0.0.1"
+
+[[group]]
+id = "foo/bar"
+version = "0.0.2"
+"#;
+
+        let result = toml::from_str::<GroupToml>(raw);
+        assert!(result.is_err());
+    }
+}
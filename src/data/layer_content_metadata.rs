@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::data::defaults;
 use crate::generic::GenericMetadata;
+use crate::mode::LifecycleMode;
 
 /// Used to specify layer availability based
 /// on buildpack phase.
@@ -28,6 +29,45 @@ impl LayerContentTypeTable {
             cache: false,
         }
     }
+
+    /// Checks this combination of layer types for effects that are usually unintended.
+    ///
+    /// libcnb prints the warning when writing layer content metadata. Buildpacks that want to
+    /// treat them as errors can call this function themselves, [`LayerTypesWarning`] implements
+    /// [`std::error::Error`].
+    ///
+    /// ```
+    /// use libcnb::data::layer_content_metadata::{LayerContentMetadata, LayerTypesWarning};
+    ///
+    /// let layer = LayerContentMetadata::default().cache(true);
+    /// assert_eq!(layer.types.warning(), Some(LayerTypesWarning::CacheOnly));
+    ///
+    /// let layer = LayerContentMetadata::default().build(true).cache(true);
+    /// assert_eq!(layer.types.warning(), None);
+    /// ```
+    pub fn warning(&self) -> Option<LayerTypesWarning> {
+        match (self.launch, self.build, self.cache) {
+            (false, false, false) => Some(LayerTypesWarning::Discarded),
+            (false, false, true) => Some(LayerTypesWarning::CacheOnly),
+            (true, _, false) => Some(LayerTypesWarning::LaunchNotCached),
+            _ => None,
+        }
+    }
+}
+
+/// A combination of layer types with effects that are usually unintended.
+///
+/// See [`LayerContentTypeTable::warning`].
+#[derive(thiserror::Error, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LayerTypesWarning {
+    #[error("The layer is neither a launch, build nor cache layer. Its contents are discarded after this buildpack finished and are neither available to subsequent buildpacks, nor in the next build, nor at launch. Set at least one of `launch`, `build` or `cache` to keep the layer.")]
+    Discarded,
+
+    #[error("The layer is a cache layer only. Its contents are restored for this buildpack in the next build, but are not available to subsequent buildpacks or at launch. Also set `build` to make it available to subsequent buildpacks, or `launch` to make it available at launch.")]
+    CacheOnly,
+
+    #[error("The layer is a launch layer, but not a cache layer. Only its metadata is restored in the next build, so its contents are only reused if the layer is kept based on its metadata. Also set `cache` if the layer is updated rather than kept or recreated.")]
+    LaunchNotCached,
 }
 
 /// Describes Layer Content Metadata
@@ -87,6 +127,19 @@ impl<M> LayerContentMetadata<M> {
         self
     }
 
+    /// Adjusts the layer types to what the given lifecycle mode allows.
+    ///
+    /// Layers are never made available at launch in [`LifecycleMode::CI`] builds. Other modes and
+    /// an unknown mode (`None`) leave the types untouched.
+    #[must_use]
+    pub fn for_lifecycle_mode(mut self, lifecycle_mode: Option<LifecycleMode>) -> Self {
+        if let Some(lifecycle_mode) = lifecycle_mode {
+            self.types.launch = self.types.launch && lifecycle_mode.allows_launch_layers();
+        }
+
+        self
+    }
+
     pub fn metadata<NM>(&mut self, metadata: NM) -> LayerContentMetadata<NM> {
         LayerContentMetadata {
             types: LayerContentTypeTable {
@@ -129,6 +182,59 @@ mod tests {
         assert!(!layer.types.cache);
     }
 
+    #[test]
+    fn lifecycle_mode_restricts_launch() {
+        let layer = LayerContentMetadata::default()
+            .launch(true)
+            .cache(true)
+            .for_lifecycle_mode(Some(LifecycleMode::CI));
+        assert!(!layer.types.launch);
+        assert!(layer.types.cache);
+
+        let layer = LayerContentMetadata::default()
+            .launch(true)
+            .for_lifecycle_mode(Some(LifecycleMode::Dev));
+        assert!(layer.types.launch);
+
+        let layer = LayerContentMetadata::default()
+            .launch(true)
+            .for_lifecycle_mode(None);
+        assert!(layer.types.launch);
+    }
+
+    #[test]
+    fn layer_types_warnings() {
+        let warning = |launch, build, cache| {
+            LayerContentMetadata::default()
+                .launch(launch)
+                .build(build)
+                .cache(cache)
+                .types
+                .warning()
+        };
+
+        assert_eq!(
+            warning(false, false, false),
+            Some(LayerTypesWarning::Discarded)
+        );
+        assert_eq!(
+            warning(false, false, true),
+            Some(LayerTypesWarning::CacheOnly)
+        );
+        assert_eq!(
+            warning(true, false, false),
+            Some(LayerTypesWarning::LaunchNotCached)
+        );
+        assert_eq!(
+            warning(true, true, false),
+            Some(LayerTypesWarning::LaunchNotCached)
+        );
+        assert_eq!(warning(false, true, false), None);
+        assert_eq!(warning(false, true, true), None);
+        assert_eq!(warning(true, false, true), None);
+        assert_eq!(warning(true, true, true), None);
+    }
+
     #[test]
     fn metadata_is_optional() {
         let layer: Result<LayerContentMetadata<Option<toml::value::Table>>, toml::de::Error> =
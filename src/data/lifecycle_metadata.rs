@@ -0,0 +1,220 @@
+use crate::data::bom;
+use crate::data::buildpack::{BuildpackId, Group};
+use crate::data::launch::{Label, Process, Slice};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use thiserror;
+
+/// Data structure for the lifecycle metadata file (`<layers>/config/metadata.toml`) written by
+/// the lifecycle builder.
+///
+/// It aggregates the `launch.toml` files of all buildpacks in the group.
+///
+/// See [Platform specification](https://github.com/buildpacks/spec/blob/main/platform.md#metadatatoml-toml)
+///
+/// # Examples
+/// ```
+/// use libcnb::data::lifecycle_metadata::LifecycleMetadataToml;
+///
+///         let raw = r#"
+/// [[buildpacks]]
+/// id = "foo/bar"
+/// version = "0.0.1"
+///
+/// [[processes]]
+/// type = "web"
+/// command = "bundle"
+/// args = ["exec", "ruby", "app.rb"]
+/// direct = false
+/// buildpack-id = "foo/bar"
+/// "#;
+///
+///         let result = toml::from_str::<LifecycleMetadataToml>(raw).unwrap();
+///         assert_eq!(result.processes[0].process.r#type.as_str(), "web");
+/// ```
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(try_from = "LifecycleMetadataTomlUnchecked")]
+pub struct LifecycleMetadataToml {
+    pub buildpacks: Vec<Group>,
+    pub processes: Vec<LifecycleProcess>,
+    pub bom: Vec<LifecycleBomEntry>,
+    pub labels: Vec<Label>,
+    pub slices: Vec<Slice>,
+}
+
+/// A [`Process`] together with the buildpack that contributed it.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LifecycleProcess {
+    #[serde(flatten)]
+    pub process: Process,
+    #[serde(rename = "buildpack-id")]
+    pub buildpack_id: BuildpackId,
+}
+
+/// A [`bom::Entry`] together with the buildpack that contributed it.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LifecycleBomEntry {
+    #[serde(flatten)]
+    pub entry: bom::Entry,
+    pub buildpack: Group,
+}
+
+// Used as a "shadow" struct to store
+// potentially invalid `LifecycleMetadataToml` data when deserializing
+// <https://dev.to/equalma/validate-fields-and-types-in-serde-with-tryfrom-c2n>
+#[derive(Deserialize)]
+struct LifecycleMetadataTomlUnchecked {
+    #[serde(default)]
+    buildpacks: Vec<Group>,
+    #[serde(default)]
+    processes: Vec<LifecycleProcess>,
+    #[serde(default)]
+    bom: Vec<LifecycleBomEntry>,
+    #[serde(default)]
+    labels: Vec<Label>,
+    #[serde(default)]
+    slices: Vec<Slice>,
+}
+
+impl TryFrom<LifecycleMetadataTomlUnchecked> for LifecycleMetadataToml {
+    type Error = LifecycleMetadataTomlError;
+
+    fn try_from(value: LifecycleMetadataTomlUnchecked) -> Result<Self, Self::Error> {
+        let LifecycleMetadataTomlUnchecked {
+            buildpacks,
+            processes,
+            bom,
+            labels,
+            slices,
+        } = value;
+
+        let mut seen = HashSet::new();
+        for process in &processes {
+            if !seen.insert(&process.process.r#type) {
+                return Err(LifecycleMetadataTomlError::DuplicateProcessType(
+                    String::from(process.process.r#type.as_str()),
+                ));
+            }
+        }
+
+        for buildpack_id in processes
+            .iter()
+            .map(|process| &process.buildpack_id)
+            .chain(bom.iter().map(|entry| &entry.buildpack.id))
+        {
+            if !buildpacks.iter().any(|group| &group.id == buildpack_id) {
+                return Err(LifecycleMetadataTomlError::UnknownBuildpack(
+                    buildpack_id.to_string(),
+                ));
+            }
+        }
+
+        Ok(LifecycleMetadataToml {
+            buildpacks,
+            processes,
+            bom,
+            labels,
+            slices,
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LifecycleMetadataTomlError {
+    #[error("Process type `{0}` MUST NOT be defined more than once.")]
+    DuplicateProcessType(String),
+
+    #[error("Buildpack `{0}` is referenced but not part of the buildpacks group.")]
+    UnknownBuildpack(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips() {
+        let raw = r#"
+[[buildpacks]]
+id = "foo/bar"
+version = "0.0.1"
+api = "0.6"
+
+[[processes]]
+type = "web"
+command = "bundle"
+args = ["exec", "ruby", "app.rb"]
+direct = false
+buildpack-id = "foo/bar"
+
+[[bom]]
+name = "ruby"
+[bom.metadata]
+version = "3.0.2"
+[bom.buildpack]
+id = "foo/bar"
+version = "0.0.1"
+
+[[labels]]
+key = "foo"
+value = "bar"
+
+[[slices]]
+paths = ["public/*"]
+"#;
+
+        let metadata_toml = toml::from_str::<LifecycleMetadataToml>(raw).unwrap();
+        let serialized = toml::to_string(&metadata_toml).unwrap();
+        let result = toml::from_str::<LifecycleMetadataToml>(&serialized).unwrap();
+
+        assert_eq!(result.buildpacks.len(), 1);
+        assert_eq!(result.processes[0].buildpack_id.as_str(), "foo/bar");
+        assert_eq!(result.processes[0].process.args.len(), 3);
+        assert_eq!(result.bom[0].entry.name, "ruby");
+        assert_eq!(result.bom[0].buildpack.id.as_str(), "foo/bar");
+        assert_eq!(result.labels[0].value, "bar");
+        assert_eq!(result.slices[0].paths, vec!["public/*"]);
+    }
+
+    #[test]
+    fn it_rejects_duplicate_process_types() {
+        let raw = r#"
+[[buildpacks]]
+id = "foo/bar"
+version = "0.0.1"
+
+[[processes]]
+type = "web"
+command = "foo"
+args = []
+direct = true
+buildpack-id = "foo/bar"
+
+[[processes]]
+type = "web"
+command = "bar"
+args = []
+direct = true
+buildpack-id = "foo/bar"
+"#;
+
+        let result = toml::from_str::<LifecycleMetadataToml>(raw);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_rejects_processes_of_unknown_buildpacks() {
+        let raw = r#"
+[[processes]]
+type = "web"
+command = "foo"
+args = []
+direct = true
+buildpack-id = "foo/bar"
+"#;
+
+        let result = toml::from_str::<LifecycleMetadataToml>(raw);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use crate::data::buildpack::Order;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use thiserror;
+
+/// Data structure for the platform order file (order.toml).
+///
+/// See [Platform specification](https://github.com/buildpacks/spec/blob/main/platform.md#ordertoml-toml)
+///
+/// # Examples
+/// ```
+/// use libcnb::data::order::OrderToml;
+///
+///         let raw = r#"
+/// [[order]]
+/// [[order.group]]
+/// id = "foo/bar"
+/// version = "0.0.1"
+///
+/// [[order.group]]
+/// id = "foo/baz"
+/// version = "0.0.2"
+/// optional = true
+/// "#;
+///
+///         let result = toml::from_str::<OrderToml>(raw).unwrap();
+///         assert_eq!(result.order[0].group.len(), 2);
+/// ```
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(try_from = "OrderTomlUnchecked")]
+pub struct OrderToml {
+    pub order: Vec<Order>,
+}
+
+// Used as a "shadow" struct to store
+// potentially invalid `OrderToml` data when deserializing
+// <https://dev.to/equalma/validate-fields-and-types-in-serde-with-tryfrom-c2n>
+#[derive(Deserialize)]
+struct OrderTomlUnchecked {
+    #[serde(default)]
+    order: Vec<Order>,
+}
+
+impl TryFrom<OrderTomlUnchecked> for OrderToml {
+    type Error = OrderTomlError;
+
+    fn try_from(value: OrderTomlUnchecked) -> Result<Self, Self::Error> {
+        let OrderTomlUnchecked { order } = value;
+
+        if order.iter().any(|order| order.group.is_empty()) {
+            Err(OrderTomlError::EmptyGroup)
+        } else {
+            Ok(OrderToml { order })
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum OrderTomlError {
+    #[error("Every order MUST contain at least one group entry.")]
+    EmptyGroup,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips() {
+        let raw = r#"
+[[order]]
+[[order.group]]
+id = "foo/bar"
+version = "0.0.1"
+
+[[order]]
+[[order.group]]
+id = "foo/baz"
+version = "0.0.2"
+optional = true
+"#;
+
+        let order_toml = toml::from_str::<OrderToml>(raw).unwrap();
+        let serialized = toml::to_string(&order_toml).unwrap();
+        let result = toml::from_str::<OrderToml>(&serialized).unwrap();
+
+        assert_eq!(result.order.len(), 2);
+        assert_eq!(result.order[1].group[0].id.as_str(), "foo/baz");
+        assert!(result.order[1].group[0].optional);
+    }
+
+    #[test]
+    fn it_rejects_empty_groups() {
+        let raw = r"
+[[order]]
+group = []
+";
+
+        let result = toml::from_str::<OrderToml>(raw);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_rejects_invalid_buildpack_ids() {
+        let raw = r#"
+[[order]]
+[[order.group]]
+id = "app"
+version = "0.0.1"
+"#;
+
+        let result = toml::from_str::<OrderToml>(raw);
+        assert!(result.is_err());
+    }
+}
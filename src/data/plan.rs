@@ -0,0 +1,192 @@
+use crate::data::build_plan::Require;
+use crate::data::buildpack::{BuildpackId, Group};
+use crate::data::buildpack_plan::{self, BuildpackPlan};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use thiserror;
+
+/// Data structure for the resolved plan file (plan.toml) written by the lifecycle after detection.
+///
+/// See [Platform specification](https://github.com/buildpacks/spec/blob/main/platform.md#plantoml-toml)
+///
+/// # Examples
+/// ```
+/// use libcnb::data::plan::PlanToml;
+/// use std::str::FromStr;
+/// use libcnb::data::buildpack::BuildpackId;
+///
+///         let raw = r#"
+/// [[entries]]
+/// [[entries.providers]]
+/// id = "foo/bar"
+/// version = "0.0.1"
+///
+/// [[entries.requires]]
+/// name = "rust"
+/// "#;
+///
+///         let result = toml::from_str::<PlanToml>(raw).unwrap();
+///         let buildpack_plan = result.buildpack_plan(&BuildpackId::from_str("foo/bar").unwrap());
+///         assert_eq!(buildpack_plan.entries[0].name, "rust");
+/// ```
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct PlanToml {
+    #[serde(default)]
+    pub entries: Vec<PlanEntry>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(try_from = "PlanEntryUnchecked")]
+pub struct PlanEntry {
+    pub providers: Vec<Group>,
+    pub requires: Vec<Require>,
+}
+
+impl PlanToml {
+    /// Builds the buildpack plan the lifecycle passes to the build phase of the given buildpack.
+    ///
+    /// The plan contains all requirements of entries the buildpack is a provider for.
+    pub fn buildpack_plan(&self, buildpack_id: &BuildpackId) -> BuildpackPlan {
+        BuildpackPlan {
+            entries: self
+                .entries
+                .iter()
+                .filter(|entry| {
+                    entry
+                        .providers
+                        .iter()
+                        .any(|provider| &provider.id == buildpack_id)
+                })
+                .flat_map(|entry| &entry.requires)
+                .map(|require| buildpack_plan::Entry {
+                    name: require.name.clone(),
+                    metadata: require.metadata.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+// Used as a "shadow" struct to store
+// potentially invalid `PlanEntry` data when deserializing
+// <https://dev.to/equalma/validate-fields-and-types-in-serde-with-tryfrom-c2n>
+#[derive(Deserialize)]
+struct PlanEntryUnchecked {
+    #[serde(default)]
+    providers: Vec<Group>,
+    #[serde(default)]
+    requires: Vec<Require>,
+}
+
+impl TryFrom<PlanEntryUnchecked> for PlanEntry {
+    type Error = PlanTomlError;
+
+    fn try_from(value: PlanEntryUnchecked) -> Result<Self, Self::Error> {
+        let PlanEntryUnchecked {
+            providers,
+            requires,
+        } = value;
+
+        if providers.is_empty() {
+            Err(PlanTomlError::EntryWithoutProviders)
+        } else if requires.is_empty() {
+            Err(PlanTomlError::EntryWithoutRequires)
+        } else {
+            Ok(PlanEntry {
+                providers,
+                requires,
+            })
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PlanTomlError {
+    #[error("Every plan entry MUST contain at least one provider.")]
+    EntryWithoutProviders,
+
+    #[error("Every plan entry MUST contain at least one requirement.")]
+    EntryWithoutRequires,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn it_round_trips() {
+        let raw = r#"
+[[entries]]
+[[entries.providers]]
+id = "foo/bar"
+version = "0.0.1"
+
+[[entries.requires]]
+name = "rust"
+[entries.requires.metadata]
+version = "1.56"
+
+[[entries]]
+[[entries.providers]]
+id = "foo/baz"
+version = "0.0.2"
+
+[[entries.requires]]
+name = "cargo"
+"#;
+
+        let plan_toml = toml::from_str::<PlanToml>(raw).unwrap();
+        let serialized = toml::to_string(&plan_toml).unwrap();
+        let result = toml::from_str::<PlanToml>(&serialized).unwrap();
+
+        assert_eq!(result.entries.len(), 2);
+        assert_eq!(
+            result.entries[0].requires[0]
+                .metadata
+                .get("version")
+                .unwrap()
+                .as_str(),
+            Some("1.56")
+        );
+    }
+
+    #[test]
+    fn it_builds_buildpack_plans() {
+        let raw = r#"
+[[entries]]
+[[entries.providers]]
+id = "foo/bar"
+version = "0.0.1"
+
+[[entries.requires]]
+name = "rust"
+
+[[entries]]
+[[entries.providers]]
+id = "foo/baz"
+version = "0.0.2"
+
+[[entries.requires]]
+name = "cargo"
+"#;
+
+        let plan_toml = toml::from_str::<PlanToml>(raw).unwrap();
+        let buildpack_plan = plan_toml.buildpack_plan(&BuildpackId::from_str("foo/baz").unwrap());
+
+        assert_eq!(buildpack_plan.entries.len(), 1);
+        assert_eq!(buildpack_plan.entries[0].name, "cargo");
+    }
+
+    #[test]
+    fn it_rejects_entries_without_providers() {
+        let raw = r#"
+[[entries]]
+[[entries.requires]]
+name = "rust"
+"#;
+
+        let result = toml::from_str::<PlanToml>(raw);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,375 @@
+use crate::data::buildpack::BuildpackId;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use thiserror;
+use toml::value::Table;
+
+/// Data structure for the Project descriptor (project.toml).
+///
+/// Both the legacy schema (0.1, using the `[project]` and `[build]` tables) and schema version 0.2
+/// (using the `[_]` and `[io.buildpacks]` tables) are supported. Regardless of the schema version
+/// in the file, the data is normalized into this struct.
+///
+/// See [Project Descriptor specification](https://github.com/buildpacks/spec/blob/main/extensions/project-descriptor.md)
+///
+/// # Examples
+/// ```
+/// use libcnb::data::project::{ProjectToml, ProjectSchemaVersion};
+///
+///         let raw = r#"
+/// [_]
+/// schema-version = "0.2"
+/// id = "com.example.app"
+///
+/// [io.buildpacks]
+/// exclude = ["/README.md"]
+///
+/// [[io.buildpacks.group]]
+/// id = "foo/bar"
+/// version = "1.0.0"
+///
+/// [[io.buildpacks.build.env]]
+/// name = "JAVA_OPTS"
+/// value = "-Xmx1g"
+/// "#;
+///
+///         let result = toml::from_str::<ProjectToml>(raw).unwrap();
+///         assert_eq!(result.schema_version, ProjectSchemaVersion::V0_2);
+///         assert_eq!(result.project.id.as_deref(), Some("com.example.app"));
+///         assert_eq!(result.build.env[0].name, "JAVA_OPTS");
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "ProjectTomlUnchecked")]
+pub struct ProjectToml {
+    pub schema_version: ProjectSchemaVersion,
+    pub project: Project,
+    pub build: Build,
+    pub metadata: Table,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ProjectSchemaVersion {
+    V0_1,
+    V0_2,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Project {
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub documentation_url: Option<String>,
+    pub source_url: Option<String>,
+    #[serde(default)]
+    pub licenses: Vec<License>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct License {
+    pub r#type: Option<String>,
+    pub uri: Option<String>,
+}
+
+/// Build configuration of a project descriptor.
+///
+/// `include` and `exclude` are mutually exclusive, at most one of them will be non-empty.
+#[derive(Debug, Default)]
+pub struct Build {
+    pub builder: Option<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub buildpacks: Vec<BuildpackReference>,
+    pub env: Vec<EnvVar>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BuildpackReference {
+    pub id: Option<BuildpackId>,
+    pub version: Option<String>,
+    pub uri: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EnvVar {
+    pub name: String,
+    pub value: String,
+}
+
+// Used as a "shadow" struct to store
+// potentially invalid `ProjectToml` data when deserializing
+// <https://dev.to/equalma/validate-fields-and-types-in-serde-with-tryfrom-c2n>
+#[derive(Deserialize)]
+struct ProjectTomlUnchecked {
+    // Schema 0.2
+    #[serde(rename = "_")]
+    underscore: Option<ProjectTableV0_2>,
+    io: Option<IoTable>,
+
+    // Schema 0.1
+    project: Option<Project>,
+    build: Option<BuildTableV0_1>,
+    metadata: Option<Table>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ProjectTableV0_2 {
+    schema_version: String,
+    #[serde(flatten)]
+    project: Project,
+    metadata: Option<Table>,
+}
+
+#[derive(Deserialize)]
+struct IoTable {
+    buildpacks: Option<IoBuildpacksTable>,
+}
+
+#[derive(Deserialize)]
+struct IoBuildpacksTable {
+    builder: Option<String>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    group: Vec<BuildpackReference>,
+    build: Option<IoBuildpacksBuildTable>,
+}
+
+#[derive(Deserialize)]
+struct IoBuildpacksBuildTable {
+    #[serde(default)]
+    env: Vec<EnvVar>,
+}
+
+#[derive(Deserialize)]
+struct BuildTableV0_1 {
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    buildpacks: Vec<BuildpackReference>,
+    #[serde(default)]
+    env: Vec<EnvVar>,
+}
+
+impl TryFrom<ProjectTomlUnchecked> for ProjectToml {
+    type Error = ProjectTomlError;
+
+    fn try_from(value: ProjectTomlUnchecked) -> Result<Self, Self::Error> {
+        let project_toml = match value.underscore {
+            Some(underscore) => {
+                if underscore.schema_version != "0.2" {
+                    return Err(ProjectTomlError::UnsupportedSchemaVersion(
+                        underscore.schema_version,
+                    ));
+                }
+
+                let build = value
+                    .io
+                    .and_then(|io| io.buildpacks)
+                    .map(|io_buildpacks| Build {
+                        builder: io_buildpacks.builder,
+                        include: io_buildpacks.include,
+                        exclude: io_buildpacks.exclude,
+                        buildpacks: io_buildpacks.group,
+                        env: io_buildpacks
+                            .build
+                            .map(|build| build.env)
+                            .unwrap_or_default(),
+                    })
+                    .unwrap_or_default();
+
+                ProjectToml {
+                    schema_version: ProjectSchemaVersion::V0_2,
+                    project: underscore.project,
+                    build,
+                    metadata: underscore.metadata.unwrap_or_default(),
+                }
+            }
+            None => {
+                let build = value
+                    .build
+                    .map(|build| Build {
+                        builder: None,
+                        include: build.include,
+                        exclude: build.exclude,
+                        buildpacks: build.buildpacks,
+                        env: build.env,
+                    })
+                    .unwrap_or_default();
+
+                ProjectToml {
+                    schema_version: ProjectSchemaVersion::V0_1,
+                    project: value.project.unwrap_or_default(),
+                    build,
+                    metadata: value.metadata.unwrap_or_default(),
+                }
+            }
+        };
+
+        if !project_toml.build.include.is_empty() && !project_toml.build.exclude.is_empty() {
+            return Err(ProjectTomlError::IncludeAndExclude);
+        }
+
+        if project_toml
+            .build
+            .buildpacks
+            .iter()
+            .any(|buildpack| buildpack.id.is_none() && buildpack.uri.is_none())
+        {
+            return Err(ProjectTomlError::InvalidBuildpackReference);
+        }
+
+        Ok(project_toml)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProjectTomlError {
+    #[error("Found schema version `{0}` but only `0.1` and `0.2` are supported.")]
+    UnsupportedSchemaVersion(String),
+
+    #[error("Build `include` and `exclude` are mutually exclusive.")]
+    IncludeAndExclude,
+
+    #[error("Buildpack reference MUST contain an `id` or an `uri`.")]
+    InvalidBuildpackReference,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_deserialize_schema_0_1() {
+        let raw = r#"
+[project]
+id = "com.example.app"
+name = "Example App"
+version = "1.0.0"
+authors = ["Jane Doe"]
+source-url = "https://example.com/app"
+
+[[project.licenses]]
+type = "MIT"
+
+[build]
+include = ["/src"]
+
+[[build.buildpacks]]
+id = "foo/bar"
+version = "0.0.1"
+
+[[build.buildpacks]]
+uri = "https://example.com/buildpack.tgz"
+
+[[build.env]]
+name = "FOO"
+value = "BAR"
+
+[metadata]
+team = "ruby"
+"#;
+
+        let result = toml::from_str::<ProjectToml>(raw).unwrap();
+        assert_eq!(result.schema_version, ProjectSchemaVersion::V0_1);
+        assert_eq!(result.project.name.as_deref(), Some("Example App"));
+        assert_eq!(
+            result.project.source_url.as_deref(),
+            Some("https://example.com/app")
+        );
+        assert_eq!(result.project.licenses[0].r#type.as_deref(), Some("MIT"));
+        assert_eq!(result.build.include, vec!["/src"]);
+        assert_eq!(
+            result.build.buildpacks[0].id.as_ref().unwrap().as_str(),
+            "foo/bar"
+        );
+        assert_eq!(result.build.env[0].value, "BAR");
+        assert_eq!(result.metadata.get("team").unwrap().as_str(), Some("ruby"));
+    }
+
+    #[test]
+    fn can_deserialize_schema_0_2() {
+        let raw = r#"
+[_]
+schema-version = "0.2"
+id = "com.example.app"
+authors = ["Jane Doe"]
+
+[_.metadata]
+team = "ruby"
+
+[io.buildpacks]
+builder = "heroku/buildpacks:20"
+exclude = ["/README.md"]
+
+[[io.buildpacks.group]]
+id = "foo/bar"
+
+[[io.buildpacks.build.env]]
+name = "FOO"
+value = "BAR"
+"#;
+
+        let result = toml::from_str::<ProjectToml>(raw).unwrap();
+        assert_eq!(result.schema_version, ProjectSchemaVersion::V0_2);
+        assert_eq!(result.project.id.as_deref(), Some("com.example.app"));
+        assert_eq!(result.project.authors, vec!["Jane Doe"]);
+        assert_eq!(
+            result.build.builder.as_deref(),
+            Some("heroku/buildpacks:20")
+        );
+        assert_eq!(result.build.exclude, vec!["/README.md"]);
+        assert_eq!(result.build.buildpacks.len(), 1);
+        assert_eq!(result.build.env[0].name, "FOO");
+        assert_eq!(result.metadata.get("team").unwrap().as_str(), Some("ruby"));
+    }
+
+    #[test]
+    fn can_deserialize_empty() {
+        let result = toml::from_str::<ProjectToml>("").unwrap();
+        assert_eq!(result.schema_version, ProjectSchemaVersion::V0_1);
+        assert!(result.build.buildpacks.is_empty());
+        assert!(result.metadata.is_empty());
+    }
+
+    #[test]
+    fn cannot_use_include_and_exclude() {
+        let raw = r#"
+[build]
+include = ["/src"]
+exclude = ["/README.md"]
+"#;
+
+        let result = toml::from_str::<ProjectToml>(raw);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn cannot_use_unknown_schema_version() {
+        let raw = r#"
+[_]
+schema-version = "0.3"
+"#;
+
+        let result = toml::from_str::<ProjectToml>(raw);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn cannot_use_buildpack_reference_without_id_or_uri() {
+        let raw = r#"
+[[build.buildpacks]]
+version = "0.0.1"
+"#;
+
+        let result = toml::from_str::<ProjectToml>(raw);
+        assert!(result.is_err());
+    }
+}
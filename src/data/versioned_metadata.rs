@@ -0,0 +1,376 @@
+use std::ops::{Deref, DerefMut};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The key of the version marker in the `metadata` table of `<layer>.toml`.
+pub const METADATA_VERSION_KEY: &str = "libcnb_metadata_version";
+
+/// Layer metadata that is part of a chain of metadata versions.
+///
+/// Each version declares its predecessor and how to migrate from it. The first version in the
+/// chain uses [`NoPreviousVersion`] as its predecessor. Wrap the latest version in [`Versioned`]
+/// to use it as layer metadata.
+///
+/// # Examples
+/// ```
+/// use libcnb::data::layer_content_metadata::LayerContentMetadata;
+/// use libcnb::data::versioned_metadata::{NoPreviousVersion, Versioned, VersionedMetadata};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Deserialize, Serialize)]
+/// struct RubyLayerMetadataV1 {
+///     version: String,
+/// }
+///
+/// impl VersionedMetadata for RubyLayerMetadataV1 {
+///     const VERSION: u32 = 1;
+///     type Previous = NoPreviousVersion;
+///
+///     fn migrate_from_previous(previous: NoPreviousVersion) -> Option<Self> {
+///         match previous {}
+///     }
+/// }
+///
+/// #[derive(Deserialize, Serialize)]
+/// struct RubyLayerMetadataV2 {
+///     version: String,
+///     stack_id: String,
+/// }
+///
+/// impl From<RubyLayerMetadataV1> for RubyLayerMetadataV2 {
+///     fn from(previous: RubyLayerMetadataV1) -> Self {
+///         RubyLayerMetadataV2 {
+///             version: previous.version,
+///             stack_id: String::from("heroku-20"),
+///         }
+///     }
+/// }
+///
+/// impl VersionedMetadata for RubyLayerMetadataV2 {
+///     const VERSION: u32 = 2;
+///     type Previous = RubyLayerMetadataV1;
+///
+///     fn migrate_from_previous(previous: RubyLayerMetadataV1) -> Option<Self> {
+///         Some(previous.into())
+///     }
+/// }
+///
+/// let layer_toml = r#"
+/// [metadata]
+/// libcnb_metadata_version = 1
+/// version = "3.0.2"
+/// "#;
+///
+/// let layer_content_metadata: LayerContentMetadata<Versioned<RubyLayerMetadataV2>> =
+///     toml::from_str(layer_toml).unwrap();
+///
+/// assert_eq!(layer_content_metadata.metadata.version, "3.0.2");
+/// assert_eq!(layer_content_metadata.metadata.stack_id, "heroku-20");
+/// ```
+pub trait VersionedMetadata: Serialize + DeserializeOwned {
+    /// The version marker of this metadata version, unique within the chain.
+    const VERSION: u32;
+
+    /// The previous metadata version, [`NoPreviousVersion`] for the first version.
+    type Previous: VersionedMetadata;
+
+    /// Migrates metadata of the previous version to this version
+    ///
+    /// Implementations usually delegate to a `From` or `TryFrom` implementation. Returning `None`
+    /// signals that the metadata cannot be migrated.
+    fn migrate_from_previous(previous: Self::Previous) -> Option<Self>;
+
+    /// Reads metadata of the given version, migrating it through the chain of previous versions if
+    /// necessary.
+    ///
+    /// Metadata without a version marker (`None`) was written before versioning was introduced.
+    /// It is read as the newest version of the chain it can be deserialized into, so no fields
+    /// are lost when it already has the shape of a later version.
+    ///
+    /// There is usually no need to implement this function.
+    fn migrate(version: Option<u32>, metadata: toml::Value) -> Option<Self> {
+        match version {
+            Some(version) if version == Self::VERSION => metadata.try_into().ok(),
+            Some(_) => {
+                Self::Previous::migrate(version, metadata).and_then(Self::migrate_from_previous)
+            }
+            None => metadata.clone().try_into().ok().or_else(|| {
+                Self::Previous::migrate(None, metadata).and_then(Self::migrate_from_previous)
+            }),
+        }
+    }
+}
+
+/// Marks the first version in a chain of [`VersionedMetadata`].
+#[derive(Deserialize, Serialize, Debug)]
+pub enum NoPreviousVersion {}
+
+impl VersionedMetadata for NoPreviousVersion {
+    const VERSION: u32 = 0;
+    type Previous = NoPreviousVersion;
+
+    fn migrate_from_previous(previous: NoPreviousVersion) -> Option<Self> {
+        match previous {}
+    }
+
+    fn migrate(_version: Option<u32>, _metadata: toml::Value) -> Option<Self> {
+        None
+    }
+}
+
+/// Layer metadata stored along with its version marker.
+///
+/// Serializes the wrapped metadata with an additional [`METADATA_VERSION_KEY`] key. When
+/// deserializing, metadata of previous versions is migrated to `M`. Deserialization fails when
+/// there is no migration path, which results in
+/// [`recover_from_invalid_metadata`](crate::layer::Layer::recover_from_invalid_metadata) being
+/// called when executing a layer.
+///
+/// See [`VersionedMetadata`] for an example.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Versioned<M>(pub M);
+
+impl<M> Versioned<M> {
+    pub fn into_inner(self) -> M {
+        self.0
+    }
+}
+
+impl<M> Deref for Versioned<M> {
+    type Target = M;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<M> DerefMut for Versioned<M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<M> From<M> for Versioned<M> {
+    fn from(metadata: M) -> Self {
+        Versioned(metadata)
+    }
+}
+
+impl<M: VersionedMetadata> Serialize for Versioned<M> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut table = match toml::Value::try_from(&self.0) {
+            Ok(toml::Value::Table(table)) => table,
+            Ok(_) => {
+                return Err(serde::ser::Error::custom(
+                    "Versioned metadata must serialize to a table",
+                ))
+            }
+            Err(error) => return Err(serde::ser::Error::custom(error)),
+        };
+
+        table.insert(
+            String::from(METADATA_VERSION_KEY),
+            toml::Value::Integer(i64::from(M::VERSION)),
+        );
+
+        table.serialize(serializer)
+    }
+}
+
+impl<'de, M: VersionedMetadata> Deserialize<'de> for Versioned<M> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut table = toml::value::Table::deserialize(deserializer)?;
+
+        let version = match table.remove(METADATA_VERSION_KEY) {
+            None => None,
+            Some(toml::Value::Integer(version)) => Some(u32::try_from(version).map_err(|_| {
+                serde::de::Error::custom(format!("Invalid metadata version {}", version))
+            })?),
+            Some(value) => {
+                return Err(serde::de::Error::custom(format!(
+                    "Invalid metadata version {}",
+                    value
+                )))
+            }
+        };
+
+        M::migrate(version, toml::Value::Table(table))
+            .map(Versioned)
+            .ok_or_else(|| {
+                serde::de::Error::custom(match version {
+                    Some(version) => format!(
+                        "No migration path from metadata version {} to version {}",
+                        version,
+                        M::VERSION
+                    ),
+                    None => format!(
+                        "Unversioned metadata cannot be migrated to version {}",
+                        M::VERSION
+                    ),
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::layer_content_metadata::LayerContentMetadata;
+    use std::convert::TryFrom;
+
+    #[derive(Deserialize, Serialize, Debug)]
+    struct V1 {
+        version: String,
+    }
+
+    impl VersionedMetadata for V1 {
+        const VERSION: u32 = 1;
+        type Previous = NoPreviousVersion;
+
+        fn migrate_from_previous(previous: NoPreviousVersion) -> Option<Self> {
+            match previous {}
+        }
+    }
+
+    #[derive(Deserialize, Serialize, Debug)]
+    struct V2 {
+        version: String,
+        patched: bool,
+    }
+
+    impl From<V1> for V2 {
+        fn from(previous: V1) -> Self {
+            V2 {
+                version: previous.version,
+                patched: false,
+            }
+        }
+    }
+
+    impl VersionedMetadata for V2 {
+        const VERSION: u32 = 2;
+        type Previous = V1;
+
+        fn migrate_from_previous(previous: V1) -> Option<Self> {
+            Some(previous.into())
+        }
+    }
+
+    #[derive(Deserialize, Serialize, Debug, Eq, PartialEq)]
+    struct V3 {
+        major: u64,
+        patched: bool,
+    }
+
+    impl TryFrom<V2> for V3 {
+        type Error = std::num::ParseIntError;
+
+        fn try_from(previous: V2) -> Result<Self, Self::Error> {
+            Ok(V3 {
+                major: previous
+                    .version
+                    .split('.')
+                    .next()
+                    .unwrap_or_default()
+                    .parse()?,
+                patched: previous.patched,
+            })
+        }
+    }
+
+    impl VersionedMetadata for V3 {
+        const VERSION: u32 = 3;
+        type Previous = V2;
+
+        fn migrate_from_previous(previous: V2) -> Option<Self> {
+            V3::try_from(previous).ok()
+        }
+    }
+
+    fn read(raw: &str) -> Result<Versioned<V3>, toml::de::Error> {
+        toml::from_str::<LayerContentMetadata<Versioned<V3>>>(raw).map(|layer| layer.metadata)
+    }
+
+    #[test]
+    fn it_round_trips_with_version_marker() {
+        let layer_content_metadata = LayerContentMetadata::default().metadata(Versioned(V3 {
+            major: 3,
+            patched: true,
+        }));
+
+        let serialized = toml::to_string(&layer_content_metadata).unwrap();
+        assert!(serialized.contains("libcnb_metadata_version = 3"));
+
+        assert_eq!(
+            read(&serialized).unwrap(),
+            Versioned(V3 {
+                major: 3,
+                patched: true
+            })
+        );
+    }
+
+    #[test]
+    fn it_migrates_through_the_chain() {
+        let metadata = read(
+            r#"
+[metadata]
+libcnb_metadata_version = 1
+version = "3.0.2"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(metadata.major, 3);
+        assert!(!metadata.patched);
+    }
+
+    #[test]
+    fn it_reads_unversioned_metadata_as_newest_matching_version() {
+        let metadata = read(
+            r#"
+[metadata]
+version = "2.7.4"
+patched = true
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(metadata.major, 2);
+        assert!(metadata.patched);
+
+        let metadata = read(
+            r#"
+[metadata]
+version = "2.7.4"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(metadata.major, 2);
+        assert!(!metadata.patched);
+    }
+
+    #[test]
+    fn it_fails_without_migration_path() {
+        assert!(read(
+            r#"
+[metadata]
+libcnb_metadata_version = 1
+version = "latest"
+"#,
+        )
+        .is_err());
+
+        assert!(read(
+            r"
+[metadata]
+libcnb_metadata_version = 4
+major = 4
+patched = false
+",
+        )
+        .is_err());
+    }
+}
@@ -1,15 +1,21 @@
 use std::fmt::Debug;
 use std::path::PathBuf;
 
-use crate::{data::build_plan::BuildPlan, data::buildpack::BuildpackToml, platform::Platform};
+use crate::{
+    data::build_plan::BuildPlan, data::buildpack::BuildpackToml, data::project::ProjectToml,
+    mode::LifecycleMode, platform::Platform, target::Target,
+};
 
 /// Context for a buildpack's detect phase execution.
 pub struct DetectContext<P: Platform, BM> {
     pub app_dir: PathBuf,
     pub buildpack_dir: PathBuf,
     pub stack_id: String,
+    pub target: Target,
     pub platform: P,
+    pub lifecycle_mode: Option<LifecycleMode>,
     pub buildpack_descriptor: BuildpackToml<BM>,
+    pub project_descriptor: Option<ProjectToml>,
 }
 
 /// Describes the outcome of the buildpack's detect phase.
@@ -1,5 +1,7 @@
 use crate::data::launch::ProcessTypeError;
+use crate::layer_graph::LayerGraphError;
 use crate::layer_lifecycle::LayerLifecycleError;
+use crate::stack::StackCompatibilityError;
 use crate::toml_file::TomlFileError;
 use std::fmt::{Debug, Display};
 
@@ -22,14 +24,20 @@ pub enum Error<E: Debug + Display> {
     #[error("Process type error: {0}")]
     ProcessTypeError(#[from] ProcessTypeError),
 
+    #[error("Stack compatibility error: {0}")]
+    StackCompatibilityError(#[from] StackCompatibilityError),
+
+    #[error("Layer graph error: {0}")]
+    LayerGraphError(#[from] LayerGraphError<E>),
+
     #[error("Could not determine app directory: {0}")]
     CannotDetermineAppDirectory(std::io::Error),
 
     #[error("Could not determine buildpack directory: {0}")]
     CannotDetermineBuildpackDirectory(std::env::VarError),
 
-    #[error("Could not determine stack id: {0}")]
-    CannotDetermineStackId(std::env::VarError),
+    #[error("Could not determine stack id or target: neither CNB_STACK_ID nor CNB_TARGET_OS and CNB_TARGET_ARCH are set")]
+    CannotDetermineTarget,
 
     #[error("Cannot create platform from platform path: {0}")]
     CannotCreatePlatformFromPath(std::io::Error),
@@ -40,12 +48,18 @@ pub enum Error<E: Debug + Display> {
     #[error("Cannot read buildpack descriptor (buildpack.toml): {0}")]
     CannotReadBuildpackDescriptor(TomlFileError),
 
+    #[error("Cannot read project descriptor (project.toml): {0}")]
+    CannotReadProjectDescriptor(TomlFileError),
+
     #[error("Cannot write build plan: {0}")]
     CannotWriteBuildPlan(TomlFileError),
 
     #[error("Cannot write test results: {0}")]
     CannotWriteTestResults(TomlFileError),
 
+    #[error("Cannot prune stale layers: {0}")]
+    CannotPruneStaleLayers(std::io::Error),
+
     #[error("Buildpack error: {0}")]
     BuildpackError(E),
 }
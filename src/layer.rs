@@ -0,0 +1,1489 @@
+//! Layer trait and its execution
+//!
+//! [`Layer`] is the successor of [`LayerLifecycle`](crate::layer_lifecycle::LayerLifecycle).
+//! Instead of five generic parameters, it declares the layer metadata, output and error types as
+//! associated types. The trait is only generic over the platform and buildpack metadata of the
+//! [`BuildContext`], which implementations that do not need them can leave generic:
+//!
+//! ```
+//! use std::path::Path;
+//! use libcnb::{BuildContext, Platform};
+//! use libcnb::data::layer_content_metadata::LayerContentMetadata;
+//! use libcnb::layer::Layer;
+//! use serde::{Deserialize, Serialize};
+//!
+//! struct HelloLayer;
+//!
+//! #[derive(Deserialize, Serialize)]
+//! struct HelloLayerMetadata {
+//!     greeting: String,
+//! }
+//!
+//! impl<P: Platform, BM> Layer<P, BM> for HelloLayer {
+//!     type Metadata = HelloLayerMetadata;
+//!     type Output = ();
+//!     type Error = std::io::Error;
+//!
+//!     fn create(
+//!         &self,
+//!         layer_path: &Path,
+//!         build_context: &BuildContext<P, BM>,
+//!     ) -> Result<LayerContentMetadata<Self::Metadata>, Self::Error> {
+//!         std::fs::write(layer_path.join("hello.txt"), "Hello World!")?;
+//!
+//!         Ok(LayerContentMetadata::default()
+//!             .launch(true)
+//!             .metadata(HelloLayerMetadata {
+//!                 greeting: String::from("Hello World!"),
+//!             }))
+//!     }
+//!
+//!     fn output(
+//!         &self,
+//!         _layer_path: &Path,
+//!         _layer_content_metadata: LayerContentMetadata<Self::Metadata>,
+//!     ) -> Result<Self::Output, Self::Error> {
+//!         Ok(())
+//!     }
+//! }
+//! ```
+//!
+//! Use [`execute_layer`] to run a [`Layer`]. The state machine is the same as the one described in
+//! [`crate::layer_lifecycle`].
+//!
+//! The `on_*` hooks of [`Layer`] only signal that a step happened. For logging and telemetry that
+//! needs the layer name, metadata, validation result or timing, use [`execute_layer_with_observer`]
+//! with a [`LayerObserver`].
+
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::build::{warn_about_layer_types, BuildContext};
+use crate::data::layer_content_metadata::LayerContentMetadata;
+use crate::error::Error;
+use crate::layer_events::{LayerEventEmitter, LayerEventKind, LayerObserver};
+use crate::layer_lifecycle::LayerLifecycleError;
+use crate::layer_normalization::LayerNormalization;
+use crate::mode::LifecycleMode;
+use crate::platform::Platform;
+use crate::toml_file::write_toml_file;
+
+/// A Cloud Native Buildpack layer
+///
+/// Use [`execute_layer`] to execute a layer.
+pub trait Layer<P: Platform, BM> {
+    /// The type of the `metadata` key of the layer content metadata (`<layer>.toml`).
+    type Metadata: Serialize + DeserializeOwned;
+
+    /// The type returned by [`execute_layer`] for this layer.
+    type Output;
+
+    /// The error type of this layer.
+    type Error: Debug + Display;
+
+    /// Creates the layer from scratch
+    ///
+    /// When used with [`execute_layer`], `path` will be created and empty. The returned
+    /// [`LayerContentMetadata`] will be automatically written to disk. Implementations only need to
+    /// care about putting files into `path`.
+    ///
+    /// `path` is the actual location of the layer. An existing layer is kept as a backup until this
+    /// function returned successfully and is restored if it fails.
+    fn create(
+        &self,
+        layer_path: &Path,
+        build_context: &BuildContext<P, BM>,
+    ) -> Result<LayerContentMetadata<Self::Metadata>, Self::Error>;
+
+    /// Derives the output of [`execute_layer`] from the final state of the layer.
+    fn output(
+        &self,
+        layer_path: &Path,
+        layer_content_metadata: LayerContentMetadata<Self::Metadata>,
+    ) -> Result<Self::Output, Self::Error>;
+
+    /// Tries to recover from invalid layer metadata
+    ///
+    /// When the metadata of the existing layer cannot be deserialized into `Self::Metadata`, this
+    /// function will be called by [`execute_layer`] with the actual layer metadata as TOML.
+    ///
+    /// The default implementation returns [`MetadataRecoveryStrategy::DeleteLayer`] to signal that
+    /// the existing layer should be deleted in its entirety.
+    fn recover_from_invalid_metadata(
+        &self,
+        #[allow(unused_variables)] layer_metadata: &toml::value::Table,
+        #[allow(unused_variables)] build_context: &BuildContext<P, BM>,
+    ) -> Result<MetadataRecoveryStrategy<Self::Metadata>, Self::Error> {
+        Ok(MetadataRecoveryStrategy::DeleteLayer)
+    }
+
+    /// Based on the current state of the layer, determines how the layer will be processed
+    ///
+    /// The default implementation always returns [`ValidateResult::RecreateLayer`].
+    fn validate(
+        &self,
+        #[allow(unused_variables)] layer_path: &Path,
+        #[allow(unused_variables)] layer_content_metadata: &LayerContentMetadata<Self::Metadata>,
+        #[allow(unused_variables)] build_context: &BuildContext<P, BM>,
+    ) -> ValidateResult {
+        ValidateResult::RecreateLayer
+    }
+
+    /// Updates an existing layer
+    ///
+    /// `path` holds the contents of the existing layer. A backup of the existing layer is kept
+    /// until this function returned successfully and is restored if it fails. The backup shares
+    /// the files of the layer via hard links, so files have to be replaced rather than modified
+    /// in place for the backup to stay intact.
+    ///
+    /// The default implementation is a no-op.
+    fn update(
+        &self,
+        #[allow(unused_variables)] layer_path: &Path,
+        layer_content_metadata: LayerContentMetadata<Self::Metadata>,
+        #[allow(unused_variables)] build_context: &BuildContext<P, BM>,
+    ) -> Result<LayerContentMetadata<Self::Metadata>, Self::Error> {
+        Ok(layer_content_metadata)
+    }
+
+    /// Normalizes the layer contents after [`create`](Layer::create) and
+    /// [`update`](Layer::update) to make the layer reproducible
+    ///
+    /// The default implementation returns `None`, which leaves the layer contents untouched. See
+    /// [`layer_normalization`](crate::layer_normalization) for details.
+    fn normalization(&self) -> Option<LayerNormalization> {
+        None
+    }
+
+    /// Determines if the layer is part of the build in the given lifecycle mode
+    ///
+    /// When this returns `false`, [`execute_layer`] deletes the layer if it exists and returns
+    /// [`excluded_output`](Layer::excluded_output) without calling any other function. Use this
+    /// for layers that only make sense in some modes, like dev-dependencies that are only needed
+    /// in [`LifecycleMode::Dev`] and [`LifecycleMode::Test`].
+    ///
+    /// The default implementation includes the layer in all modes.
+    fn include_in_mode(
+        &self,
+        #[allow(unused_variables)] lifecycle_mode: Option<LifecycleMode>,
+    ) -> bool {
+        true
+    }
+
+    /// The output of [`execute_layer`] for a layer excluded via
+    /// [`include_in_mode`](Layer::include_in_mode)
+    ///
+    /// The default implementation returns `None` and [`execute_layer`] fails with
+    /// [`LayerLifecycleError::MissingExcludedLayerOutput`]. Layers that can be excluded have to
+    /// implement this as well.
+    fn excluded_output(&self) -> Option<Self::Output> {
+        None
+    }
+
+    fn on_lifecycle_start(&self) {}
+    fn on_keep(&self) {}
+    fn on_update(&self) {}
+    fn on_create(&self) {}
+    fn on_lifecycle_end(&self) {}
+}
+
+/// The result of the recovery process for invalid layer metadata
+///
+/// See [`Layer::recover_from_invalid_metadata`]
+pub enum MetadataRecoveryStrategy<M> {
+    /// Delete the layer entirely
+    DeleteLayer,
+    /// Replace the metadata
+    ReplaceMetadata(M),
+}
+
+/// The result of a layer validation
+///
+/// See [`Layer::validate`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ValidateResult {
+    /// Keep the layer just as it is
+    ///
+    /// No [`Layer`] functions will be called for this layer
+    KeepLayer,
+
+    /// Delete the layer and create a new one
+    ///
+    /// Only [`create`](Layer::create) will be called
+    RecreateLayer,
+
+    /// Update the existing layer
+    ///
+    /// Only [`update`](Layer::update) will be called
+    UpdateLayer,
+}
+
+/// Executes a [`Layer`] for a given layer name and [`BuildContext`]
+pub fn execute_layer<P: Platform, BM, L: Layer<P, BM>>(
+    layer_name: impl AsRef<str>,
+    layer: L,
+    context: &BuildContext<P, BM>,
+) -> Result<L::Output, Error<L::Error>> {
+    execute_layer_with_observer(layer_name, layer, context, &())
+}
+
+/// Executes a [`Layer`] for a given layer name and [`BuildContext`], reporting each step to the
+/// given [`LayerObserver`]
+///
+/// See [`crate::layer_events`] for the emitted events.
+pub fn execute_layer_with_observer<P: Platform, BM, L: Layer<P, BM>, O: LayerObserver>(
+    layer_name: impl AsRef<str>,
+    layer: L,
+    context: &BuildContext<P, BM>,
+    observer: &O,
+) -> Result<L::Output, Error<L::Error>> {
+    if !layer.include_in_mode(context.lifecycle_mode) {
+        return execute_excluded_layer(layer_name, &layer, context);
+    }
+
+    let layer_path = context.layer_path(&layer_name);
+    let emitter = LayerEventEmitter::new(observer, layer_name.as_ref(), &layer_path);
+    context.layer_usage.touch(&layer_name);
+
+    layer.on_lifecycle_start();
+    emitter.emit::<L::Metadata>(LayerEventKind::Start);
+
+    match run_layer(layer_name.as_ref(), &layer_path, &layer, context, &emitter) {
+        Ok((validate_result, layer_content_metadata)) => {
+            emitter.emit(LayerEventKind::End {
+                validate_result,
+                metadata: &layer_content_metadata,
+            });
+
+            layer
+                .output(&layer_path, layer_content_metadata)
+                .map_err(Error::BuildpackError)
+        }
+        Err(error) => {
+            emitter.emit::<L::Metadata>(LayerEventKind::Failed { error: &error });
+            Err(error)
+        }
+    }
+}
+
+/// Deletes a layer excluded in the lifecycle mode of the build, no events are emitted for it.
+fn execute_excluded_layer<P: Platform, BM, L: Layer<P, BM>>(
+    layer_name: impl AsRef<str>,
+    layer: &L,
+    context: &BuildContext<P, BM>,
+) -> Result<L::Output, Error<L::Error>> {
+    layer.on_lifecycle_start();
+
+    context
+        .delete_layer(&layer_name)
+        .map_err(LayerLifecycleError::CannotDeleteLayer)?;
+
+    layer.on_lifecycle_end();
+
+    layer.excluded_output().ok_or_else(|| {
+        LayerLifecycleError::MissingExcludedLayerOutput(String::from(layer_name.as_ref())).into()
+    })
+}
+
+type LayerRunResult<M> = (Option<ValidateResult>, LayerContentMetadata<M>);
+
+/// Runs the layer state machine, returns the result of the validation (if there was an existing
+/// layer) and the final layer content metadata.
+fn run_layer<P: Platform, BM, L: Layer<P, BM>, O: LayerObserver>(
+    layer_name: &str,
+    layer_path: &Path,
+    layer: &L,
+    context: &BuildContext<P, BM>,
+    emitter: &LayerEventEmitter<O>,
+) -> Result<LayerRunResult<L::Metadata>, Error<L::Error>> {
+    LayerBackup::restore_leftover(layer_name, context)
+        .map_err(LayerLifecycleError::CannotRestoreLayer)?;
+
+    let layer_content_metadata = match context.read_layer_content_metadata(layer_name) {
+        Ok(value) => value,
+        Err(_) => {
+            // If we cannot read the metadata due to a TOML file error, it's very likely that the
+            // metadata could not be parsed into `L::Metadata` due to field/type mismatch(es).
+            // Regardless of the actual error, we run the metadata recovery process here.
+            metadata_recovery(layer_name, layer, context, emitter)?
+        }
+    };
+
+    let validate_result = match layer_content_metadata {
+        Some(layer_content_metadata) => {
+            let validate_result = layer.validate(layer_path, &layer_content_metadata, context);
+            emitter.emit(LayerEventKind::Validated {
+                previous_metadata: &layer_content_metadata,
+                validate_result,
+            });
+
+            let handler = match validate_result {
+                ValidateResult::KeepLayer => handle_layer_keep,
+                ValidateResult::RecreateLayer => handle_layer_recreate,
+                ValidateResult::UpdateLayer => handle_layer_update,
+            };
+
+            handler(
+                layer_name,
+                layer_path,
+                layer_content_metadata,
+                layer,
+                context,
+            )?;
+            Some(validate_result)
+        }
+        None => {
+            handle_layer_create(layer_name, layer_path, layer, context)?;
+            None
+        }
+    };
+
+    layer.on_lifecycle_end();
+
+    match context.read_layer_content_metadata(layer_name) {
+        Err(toml_file_error) => Err(Error::LayerLifecycleError(
+            LayerLifecycleError::CannotReadLayerContentMetadata(toml_file_error),
+        )),
+        Ok(None) => Err(Error::LayerLifecycleError(
+            LayerLifecycleError::CannotFindLayerMetadataAfterLifecycle(),
+        )),
+        Ok(Some(metadata)) => Ok((validate_result, metadata)),
+    }
+}
+
+fn handle_layer_keep<P: Platform, BM, L: Layer<P, BM>>(
+    layer_name: impl AsRef<str>,
+    _layer_path: &Path,
+    layer_content_metadata: LayerContentMetadata<L::Metadata>,
+    layer: &L,
+    context: &BuildContext<P, BM>,
+) -> Result<(), Error<L::Error>> {
+    layer.on_keep();
+
+    // Metadata that was migrated from a previous version when it was read differs from the
+    // metadata on disk. It is written back, so the migration does not need to happen again.
+    let stored_metadata = context
+        .read_layer_content_metadata::<toml::value::Table>(&layer_name)
+        .map_err(LayerLifecycleError::CannotReadLayerContentMetadata)?
+        .and_then(|stored_metadata| toml::Value::try_from(stored_metadata).ok());
+
+    let kept_metadata = toml::Value::try_from(&layer_content_metadata).ok();
+
+    if kept_metadata.is_some() && kept_metadata != stored_metadata {
+        write_toml_file(
+            &layer_content_metadata,
+            context.layer_content_metadata_path(&layer_name),
+        )
+        .map_err(LayerLifecycleError::CannotWriteLayerMetadata)?;
+    }
+
+    Ok(())
+}
+
+fn handle_layer_create<P: Platform, BM, L: Layer<P, BM>>(
+    layer_name: impl AsRef<str>,
+    layer_path: &Path,
+    layer: &L,
+    context: &BuildContext<P, BM>,
+) -> Result<(), Error<L::Error>> {
+    let backup = LayerBackup::move_existing(&layer_name, context)
+        .map_err(LayerLifecycleError::CannotBackUpLayer)?;
+
+    fs::create_dir_all(layer_path)
+        .map_err(LayerLifecycleError::CannotCreateLayerDirectoryBeforeCreate)?;
+
+    layer.on_create();
+
+    let layer_content_metadata = layer
+        .create(layer_path, context)
+        .map_err(Error::BuildpackError)?;
+
+    normalize_layer(layer_path, layer, context)?;
+    backup.commit(layer_content_metadata, context)
+}
+
+fn handle_layer_recreate<P: Platform, BM, L: Layer<P, BM>>(
+    layer_name: impl AsRef<str>,
+    layer_path: &Path,
+    _layer_content_metadata: LayerContentMetadata<L::Metadata>,
+    layer: &L,
+    context: &BuildContext<P, BM>,
+) -> Result<(), Error<L::Error>> {
+    // The existing layer is kept as a backup until the new one has been created successfully.
+    handle_layer_create(layer_name, layer_path, layer, context)
+}
+
+fn handle_layer_update<P: Platform, BM, L: Layer<P, BM>>(
+    layer_name: impl AsRef<str>,
+    layer_path: &Path,
+    layer_content_metadata: LayerContentMetadata<L::Metadata>,
+    layer: &L,
+    context: &BuildContext<P, BM>,
+) -> Result<(), Error<L::Error>> {
+    let backup = LayerBackup::link_existing(&layer_name, context)
+        .map_err(LayerLifecycleError::CannotBackUpLayer)?;
+
+    fs::create_dir_all(layer_path)
+        .map_err(LayerLifecycleError::CannotCreateLayerDirectoryBeforeCreate)?;
+
+    layer.on_update();
+
+    let content_metadata = layer
+        .update(layer_path, layer_content_metadata, context)
+        .map_err(Error::BuildpackError)?;
+
+    normalize_layer(layer_path, layer, context)?;
+    backup.commit(content_metadata, context)
+}
+
+fn normalize_layer<P: Platform, BM, L: Layer<P, BM>>(
+    layer_path: &Path,
+    layer: &L,
+    context: &BuildContext<P, BM>,
+) -> Result<(), Error<L::Error>> {
+    match layer.normalization() {
+        Some(normalization) => normalization
+            .normalize(layer_path, context.platform.env())
+            .map_err(|io_error| LayerLifecycleError::CannotNormalizeLayer(io_error).into()),
+        None => Ok(()),
+    }
+}
+
+/// A backup of an existing layer while the layer is created or updated at its actual location
+///
+/// The backup is a hidden directory in the layers directory that holds the previous layer
+/// directory and its content metadata file. The content metadata of the layer itself is moved to
+/// the backup first, so a layer that is being created or updated never has metadata and is treated
+/// as if it does not exist.
+///
+/// The content metadata file in the backup marks it as restorable. [`LayerBackup::commit`] removes
+/// it before the metadata of the new layer is moved into place and removes the backup afterwards,
+/// so a leftover backup never replaces a committed layer. If the backup is dropped without being
+/// committed, the new layer is deleted and the previous one restored.
+struct LayerBackup {
+    layer_name: String,
+    layer_path: PathBuf,
+    layer_metadata_path: PathBuf,
+    path: PathBuf,
+}
+
+impl LayerBackup {
+    /// Moves the existing layer, if any, to the backup. The layer directory does not exist
+    /// afterwards.
+    fn move_existing<P: Platform, BM>(
+        layer_name: impl AsRef<str>,
+        context: &BuildContext<P, BM>,
+    ) -> Result<Self, std::io::Error> {
+        let backup = LayerBackup::empty(layer_name, context)?;
+
+        if backup.move_metadata()? {
+            match fs::rename(&backup.layer_path, backup.layer_backup_path()) {
+                Err(io_error) if io_error.kind() == std::io::ErrorKind::NotFound => {
+                    fs::create_dir(backup.layer_backup_path())
+                }
+                result => result,
+            }?;
+        } else {
+            remove_dir_if_exists(&backup.layer_path)?;
+        }
+
+        Ok(backup)
+    }
+
+    /// Links the files of the existing layer into the backup. The layer directory stays in place,
+    /// only its metadata is moved to the backup.
+    fn link_existing<P: Platform, BM>(
+        layer_name: impl AsRef<str>,
+        context: &BuildContext<P, BM>,
+    ) -> Result<Self, std::io::Error> {
+        let backup = LayerBackup::empty(layer_name, context)?;
+
+        if backup.layer_path.exists() {
+            link_dir(&backup.layer_path, &backup.layer_backup_path())?;
+        } else {
+            fs::create_dir(backup.layer_backup_path())?;
+        }
+
+        backup.move_metadata()?;
+        Ok(backup)
+    }
+
+    /// Restores the backup left behind by an interrupted execution of the layer, if any.
+    fn restore_leftover<P: Platform, BM>(
+        layer_name: impl AsRef<str>,
+        context: &BuildContext<P, BM>,
+    ) -> Result<(), std::io::Error> {
+        let backup = std::mem::ManuallyDrop::new(LayerBackup::new(layer_name, context));
+
+        if backup.path.exists() {
+            backup.restore()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn empty<P: Platform, BM>(
+        layer_name: impl AsRef<str>,
+        context: &BuildContext<P, BM>,
+    ) -> Result<Self, std::io::Error> {
+        let backup = LayerBackup::new(layer_name, context);
+        remove_dir_if_exists(&backup.path)?;
+        fs::create_dir_all(&backup.path)?;
+
+        Ok(backup)
+    }
+
+    fn new<P: Platform, BM>(layer_name: impl AsRef<str>, context: &BuildContext<P, BM>) -> Self {
+        let layer_name = String::from(layer_name.as_ref());
+
+        LayerBackup {
+            layer_path: context.layer_path(&layer_name),
+            layer_metadata_path: context.layer_content_metadata_path(&layer_name),
+            path: context.layers_dir.join(format!(".{}.backup", layer_name)),
+            layer_name,
+        }
+    }
+
+    fn layer_backup_path(&self) -> PathBuf {
+        self.path.join("layer")
+    }
+
+    fn metadata_backup_path(&self) -> PathBuf {
+        self.path.join("layer.toml")
+    }
+
+    fn new_metadata_path(&self) -> PathBuf {
+        self.path.join("new.toml")
+    }
+
+    /// Moves the metadata of the existing layer to the backup, returns whether there was any.
+    ///
+    /// From then on, the layer is treated as if it does not exist and the backup is restored
+    /// unless the layer is committed.
+    fn move_metadata(&self) -> Result<bool, std::io::Error> {
+        match fs::rename(&self.layer_metadata_path, self.metadata_backup_path()) {
+            Ok(()) => Ok(true),
+            Err(io_error) if io_error.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(io_error) => Err(io_error),
+        }
+    }
+
+    /// Moves the layer content metadata of the new layer into place and removes the backup.
+    fn commit<P: Platform, BM, M: Serialize, E: Debug + Display>(
+        self,
+        layer_content_metadata: LayerContentMetadata<M>,
+        context: &BuildContext<P, BM>,
+    ) -> Result<(), Error<E>> {
+        // Checked before adjusting the types, the restrictions of the lifecycle mode are intended.
+        warn_about_layer_types(
+            &context.layer_usage,
+            &self.layer_name,
+            &layer_content_metadata.types,
+        );
+
+        // Written to the backup first, the previous layer is restored if this fails.
+        write_toml_file(
+            &layer_content_metadata.for_lifecycle_mode(context.lifecycle_mode),
+            self.new_metadata_path(),
+        )
+        .map_err(LayerLifecycleError::CannotWriteLayerMetadata)?;
+
+        // Without its metadata, the backup is no longer restored. If the build is interrupted
+        // before the new metadata is in place, the layer is treated as if it does not exist.
+        fs::remove_file(self.metadata_backup_path())
+            .or_else(|io_error| match io_error.kind() {
+                std::io::ErrorKind::NotFound => Ok(()),
+                _ => Err(io_error),
+            })
+            .and_then(|()| fs::rename(self.new_metadata_path(), &self.layer_metadata_path))
+            .map_err(|io_error| LayerLifecycleError::CannotWriteLayerMetadata(io_error.into()))?;
+
+        // The new layer is committed, failing to clean up the backup must not restore it.
+        let backup = std::mem::ManuallyDrop::new(self);
+        if let Err(io_error) = remove_dir_if_exists(&backup.path) {
+            eprintln!(
+                "Warning: could not remove layer backup {}: {}",
+                backup.path.display(),
+                io_error
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the current layer with the backup of the previous one
+    ///
+    /// If the metadata of the previous layer has not been moved to the backup yet, the previous
+    /// layer has not been touched and is kept. The backup is removed in either case.
+    fn restore(&self) -> Result<(), std::io::Error> {
+        if self.metadata_backup_path().exists() {
+            remove_if_exists(&self.layer_metadata_path)?;
+            remove_dir_if_exists(&self.layer_path)?;
+
+            // The directory is restored before the metadata, a layer directory without metadata
+            // is treated as if the layer does not exist.
+            fs::rename(self.layer_backup_path(), &self.layer_path)?;
+            fs::rename(self.metadata_backup_path(), &self.layer_metadata_path)?;
+        } else if !self.layer_metadata_path.exists() {
+            remove_dir_if_exists(&self.layer_path)?;
+        }
+
+        remove_dir_if_exists(&self.path)
+    }
+}
+
+impl Drop for LayerBackup {
+    fn drop(&mut self) {
+        // Only reached when the layer was not committed.
+        if let Err(io_error) = self.restore() {
+            eprintln!(
+                "Warning: could not restore layer {} from backup {}: {}",
+                self.layer_name,
+                self.path.display(),
+                io_error
+            );
+        }
+    }
+}
+
+/// Recreates a directory tree with hard links to its files, symlinks are copied as symlinks.
+///
+/// Files are copied if they cannot be linked.
+fn link_dir(source: &Path, destination: &Path) -> Result<(), std::io::Error> {
+    fs::create_dir(destination)?;
+    fs::set_permissions(destination, fs::metadata(source)?.permissions())?;
+
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let destination = destination.join(entry.file_name());
+
+        if file_type.is_dir() {
+            link_dir(&entry.path(), &destination)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&entry.path(), &destination)?;
+        } else if fs::hard_link(entry.path(), &destination).is_err() {
+            fs::copy(entry.path(), &destination)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(source: &Path, destination: &Path) -> Result<(), std::io::Error> {
+    std::os::unix::fs::symlink(fs::read_link(source)?, destination)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(source: &Path, destination: &Path) -> Result<(), std::io::Error> {
+    fs::copy(source, destination).map(|_| ())
+}
+
+fn remove_dir_if_exists(path: impl AsRef<Path>) -> Result<(), std::io::Error> {
+    match fs::remove_dir_all(path) {
+        Err(io_error) if io_error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn remove_if_exists(path: impl AsRef<Path>) -> Result<(), std::io::Error> {
+    match fs::remove_file(path) {
+        Err(io_error) if io_error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn metadata_recovery<P: Platform, BM, L: Layer<P, BM>, O: LayerObserver>(
+    layer_name: impl AsRef<str>,
+    layer: &L,
+    context: &BuildContext<P, BM>,
+    emitter: &LayerEventEmitter<O>,
+) -> Result<Option<LayerContentMetadata<L::Metadata>>, Error<L::Error>> {
+    let (mut layer_content_metadata, metadata_recovery_strategy) =
+        match metadata_recovery_strategy(&layer_name, layer, context)? {
+            None => return Ok(None),
+            Some(value) => value,
+        };
+
+    emitter.emit(LayerEventKind::MetadataRecovery {
+        strategy: &metadata_recovery_strategy,
+    });
+
+    match metadata_recovery_strategy {
+        MetadataRecoveryStrategy::DeleteLayer => {
+            context
+                .delete_layer(&layer_name)
+                .map_err(LayerLifecycleError::CannotDeleteLayer)?;
+
+            Ok(None)
+        }
+        MetadataRecoveryStrategy::ReplaceMetadata(replacement_metadata) => {
+            let updated_metadata = layer_content_metadata.metadata(replacement_metadata);
+
+            context
+                .write_layer_content_metadata(&layer_name, &updated_metadata)
+                .map_err(LayerLifecycleError::CannotReplaceLayerMetadata)?;
+
+            Ok(Some(updated_metadata))
+        }
+    }
+}
+
+type UntypedMetadataRecovery<M> = (
+    LayerContentMetadata<toml::value::Table>,
+    MetadataRecoveryStrategy<M>,
+);
+
+/// Reads the existing layer content metadata as a TOML table and asks the layer how to recover
+/// from it, without modifying the layer.
+fn metadata_recovery_strategy<P: Platform, BM, L: Layer<P, BM>>(
+    layer_name: impl AsRef<str>,
+    layer: &L,
+    context: &BuildContext<P, BM>,
+) -> Result<Option<UntypedMetadataRecovery<L::Metadata>>, Error<L::Error>> {
+    // Read existing layer content metadata as TOML table, handling potential errors and
+    // non-existent metadata so subsequent steps don't have to deal with either.
+    let layer_content_metadata = {
+        let maybe_layer_content_metadata = context
+            .read_layer_content_metadata(&layer_name)
+            .map_err(|toml_file_error| {
+                Error::LayerLifecycleError(LayerLifecycleError::CannotNotReadUntypedLayerMetadata(
+                    toml_file_error,
+                ))
+            })?;
+
+        match maybe_layer_content_metadata {
+            None => return Ok(None),
+            Some(value) => value,
+        }
+    };
+
+    let metadata_recovery_strategy = layer
+        .recover_from_invalid_metadata(&layer_content_metadata.metadata, context)
+        .map_err(Error::BuildpackError)?;
+
+    Ok(Some((layer_content_metadata, metadata_recovery_strategy)))
+}
+
+/// The action [`execute_layer`] takes for a layer
+///
+/// See [`plan_layer`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LayerAction {
+    /// Create the layer via [`Layer::create`]
+    Create,
+    /// Keep the existing layer as it is
+    Keep,
+    /// Update the existing layer via [`Layer::update`]
+    Update,
+    /// Replace the existing layer with one created via [`Layer::create`]
+    Recreate,
+    /// Delete the layer, if it exists
+    Delete,
+}
+
+/// The reason for a planned [`LayerAction`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LayerActionReason {
+    /// There is no existing layer.
+    NoExistingLayer,
+    /// The metadata of the existing layer is invalid and the layer will be deleted as decided by
+    /// [`Layer::recover_from_invalid_metadata`].
+    InvalidMetadata,
+    /// The action is the result of [`Layer::validate`]. If the metadata of the existing layer was
+    /// invalid, `metadata_replaced` signals that it has been replaced before validation.
+    Validated { metadata_replaced: bool },
+    /// The layer is not included in the lifecycle mode of the build.
+    ExcludedInMode(Option<LifecycleMode>),
+}
+
+/// The result of planning a layer, see [`plan_layer`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LayerPlan {
+    pub layer_name: String,
+    pub action: LayerAction,
+    pub reason: LayerActionReason,
+}
+
+impl Display for LayerPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self.action {
+            LayerAction::Create => "create",
+            LayerAction::Keep => "keep",
+            LayerAction::Update => "update",
+            LayerAction::Recreate => "recreate",
+            LayerAction::Delete => "delete",
+        };
+
+        write!(f, "{}: {} (", self.layer_name, action)?;
+
+        match self.reason {
+            LayerActionReason::NoExistingLayer => write!(f, "no existing layer"),
+            LayerActionReason::InvalidMetadata => write!(f, "invalid metadata"),
+            LayerActionReason::Validated {
+                metadata_replaced: false,
+            } => write!(f, "validated"),
+            LayerActionReason::Validated {
+                metadata_replaced: true,
+            } => write!(f, "validated after replacing invalid metadata"),
+            LayerActionReason::ExcludedInMode(Some(lifecycle_mode)) => {
+                write!(f, "excluded in {} mode", lifecycle_mode)
+            }
+            LayerActionReason::ExcludedInMode(None) => write!(f, "excluded without mode"),
+        }?;
+
+        write!(f, ")")
+    }
+}
+
+/// Determines the action [`execute_layer`] would take for a layer, without executing it
+///
+/// Existing metadata is read and [`Layer::recover_from_invalid_metadata`] and [`Layer::validate`]
+/// are called, but nothing is deleted or written. Neither [`Layer::create`], [`Layer::update`] nor
+/// any of the `on_*` hooks are called.
+///
+/// # Example
+/// ```no_run
+/// # use libcnb::layer::{plan_layer, Layer};
+/// # use libcnb::{Error, GenericBuildContext, GenericMetadata, GenericPlatform};
+/// # fn plan<L: Layer<GenericPlatform, GenericMetadata>>(
+/// #     context: &GenericBuildContext,
+/// #     ruby_layer: L,
+/// # ) -> Result<(), Error<L::Error>> {
+/// let layer_plan = plan_layer("ruby", ruby_layer, context)?;
+/// println!("{}", layer_plan); // ruby: keep (validated)
+/// # Ok(())
+/// # }
+/// ```
+pub fn plan_layer<P: Platform, BM, L: Layer<P, BM>>(
+    layer_name: impl AsRef<str>,
+    layer: L,
+    context: &BuildContext<P, BM>,
+) -> Result<LayerPlan, Error<L::Error>> {
+    let layer_plan = |action, reason| LayerPlan {
+        layer_name: String::from(layer_name.as_ref()),
+        action,
+        reason,
+    };
+
+    if !layer.include_in_mode(context.lifecycle_mode) {
+        return Ok(layer_plan(
+            LayerAction::Delete,
+            LayerActionReason::ExcludedInMode(context.lifecycle_mode),
+        ));
+    }
+
+    let (layer_content_metadata, metadata_replaced) =
+        match context.read_layer_content_metadata(&layer_name) {
+            Ok(layer_content_metadata) => (layer_content_metadata, false),
+            Err(_) => match metadata_recovery_strategy(&layer_name, &layer, context)? {
+                None => (None, false),
+                Some((_, MetadataRecoveryStrategy::DeleteLayer)) => {
+                    return Ok(layer_plan(
+                        LayerAction::Create,
+                        LayerActionReason::InvalidMetadata,
+                    ));
+                }
+                Some((
+                    mut layer_content_metadata,
+                    MetadataRecoveryStrategy::ReplaceMetadata(replacement_metadata),
+                )) => (
+                    Some(layer_content_metadata.metadata(replacement_metadata)),
+                    true,
+                ),
+            },
+        };
+
+    Ok(match layer_content_metadata {
+        None => layer_plan(LayerAction::Create, LayerActionReason::NoExistingLayer),
+        Some(layer_content_metadata) => {
+            let action = match layer.validate(
+                &context.layer_path(&layer_name),
+                &layer_content_metadata,
+                context,
+            ) {
+                ValidateResult::KeepLayer => LayerAction::Keep,
+                ValidateResult::UpdateLayer => LayerAction::Update,
+                ValidateResult::RecreateLayer => LayerAction::Recreate,
+            };
+
+            layer_plan(action, LayerActionReason::Validated { metadata_replaced })
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::versioned_metadata::{
+        NoPreviousVersion, Versioned, VersionedMetadata, METADATA_VERSION_KEY,
+    };
+    use crate::generic::{GenericMetadata, GenericPlatform};
+    use crate::layer_events::LayerEvent;
+    use crate::test_support::build_context;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Serialize)]
+    struct TestLayerMetadata {
+        version: String,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    struct CurrentMetadata {
+        version: String,
+        checksum: Option<String>,
+    }
+
+    impl From<TestLayerMetadata> for CurrentMetadata {
+        fn from(previous: TestLayerMetadata) -> Self {
+            CurrentMetadata {
+                version: previous.version,
+                checksum: None,
+            }
+        }
+    }
+
+    impl VersionedMetadata for TestLayerMetadata {
+        const VERSION: u32 = 1;
+        type Previous = NoPreviousVersion;
+
+        fn migrate_from_previous(previous: NoPreviousVersion) -> Option<Self> {
+            match previous {}
+        }
+    }
+
+    impl VersionedMetadata for CurrentMetadata {
+        const VERSION: u32 = 2;
+        type Previous = TestLayerMetadata;
+
+        fn migrate_from_previous(previous: TestLayerMetadata) -> Option<Self> {
+            Some(previous.into())
+        }
+    }
+
+    struct TestLayer {
+        version: &'static str,
+        fail: bool,
+        validate_result: fn() -> ValidateResult,
+    }
+
+    impl TestLayer {
+        fn write_and_maybe_fail(
+            &self,
+            layer_path: &Path,
+        ) -> Result<LayerContentMetadata<TestLayerMetadata>, std::io::Error> {
+            // Replaced rather than modified in place, the backup of updated layers links to it.
+            remove_if_exists(layer_path.join("version"))?;
+            fs::write(layer_path.join("version"), self.version)?;
+
+            if self.fail {
+                Err(std::io::Error::new(std::io::ErrorKind::Other, "failed"))
+            } else {
+                Ok(LayerContentMetadata::default().metadata(TestLayerMetadata {
+                    version: String::from(self.version),
+                }))
+            }
+        }
+    }
+
+    impl<P: Platform, BM> Layer<P, BM> for TestLayer {
+        type Metadata = TestLayerMetadata;
+        type Output = ();
+        type Error = std::io::Error;
+
+        fn create(
+            &self,
+            layer_path: &Path,
+            build_context: &BuildContext<P, BM>,
+        ) -> Result<LayerContentMetadata<Self::Metadata>, Self::Error> {
+            // Layers are built at their actual location, absolute paths in them stay valid.
+            assert_eq!(layer_path, build_context.layer_path("test"));
+            self.write_and_maybe_fail(layer_path)
+        }
+
+        fn validate(
+            &self,
+            _layer_path: &Path,
+            _layer_content_metadata: &LayerContentMetadata<Self::Metadata>,
+            _build_context: &BuildContext<P, BM>,
+        ) -> ValidateResult {
+            (self.validate_result)()
+        }
+
+        fn update(
+            &self,
+            layer_path: &Path,
+            _layer_content_metadata: LayerContentMetadata<Self::Metadata>,
+            build_context: &BuildContext<P, BM>,
+        ) -> Result<LayerContentMetadata<Self::Metadata>, Self::Error> {
+            assert_eq!(layer_path, build_context.layer_path("test"));
+            assert!(!build_context.layer_content_metadata_path("test").exists());
+            self.write_and_maybe_fail(layer_path)
+        }
+
+        fn output(
+            &self,
+            _layer_path: &Path,
+            _layer_content_metadata: LayerContentMetadata<Self::Metadata>,
+        ) -> Result<Self::Output, Self::Error> {
+            Ok(())
+        }
+    }
+
+    struct NormalizedTestLayer(TestLayer);
+
+    impl<P: Platform, BM> Layer<P, BM> for NormalizedTestLayer {
+        type Metadata = TestLayerMetadata;
+        type Output = ();
+        type Error = std::io::Error;
+
+        fn create(
+            &self,
+            layer_path: &Path,
+            _build_context: &BuildContext<P, BM>,
+        ) -> Result<LayerContentMetadata<Self::Metadata>, Self::Error> {
+            self.0.write_and_maybe_fail(layer_path)
+        }
+
+        fn output(
+            &self,
+            _layer_path: &Path,
+            _layer_content_metadata: LayerContentMetadata<Self::Metadata>,
+        ) -> Result<Self::Output, Self::Error> {
+            Ok(())
+        }
+
+        fn normalization(&self) -> Option<LayerNormalization> {
+            Some(LayerNormalization::new().mtime(std::time::SystemTime::UNIX_EPOCH))
+        }
+    }
+
+    fn layers_dir_entries(context: &BuildContext<GenericPlatform, GenericMetadata>) -> Vec<String> {
+        let mut entries: Vec<String> = fs::read_dir(&context.layers_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn create_writes_layer_in_place() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let layer = TestLayer {
+            version: "1.0.0",
+            fail: false,
+            validate_result: || ValidateResult::RecreateLayer,
+        };
+
+        execute_layer("test", layer, &context).unwrap();
+
+        assert_eq!(layers_dir_entries(&context), vec!["test", "test.toml"]);
+        assert_eq!(
+            fs::read_to_string(context.layer_path("test").join("version")).unwrap(),
+            "1.0.0"
+        );
+    }
+
+    #[test]
+    fn create_normalizes_layer_contents_when_requested() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let layer = NormalizedTestLayer(TestLayer {
+            version: "1.0.0",
+            fail: false,
+            validate_result: || ValidateResult::RecreateLayer,
+        });
+
+        execute_layer("test", layer, &context).unwrap();
+
+        let modified = fs::metadata(context.layer_path("test").join("version"))
+            .and_then(|metadata| metadata.modified())
+            .unwrap();
+        assert_eq!(modified, std::time::SystemTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn failed_create_leaves_no_layer_behind() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let layer = TestLayer {
+            version: "1.0.0",
+            fail: true,
+            validate_result: || ValidateResult::RecreateLayer,
+        };
+
+        assert!(execute_layer("test", layer, &context).is_err());
+        assert!(layers_dir_entries(&context).is_empty());
+    }
+
+    #[test]
+    fn failed_recreate_keeps_existing_layer() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let layer = TestLayer {
+            version: "1.0.0",
+            fail: false,
+            validate_result: || ValidateResult::RecreateLayer,
+        };
+        execute_layer("test", layer, &context).unwrap();
+
+        let layer = TestLayer {
+            version: "2.0.0",
+            fail: true,
+            validate_result: || ValidateResult::RecreateLayer,
+        };
+        assert!(execute_layer("test", layer, &context).is_err());
+
+        assert_eq!(layers_dir_entries(&context), vec!["test", "test.toml"]);
+        assert_eq!(
+            fs::read_to_string(context.layer_path("test").join("version")).unwrap(),
+            "1.0.0"
+        );
+    }
+
+    #[test]
+    fn update_replaces_existing_layer() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let layer = TestLayer {
+            version: "1.0.0",
+            fail: false,
+            validate_result: || ValidateResult::UpdateLayer,
+        };
+        execute_layer("test", layer, &context).unwrap();
+
+        let layer = TestLayer {
+            version: "2.0.0",
+            fail: false,
+            validate_result: || ValidateResult::UpdateLayer,
+        };
+        execute_layer("test", layer, &context).unwrap();
+
+        assert_eq!(layers_dir_entries(&context), vec!["test", "test.toml"]);
+        let metadata = context
+            .read_layer_content_metadata::<TestLayerMetadata>("test")
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.metadata.version, "2.0.0");
+    }
+
+    #[test]
+    fn failed_update_restores_existing_layer() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let layer = TestLayer {
+            version: "1.0.0",
+            fail: false,
+            validate_result: || ValidateResult::UpdateLayer,
+        };
+        execute_layer("test", layer, &context).unwrap();
+
+        let layer = TestLayer {
+            version: "2.0.0",
+            fail: true,
+            validate_result: || ValidateResult::UpdateLayer,
+        };
+        assert!(execute_layer("test", layer, &context).is_err());
+
+        assert_eq!(layers_dir_entries(&context), vec!["test", "test.toml"]);
+        assert_eq!(
+            fs::read_to_string(context.layer_path("test").join("version")).unwrap(),
+            "1.0.0"
+        );
+        let metadata = context
+            .read_layer_content_metadata::<TestLayerMetadata>("test")
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.metadata.version, "1.0.0");
+    }
+
+    #[test]
+    fn leftover_backup_is_restored() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let layer = TestLayer {
+            version: "1.0.0",
+            fail: false,
+            validate_result: || ValidateResult::KeepLayer,
+        };
+        execute_layer("test", layer, &context).unwrap();
+
+        // An update that was interrupted after backing up the layer.
+        let backup = LayerBackup::link_existing("test", &context).unwrap();
+        fs::remove_file(context.layer_path("test").join("version")).unwrap();
+        fs::write(context.layer_path("test").join("version"), "2.0.0").unwrap();
+        std::mem::forget(backup);
+        assert_eq!(layers_dir_entries(&context), vec![".test.backup", "test"]);
+
+        let layer = TestLayer {
+            version: "3.0.0",
+            fail: false,
+            validate_result: || ValidateResult::KeepLayer,
+        };
+        execute_layer("test", layer, &context).unwrap();
+
+        assert_eq!(layers_dir_entries(&context), vec!["test", "test.toml"]);
+        assert_eq!(
+            fs::read_to_string(context.layer_path("test").join("version")).unwrap(),
+            "1.0.0"
+        );
+    }
+
+    #[test]
+    fn leftover_backup_of_committed_layer_is_removed() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let layer = TestLayer {
+            version: "1.0.0",
+            fail: false,
+            validate_result: || ValidateResult::UpdateLayer,
+        };
+        execute_layer("test", layer, &context).unwrap();
+
+        // A backup left behind after the new metadata was committed.
+        let backup = LayerBackup::link_existing("test", &context).unwrap();
+        fs::remove_file(backup.metadata_backup_path()).unwrap();
+        context
+            .write_layer_content_metadata(
+                "test",
+                &LayerContentMetadata::default().metadata(TestLayerMetadata {
+                    version: String::from("2.0.0"),
+                }),
+            )
+            .unwrap();
+        std::mem::forget(backup);
+
+        let layer = TestLayer {
+            version: "3.0.0",
+            fail: false,
+            validate_result: || ValidateResult::KeepLayer,
+        };
+        execute_layer("test", layer, &context).unwrap();
+
+        assert_eq!(layers_dir_entries(&context), vec!["test", "test.toml"]);
+        let metadata = context
+            .read_layer_content_metadata::<TestLayerMetadata>("test")
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.metadata.version, "2.0.0");
+    }
+
+    #[test]
+    fn update_links_instead_of_copying_files() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let layer = TestLayer {
+            version: "1.0.0",
+            fail: false,
+            validate_result: || ValidateResult::UpdateLayer,
+        };
+        execute_layer("test", layer, &context).unwrap();
+
+        let backup = LayerBackup::link_existing("test", &context).unwrap();
+        assert_eq!(
+            fs::read_to_string(backup.layer_backup_path().join("version")).unwrap(),
+            "1.0.0"
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let metadata = fs::metadata(context.layer_path("test").join("version")).unwrap();
+            assert_eq!(metadata.nlink(), 2);
+        }
+    }
+
+    #[test]
+    fn versioned_metadata_is_migrated_before_validate() {
+        struct VersionedLayer;
+
+        impl<P: Platform, BM> Layer<P, BM> for VersionedLayer {
+            type Metadata = Versioned<CurrentMetadata>;
+            type Output = String;
+            type Error = std::io::Error;
+
+            fn create(
+                &self,
+                _layer_path: &Path,
+                _build_context: &BuildContext<P, BM>,
+            ) -> Result<LayerContentMetadata<Self::Metadata>, Self::Error> {
+                unreachable!("The existing layer must be migrated and kept")
+            }
+
+            fn validate(
+                &self,
+                _layer_path: &Path,
+                layer_content_metadata: &LayerContentMetadata<Self::Metadata>,
+                _build_context: &BuildContext<P, BM>,
+            ) -> ValidateResult {
+                assert_eq!(layer_content_metadata.metadata.version, "1.0.0");
+                assert_eq!(layer_content_metadata.metadata.checksum, None);
+                ValidateResult::KeepLayer
+            }
+
+            fn output(
+                &self,
+                _layer_path: &Path,
+                layer_content_metadata: LayerContentMetadata<Self::Metadata>,
+            ) -> Result<Self::Output, Self::Error> {
+                Ok(layer_content_metadata.metadata.into_inner().version)
+            }
+        }
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let layer = TestLayer {
+            version: "1.0.0",
+            fail: false,
+            validate_result: || ValidateResult::RecreateLayer,
+        };
+        execute_layer("test", layer, &context).unwrap();
+
+        // Unversioned metadata written by a previous buildpack version
+        assert_eq!(
+            execute_layer("test", VersionedLayer, &context).unwrap(),
+            "1.0.0"
+        );
+
+        // The migrated metadata is written back when the layer is kept.
+        let stored_metadata = context
+            .read_layer_content_metadata::<toml::value::Table>("test")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            stored_metadata.metadata.get(METADATA_VERSION_KEY),
+            Some(&toml::Value::Integer(2))
+        );
+        assert_eq!(
+            execute_layer("test", VersionedLayer, &context).unwrap(),
+            "1.0.0"
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        events: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl LayerObserver for RecordingObserver {
+        fn on_layer_event<M: Serialize>(&self, event: &LayerEvent<M>) {
+            let description = match &event.kind {
+                LayerEventKind::Start => String::from("start"),
+                LayerEventKind::MetadataRecovery { .. } => String::from("recovery"),
+                LayerEventKind::Validated {
+                    validate_result, ..
+                } => format!("validated {:?}", validate_result),
+                LayerEventKind::End {
+                    validate_result,
+                    metadata,
+                } => format!(
+                    "end {:?} {}",
+                    validate_result,
+                    toml::to_string(&metadata.metadata).unwrap().trim()
+                ),
+                LayerEventKind::Failed { error } => format!("failed {}", error),
+            };
+
+            self.events
+                .borrow_mut()
+                .push(format!("{}: {}", event.layer_name, description));
+        }
+    }
+
+    #[test]
+    fn observer_receives_events() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+        let observer = RecordingObserver::default();
+
+        let layer = TestLayer {
+            version: "1.0.0",
+            fail: false,
+            validate_result: || ValidateResult::KeepLayer,
+        };
+        execute_layer_with_observer("test", layer, &context, &observer).unwrap();
+
+        let layer = TestLayer {
+            version: "2.0.0",
+            fail: false,
+            validate_result: || ValidateResult::KeepLayer,
+        };
+        execute_layer_with_observer("test", layer, &context, &observer).unwrap();
+
+        let layer = TestLayer {
+            version: "3.0.0",
+            fail: true,
+            validate_result: || ValidateResult::UpdateLayer,
+        };
+        assert!(execute_layer_with_observer("test", layer, &context, &observer).is_err());
+
+        assert_eq!(
+            observer.events.into_inner(),
+            vec![
+                "test: start",
+                "test: end None version = \"1.0.0\"",
+                "test: start",
+                "test: validated KeepLayer",
+                "test: end Some(KeepLayer) version = \"1.0.0\"",
+                "test: start",
+                "test: validated UpdateLayer",
+                "test: failed Buildpack error: failed",
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_does_not_modify_layers() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let layer = || TestLayer {
+            version: "1.0.0",
+            fail: false,
+            validate_result: || ValidateResult::UpdateLayer,
+        };
+
+        let layer_plan = plan_layer("test", layer(), &context).unwrap();
+        assert_eq!(layer_plan.action, LayerAction::Create);
+        assert_eq!(layer_plan.reason, LayerActionReason::NoExistingLayer);
+        assert!(layers_dir_entries(&context).is_empty());
+
+        execute_layer("test", layer(), &context).unwrap();
+
+        let layer_plan = plan_layer("test", layer(), &context).unwrap();
+        assert_eq!(layer_plan.to_string(), "test: update (validated)");
+
+        fs::write(
+            context.layer_content_metadata_path("test"),
+            "[metadata]\nversion = 1",
+        )
+        .unwrap();
+
+        let layer_plan = plan_layer("test", layer(), &context).unwrap();
+        assert_eq!(layer_plan.action, LayerAction::Create);
+        assert_eq!(layer_plan.reason, LayerActionReason::InvalidMetadata);
+        assert_eq!(layers_dir_entries(&context), vec!["test", "test.toml"]);
+        assert_eq!(
+            fs::read_to_string(context.layer_path("test").join("version")).unwrap(),
+            "1.0.0"
+        );
+    }
+}
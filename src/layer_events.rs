@@ -0,0 +1,129 @@
+//! Structured events emitted while executing a layer
+//!
+//! Pass a [`LayerObserver`] to [`execute_layer_with_observer`](crate::layer::execute_layer_with_observer)
+//! to receive a [`LayerEvent`] for every step of the layer execution. This allows consistently
+//! formatted build output and telemetry across layers without each layer implementing it:
+//!
+//! ```
+//! use libcnb::layer_events::{LayerEvent, LayerEventKind, LayerObserver};
+//! use libcnb::layer::ValidateResult;
+//! use serde::Serialize;
+//!
+//! struct BuildLog;
+//!
+//! impl LayerObserver for BuildLog {
+//!     fn on_layer_event<M: Serialize>(&self, event: &LayerEvent<M>) {
+//!         match &event.kind {
+//!             LayerEventKind::End { validate_result: Some(ValidateResult::KeepLayer), .. } => {
+//!                 println!("---> Reusing cached {}", event.layer_name)
+//!             }
+//!             LayerEventKind::End { .. } => {
+//!                 println!("---> Installed {} in {:?}", event.layer_name, event.elapsed)
+//!             }
+//!             LayerEventKind::Failed { error } => {
+//!                 eprintln!("---> Failed to install {}: {}", event.layer_name, error)
+//!             }
+//!             _ => {}
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::fmt::Display;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::data::layer_content_metadata::LayerContentMetadata;
+use crate::layer::{MetadataRecoveryStrategy, ValidateResult};
+
+/// An event emitted while executing a layer.
+pub struct LayerEvent<'a, M> {
+    pub layer_name: &'a str,
+    pub layer_path: &'a Path,
+
+    /// Time passed since the execution of the layer started.
+    pub elapsed: Duration,
+
+    pub kind: LayerEventKind<'a, M>,
+}
+
+/// The kind of a [`LayerEvent`], in the order they are emitted.
+pub enum LayerEventKind<'a, M> {
+    /// The execution of the layer started.
+    Start,
+
+    /// The existing metadata could not be read, the given recovery strategy will be applied.
+    MetadataRecovery {
+        strategy: &'a MetadataRecoveryStrategy<M>,
+    },
+
+    /// The existing layer has been validated. Not emitted when there is no existing layer.
+    Validated {
+        previous_metadata: &'a LayerContentMetadata<M>,
+        validate_result: ValidateResult,
+    },
+
+    /// The execution of the layer finished successfully.
+    End {
+        /// The result of the validation, `None` if the layer was created since none existed.
+        validate_result: Option<ValidateResult>,
+        metadata: &'a LayerContentMetadata<M>,
+    },
+
+    /// The execution of the layer failed, no further events will be emitted.
+    Failed { error: &'a dyn Display },
+}
+
+/// Receives the [`LayerEvent`]s of layer executions.
+///
+/// The unit type `()` is an observer that ignores all events.
+pub trait LayerObserver {
+    fn on_layer_event<M: Serialize>(&self, event: &LayerEvent<M>);
+}
+
+impl LayerObserver for () {
+    fn on_layer_event<M: Serialize>(&self, _event: &LayerEvent<M>) {}
+}
+
+impl<O: LayerObserver> LayerObserver for &O {
+    fn on_layer_event<M: Serialize>(&self, event: &LayerEvent<M>) {
+        (*self).on_layer_event(event);
+    }
+}
+
+impl<A: LayerObserver, B: LayerObserver> LayerObserver for (A, B) {
+    fn on_layer_event<M: Serialize>(&self, event: &LayerEvent<M>) {
+        self.0.on_layer_event(event);
+        self.1.on_layer_event(event);
+    }
+}
+
+/// Emits events for a single layer execution.
+pub(crate) struct LayerEventEmitter<'a, O> {
+    observer: &'a O,
+    layer_name: &'a str,
+    layer_path: &'a Path,
+    start: Instant,
+}
+
+impl<'a, O: LayerObserver> LayerEventEmitter<'a, O> {
+    pub(crate) fn new(observer: &'a O, layer_name: &'a str, layer_path: &'a Path) -> Self {
+        LayerEventEmitter {
+            observer,
+            layer_name,
+            layer_path,
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn emit<M: Serialize>(&self, kind: LayerEventKind<M>) {
+        self.observer.on_layer_event(&LayerEvent {
+            layer_name: self.layer_name,
+            layer_path: self.layer_path,
+            elapsed: self.start.elapsed(),
+            kind,
+        });
+    }
+}
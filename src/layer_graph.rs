@@ -0,0 +1,664 @@
+//! Execution of multiple layers with dependencies between them
+//!
+//! A [`LayerGraph`] declares a set of layers and the layers each of them depends on. Layers that
+//! do not depend on each other are executed in parallel threads, dependent layers are executed
+//! once all their dependencies succeeded. Each layer is constructed right before its execution
+//! with access to the [`LayerOutputs`] of its dependencies:
+//!
+//! ```no_run
+//! # use std::path::Path;
+//! # use libcnb::data::layer_content_metadata::LayerContentMetadata;
+//! # use libcnb::layer::Layer;
+//! # use libcnb::layer_graph::LayerGraph;
+//! # use libcnb::{BuildContext, Env, GenericBuildContext, GenericMetadata, GenericPlatform, Platform};
+//! # struct JdkLayer;
+//! # struct MavenLayer;
+//! # struct MavenDependenciesLayer { env: Env }
+//! # macro_rules! layer { ($name:ident) => {
+//! # impl<P: Platform, BM> Layer<P, BM> for $name {
+//! #     type Metadata = GenericMetadata;
+//! #     type Output = ();
+//! #     type Error = std::io::Error;
+//! #     fn create(&self, _: &Path, _: &BuildContext<P, BM>) -> Result<LayerContentMetadata<GenericMetadata>, std::io::Error> {
+//! #         Ok(LayerContentMetadata::default())
+//! #     }
+//! #     fn output(&self, _: &Path, _: LayerContentMetadata<GenericMetadata>) -> Result<(), std::io::Error> {
+//! #         Ok(())
+//! #     }
+//! # }
+//! # } }
+//! # layer!(JdkLayer);
+//! # layer!(MavenLayer);
+//! # layer!(MavenDependenciesLayer);
+//! # fn build(context: GenericBuildContext) -> libcnb::Result<(), std::io::Error> {
+//! // The JDK and Maven are downloaded at the same time.
+//! let outputs = LayerGraph::new()
+//!     .layer("jdk", &[], |_| Ok(JdkLayer))
+//!     .layer("maven", &[], |_| Ok(MavenLayer))
+//!     .layer("dependencies", &["jdk", "maven"], |dependencies| {
+//!         Ok(MavenDependenciesLayer {
+//!             env: dependencies.build_env(&Env::from_current()),
+//!         })
+//!     })
+//!     .execute(&context)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::any::Any;
+use std::collections::HashSet;
+use std::fmt::{Debug, Display};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::Arc;
+
+use crate::build::BuildContext;
+use crate::env::Env;
+use crate::error::Error;
+use crate::layer::{execute_layer, Layer};
+use crate::layer_env::{LayerEnv, TargetLifecycle};
+use crate::layer_lifecycle::LayerLifecycleError;
+use crate::platform::Platform;
+
+type LayerExecution<'a, P, BM, E> = Box<
+    dyn FnOnce(&LayerOutputs, &BuildContext<P, BM>) -> Result<LayerOutput, Error<E>> + Send + 'a,
+>;
+
+struct LayerNode<'a, P: Platform, BM, E: Debug + Display> {
+    layer_name: String,
+    dependencies: Vec<String>,
+    execution: LayerExecution<'a, P, BM, E>,
+}
+
+#[derive(Clone)]
+struct LayerOutput {
+    output: Arc<dyn Any + Send + Sync>,
+    layer_env: Arc<LayerEnv>,
+}
+
+/// A set of layers with dependencies between them, see [the module documentation](self).
+///
+/// All layers of a graph share the same error type.
+pub struct LayerGraph<'a, P: Platform, BM, E: Debug + Display> {
+    nodes: Vec<LayerNode<'a, P, BM, E>>,
+}
+
+impl<'a, P: Platform, BM, E: Debug + Display> LayerGraph<'a, P, BM, E> {
+    pub fn new() -> Self {
+        LayerGraph { nodes: Vec::new() }
+    }
+
+    /// Adds a layer that depends on the given layers.
+    ///
+    /// `make_layer` is called right before the layer is executed and receives the outputs of all
+    /// direct and transitive dependencies of the layer.
+    #[must_use]
+    pub fn layer<L, F>(
+        mut self,
+        layer_name: impl Into<String>,
+        dependencies: &[&str],
+        make_layer: F,
+    ) -> Self
+    where
+        L: Layer<P, BM, Error = E>,
+        L::Output: Send + Sync + 'static,
+        F: FnOnce(&LayerOutputs) -> Result<L, E> + Send + 'a,
+    {
+        let layer_name = layer_name.into();
+        let execution_layer_name = layer_name.clone();
+
+        self.nodes.push(LayerNode {
+            layer_name,
+            dependencies: dependencies.iter().map(|s| String::from(*s)).collect(),
+            execution: Box::new(move |dependencies, context| {
+                let layer = make_layer(dependencies).map_err(Error::BuildpackError)?;
+                let output = execute_layer(&execution_layer_name, layer, context)?;
+
+                let layer_env =
+                    LayerEnv::read_from_layer_dir(context.layer_path(&execution_layer_name))
+                        .map_err(LayerLifecycleError::CannotReadLayerEnv)?;
+
+                Ok(LayerOutput {
+                    output: Arc::new(output),
+                    layer_env: Arc::new(layer_env),
+                })
+            }),
+        });
+
+        self
+    }
+
+    /// Executes all layers of the graph
+    ///
+    /// Layers are executed as soon as all their dependencies succeeded, independent layers in
+    /// parallel. When a layer fails, its dependents are skipped but all other layers are still
+    /// executed. All failures are reported together in [`LayerGraphError::LayersFailed`].
+    pub fn execute(self, context: &BuildContext<P, BM>) -> Result<LayerOutputs, LayerGraphError<E>>
+    where
+        P: Sync,
+        BM: Sync,
+        E: Send,
+    {
+        let order = self.topological_order()?;
+        let dependencies: Vec<Vec<usize>> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                node.dependencies
+                    .iter()
+                    .map(|dependency| self.index_of(dependency).unwrap_or_default())
+                    .collect()
+            })
+            .collect();
+
+        let layer_names: Vec<String> = self
+            .nodes
+            .iter()
+            .map(|node| node.layer_name.clone())
+            .collect();
+
+        let mut executions: Vec<Option<LayerExecution<P, BM, E>>> = self
+            .nodes
+            .into_iter()
+            .map(|node| Some(node.execution))
+            .collect();
+
+        let mut outputs: Vec<Option<LayerOutput>> = vec![None; layer_names.len()];
+        let mut failures = Vec::new();
+        let mut skipped = HashSet::new();
+        let mut panic_payload = None;
+
+        crossbeam_utils::thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            let mut running = 0;
+
+            loop {
+                for &index in &order {
+                    let ready = dependencies[index]
+                        .iter()
+                        .all(|&dependency| outputs[dependency].is_some());
+
+                    if ready && panic_payload.is_none() && !skipped.contains(&index) {
+                        if let Some(execution) = executions[index].take() {
+                            let layer_outputs = LayerOutputs::collect(
+                                &transitive_dependencies(index, &dependencies, &order),
+                                &layer_names,
+                                &outputs,
+                            );
+
+                            let sender = sender.clone();
+                            scope.spawn(move |_| {
+                                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                                    execution(&layer_outputs, context)
+                                }));
+
+                                // The receiver outlives all threads of the scope.
+                                let _ = sender.send((index, result));
+                            });
+
+                            running += 1;
+                        }
+                    }
+                }
+
+                if running == 0 {
+                    break;
+                }
+
+                if let Ok((index, result)) = receiver.recv() {
+                    running -= 1;
+
+                    match result {
+                        Err(payload) => panic_payload = Some(payload),
+                        Ok(Ok(output)) => outputs[index] = Some(output),
+                        Ok(Err(error)) => {
+                            failures.push(LayerFailure {
+                                layer_name: layer_names[index].clone(),
+                                error,
+                            });
+
+                            for &dependent in &order {
+                                if transitive_dependencies(dependent, &dependencies, &order)
+                                    .contains(&index)
+                                {
+                                    skipped.insert(dependent);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        })
+        .expect("Layer execution threads do not panic, panics are caught");
+
+        if let Some(payload) = panic_payload {
+            panic::resume_unwind(payload);
+        }
+
+        if failures.is_empty() {
+            Ok(LayerOutputs::collect(&order, &layer_names, &outputs))
+        } else {
+            let mut skipped: Vec<usize> = skipped.into_iter().collect();
+            skipped.sort_unstable();
+
+            Err(LayerGraphError::LayersFailed {
+                failures,
+                skipped: skipped
+                    .into_iter()
+                    .map(|index| layer_names[index].clone())
+                    .collect(),
+            })
+        }
+    }
+
+    fn index_of(&self, layer_name: &str) -> Option<usize> {
+        self.nodes
+            .iter()
+            .position(|node| node.layer_name == layer_name)
+    }
+
+    /// Validates the graph and returns the indices of all nodes in an order in which each node
+    /// comes after all of its dependencies. Nodes are otherwise kept in declaration order.
+    fn topological_order(&self) -> Result<Vec<usize>, LayerGraphError<E>> {
+        let mut layer_names = HashSet::new();
+        for node in &self.nodes {
+            if !layer_names.insert(&node.layer_name) {
+                return Err(LayerGraphError::DuplicateLayer(node.layer_name.clone()));
+            }
+
+            for dependency in &node.dependencies {
+                if self.index_of(dependency).is_none() {
+                    return Err(LayerGraphError::UnknownDependency(
+                        node.layer_name.clone(),
+                        dependency.clone(),
+                    ));
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while order.len() < self.nodes.len() {
+            let next = (0..self.nodes.len()).find(|index| {
+                !order.contains(index)
+                    && self.nodes[*index].dependencies.iter().all(|dependency| {
+                        self.index_of(dependency)
+                            .map_or(false, |dependency| order.contains(&dependency))
+                    })
+            });
+
+            match next {
+                Some(index) => order.push(index),
+                None => {
+                    return Err(LayerGraphError::DependencyCycle(
+                        (0..self.nodes.len())
+                            .filter(|index| !order.contains(index))
+                            .map(|index| self.nodes[index].layer_name.clone())
+                            .collect(),
+                    ))
+                }
+            }
+        }
+
+        Ok(order)
+    }
+}
+
+impl<P: Platform, BM, E: Debug + Display> Default for LayerGraph<'_, P, BM, E> {
+    fn default() -> Self {
+        LayerGraph::new()
+    }
+}
+
+/// Returns the indices of all direct and transitive dependencies of a node, in topological order.
+fn transitive_dependencies(
+    index: usize,
+    dependencies: &[Vec<usize>],
+    order: &[usize],
+) -> Vec<usize> {
+    let mut result = HashSet::new();
+    let mut pending = dependencies[index].clone();
+
+    while let Some(dependency) = pending.pop() {
+        if result.insert(dependency) {
+            pending.extend(&dependencies[dependency]);
+        }
+    }
+
+    order
+        .iter()
+        .copied()
+        .filter(|index| result.contains(index))
+        .collect()
+}
+
+/// Outputs and environments of executed layers.
+pub struct LayerOutputs {
+    layers: Vec<(String, LayerOutput)>,
+}
+
+impl LayerOutputs {
+    fn collect(indices: &[usize], layer_names: &[String], outputs: &[Option<LayerOutput>]) -> Self {
+        LayerOutputs {
+            layers: indices
+                .iter()
+                .filter_map(|&index| {
+                    outputs[index]
+                        .clone()
+                        .map(|output| (layer_names[index].clone(), output))
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the output of the given layer, `None` if the layer is unknown or its output is not
+    /// of type `T`.
+    pub fn output<T: Any>(&self, layer_name: impl AsRef<str>) -> Option<&T> {
+        self.get(layer_name)
+            .and_then(|layer_output| layer_output.output.downcast_ref())
+    }
+
+    /// Returns the environment of the given layer as read from its layer directory.
+    pub fn layer_env(&self, layer_name: impl AsRef<str>) -> Option<&LayerEnv> {
+        self.get(layer_name)
+            .map(|layer_output| layer_output.layer_env.as_ref())
+    }
+
+    /// Applies the build environment of all layers to the given [`Env`], dependencies before
+    /// their dependents.
+    pub fn build_env(&self, env: &Env) -> Env {
+        self.layers
+            .iter()
+            .fold(env.clone(), |env, (_, layer_output)| {
+                layer_output.layer_env.apply(TargetLifecycle::Build, &env)
+            })
+    }
+
+    /// The names of all layers, dependencies before their dependents.
+    pub fn layer_names(&self) -> impl Iterator<Item = &str> {
+        self.layers
+            .iter()
+            .map(|(layer_name, _)| layer_name.as_str())
+    }
+
+    fn get(&self, layer_name: impl AsRef<str>) -> Option<&LayerOutput> {
+        self.layers
+            .iter()
+            .find(|(name, _)| name == layer_name.as_ref())
+            .map(|(_, layer_output)| layer_output)
+    }
+}
+
+/// A failed layer of a [`LayerGraph`]
+#[derive(Debug)]
+pub struct LayerFailure<E: Debug + Display> {
+    pub layer_name: String,
+    pub error: Error<E>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LayerGraphError<E: Debug + Display> {
+    #[error("Layer {0} is declared more than once")]
+    DuplicateLayer(String),
+
+    #[error("Layer {0} depends on unknown layer {1}")]
+    UnknownDependency(String, String),
+
+    #[error("Layers depend on each other: {}", .0.join(", "))]
+    DependencyCycle(Vec<String>),
+
+    #[error("{} layer(s) failed: {}", .failures.len(), format_failures(.failures))]
+    LayersFailed {
+        failures: Vec<LayerFailure<E>>,
+        /// Layers that were not executed because one of their dependencies failed.
+        skipped: Vec<String>,
+    },
+}
+
+fn format_failures<E: Debug + Display>(failures: &[LayerFailure<E>]) -> String {
+    failures
+        .iter()
+        .map(|failure| format!("{}: {}", failure.layer_name, failure.error))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::layer_content_metadata::LayerContentMetadata;
+    use crate::generic::{GenericMetadata, GenericPlatform};
+    use crate::test_support::build_context;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::{Barrier, Mutex};
+    use std::time::Duration;
+
+    /// Writes a `bin/<layer>` file and outputs the outputs of its dependencies plus its own name.
+    struct TestLayer {
+        name: &'static str,
+        dependency_outputs: Vec<String>,
+        barrier: Option<Arc<Barrier>>,
+        fail: bool,
+    }
+
+    impl TestLayer {
+        fn new(name: &'static str, dependencies: &LayerOutputs) -> Self {
+            TestLayer {
+                name,
+                dependency_outputs: dependencies
+                    .layer_names()
+                    .map(|layer_name| dependencies.output::<String>(layer_name).unwrap().clone())
+                    .collect(),
+                barrier: None,
+                fail: false,
+            }
+        }
+    }
+
+    impl Layer<GenericPlatform, GenericMetadata> for TestLayer {
+        type Metadata = GenericMetadata;
+        type Output = String;
+        type Error = String;
+
+        fn create(
+            &self,
+            layer_path: &Path,
+            _build_context: &BuildContext<GenericPlatform, GenericMetadata>,
+        ) -> Result<LayerContentMetadata<Self::Metadata>, Self::Error> {
+            if let Some(barrier) = &self.barrier {
+                barrier.wait();
+            }
+
+            if self.fail {
+                return Err(format!("{} failed", self.name));
+            }
+
+            fs::create_dir_all(layer_path.join("bin")).unwrap();
+            fs::write(layer_path.join("bin").join(self.name), "").unwrap();
+            Ok(LayerContentMetadata::default())
+        }
+
+        fn output(
+            &self,
+            _layer_path: &Path,
+            _layer_content_metadata: LayerContentMetadata<Self::Metadata>,
+        ) -> Result<Self::Output, Self::Error> {
+            let mut output = self.dependency_outputs.join("+");
+            if !output.is_empty() {
+                output.push('+');
+            }
+            output.push_str(self.name);
+            Ok(output)
+        }
+    }
+
+    #[test]
+    fn it_passes_outputs_and_env_to_dependents() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+        let env_paths = Mutex::new(None);
+
+        let outputs = LayerGraph::new()
+            .layer("app", &["bundler"], |dependencies| {
+                let env = dependencies.build_env(&Env::new());
+                *env_paths.lock().unwrap() = env.get("PATH");
+                Ok(TestLayer::new("app", dependencies))
+            })
+            .layer("bundler", &["ruby"], |dependencies| {
+                Ok(TestLayer::new("bundler", dependencies))
+            })
+            .layer("ruby", &[], |dependencies| {
+                Ok(TestLayer::new("ruby", dependencies))
+            })
+            .execute(&context)
+            .unwrap();
+
+        assert_eq!(
+            outputs.layer_names().collect::<Vec<_>>(),
+            vec!["ruby", "bundler", "app"]
+        );
+        assert_eq!(
+            outputs.output::<String>("app").map(String::as_str),
+            Some("ruby+ruby+bundler+app")
+        );
+        assert_eq!(outputs.output::<u32>("app"), None);
+
+        let path = env_paths.into_inner().unwrap().unwrap();
+        assert_eq!(
+            path,
+            std::env::join_paths(vec![
+                context.layer_path("bundler").join("bin"),
+                context.layer_path("ruby").join("bin"),
+            ])
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn it_executes_independent_layers_in_parallel() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        // Both layers wait for each other, this only completes if they run at the same time.
+        let barrier = Arc::new(Barrier::new(2));
+        let jdk_barrier = Arc::clone(&barrier);
+        let maven_barrier = Arc::clone(&barrier);
+
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let tmpdir = tmpdir;
+            let result = LayerGraph::new()
+                .layer("jdk", &[], |dependencies| {
+                    Ok(TestLayer {
+                        barrier: Some(jdk_barrier),
+                        ..TestLayer::new("jdk", dependencies)
+                    })
+                })
+                .layer("maven", &[], |dependencies| {
+                    Ok(TestLayer {
+                        barrier: Some(maven_barrier),
+                        ..TestLayer::new("maven", dependencies)
+                    })
+                })
+                .execute(&context)
+                .is_ok();
+
+            sender.send(result).unwrap();
+            drop(tmpdir);
+        });
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(true));
+    }
+
+    #[test]
+    fn it_aggregates_failures_and_skips_dependents() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let result = LayerGraph::new()
+            .layer("jdk", &[], |dependencies| {
+                Ok(TestLayer {
+                    fail: true,
+                    ..TestLayer::new("jdk", dependencies)
+                })
+            })
+            .layer("maven", &[], |_| {
+                Err::<TestLayer, _>(String::from("no maven version"))
+            })
+            .layer("node", &[], |dependencies| {
+                Ok(TestLayer::new("node", dependencies))
+            })
+            .layer("dependencies", &["jdk", "maven"], |dependencies| {
+                Ok(TestLayer::new("dependencies", dependencies))
+            })
+            .execute(&context);
+
+        match result {
+            Err(LayerGraphError::LayersFailed { failures, skipped }) => {
+                let mut failed: Vec<String> = failures
+                    .iter()
+                    .map(|failure| format!("{}: {}", failure.layer_name, failure.error))
+                    .collect();
+                failed.sort();
+
+                assert_eq!(
+                    failed,
+                    vec![
+                        "jdk: Buildpack error: jdk failed",
+                        "maven: Buildpack error: no maven version"
+                    ]
+                );
+                assert_eq!(skipped, vec!["dependencies"]);
+            }
+            _ => panic!("Expected layers to fail"),
+        }
+
+        assert!(context.layer_path("node").exists());
+        assert!(!context.layer_path("dependencies").exists());
+    }
+
+    #[test]
+    fn it_rejects_invalid_graphs() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let result = LayerGraph::new()
+            .layer("a", &["b"], |dependencies| {
+                Ok(TestLayer::new("a", dependencies))
+            })
+            .layer("b", &["a"], |dependencies| {
+                Ok(TestLayer::new("b", dependencies))
+            })
+            .layer("c", &[], |dependencies| {
+                Ok(TestLayer::new("c", dependencies))
+            })
+            .execute(&context);
+
+        assert!(
+            matches!(result, Err(LayerGraphError::DependencyCycle(layer_names)) if layer_names == vec!["a", "b"])
+        );
+
+        let result = LayerGraph::new()
+            .layer("a", &["z"], |dependencies| {
+                Ok(TestLayer::new("a", dependencies))
+            })
+            .execute(&context);
+
+        assert!(matches!(
+            result,
+            Err(LayerGraphError::UnknownDependency(_, _))
+        ));
+
+        let result = LayerGraph::new()
+            .layer("a", &[], |dependencies| {
+                Ok(TestLayer::new("a", dependencies))
+            })
+            .layer("a", &[], |dependencies| {
+                Ok(TestLayer::new("a", dependencies))
+            })
+            .execute(&context);
+
+        assert!(matches!(result, Err(LayerGraphError::DuplicateLayer(_))));
+        assert!(!context.layer_path("c").exists());
+    }
+}
@@ -1,5 +1,9 @@
 //! Layer Lifecycle controller
 //!
+//! New layers should implement [`crate::layer::Layer`] instead, which declares its types as
+//! associated types. Existing [`LayerLifecycle`] implementations can be used where a
+//! [`Layer`] is expected via [`LayerLifecycleAdapter`].
+//!
 //! This represents a controller that manages state associated with the lifecycle
 //! of a given layer (CRUD).
 //!
@@ -53,6 +57,10 @@
 //!
 //! - [`LayerLifecycle::recover_from_invalid_metadata`] (defaults to [`MetadataRecoveryStrategy::DeleteLayer`])
 //!
+//! Whether the layer is part of the build at all can depend on the lifecycle mode:
+//!
+//! - [`LayerLifecycle::include_in_mode`] (defaults to `true`)
+//!
 //! There are also callback hooks, unlike `create` and `update`
 //! these callback hooks do not present the ability to mutate
 //! the directory or metadata:
@@ -63,6 +71,12 @@
 //! - [`LayerLifecycle::on_create`]
 //! - [`LayerLifecycle::on_lifecycle_end`]
 //!
+//! Structured events with the layer name, metadata and timing are available via
+//! [`crate::layer::execute_layer_with_observer`].
+//!
+//! To find out what a build would do without modifying any layers, use
+//! [`plan_layer_lifecycle`] instead.
+//!
 //! ## Lifecycle State
 //!
 //! This section describes the state machine of the layer lifecycle controller so that
@@ -70,12 +84,15 @@
 //!
 //! ### None state
 //!
-//! On the first run there will be no data. The directory and toml file
-//! will be created and the user's defined `create` function will be executed.
+//! On the first run there will be no data. The directory will be created and
+//! the user's defined `create` function will be executed.
 //!
 //! In `create` the directory can be modified, and a representation of the toml
 //! file is returned via `LayerContentMetadata<LM>` struct.
 //!
+//! The toml file is only written once `create` succeeded. If `create` fails, the
+//! directory is removed and the layer does not exist.
+//!
 //! For all other runs `validate` will be called and the result determines
 //! the lifecycle state.
 //!
@@ -86,17 +103,29 @@
 //!
 //! ### `validate` returns [`ValidateResult::RecreateLayer`]
 //!
-//! The directory and toml file are deleted and then `create` is called similar
-//! to the `None` state above.
+//! The existing directory and toml file are moved to a backup and `create` is
+//! called similar to the `None` state above. If `create` fails, the backup is
+//! restored.
 //!
 //! ### `validate` returns [`ValidateResult::UpdateLayer`]
 //!
 //! Nothing is deleted. The existing path and toml representations are passed
-//! into the user's `update` function.
+//! into the user's `update` function. A backup with hard links to the files of the
+//! existing layer is kept and restored if `update` fails, instead of leaving a
+//! partially updated layer behind. Files have to be replaced rather than modified
+//! in place for the backup to stay intact.
 //!
 //! In `update`, the directory can be modified and a representation of the toml file
 //! is returned via `LayerContentMetadata<LM>` struct.
 //!
+//! ## Lifecycle modes
+//!
+//! The layer lifecycle takes the [`LifecycleMode`] of the build context into account. Layers
+//! excluded via [`LayerLifecycle::include_in_mode`] are deleted instead of created, for example a
+//! dev-dependencies layer restored from the cache of a `Dev` build in a `Package` build. The
+//! layer types of written metadata are adjusted with [`LayerContentMetadata::for_lifecycle_mode`],
+//! so no layer is made available at launch in a `CI` build.
+//!
 //! ## Metadata recovery
 //!
 //! Metadata is in the `<layer>.toml` file. TOML data in libcnb is represented
@@ -117,8 +146,16 @@
 //!   when data cannot be deserialized
 //! - [`MetadataRecoveryStrategy::ReplaceMetadata<M>`] will replace the old layer
 //!   metadata with the contents in `<M>`
+//!
+//! Instead of migrating raw TOML, layer metadata can be declared as a chain of
+//! [`VersionedMetadata`](crate::data::versioned_metadata::VersionedMetadata) types
+//! and wrapped in [`Versioned`](crate::data::versioned_metadata::Versioned). A version
+//! marker is then stored in `<layer>.toml` and metadata of previous versions is
+//! migrated when it is read, before `validate` is called. Only metadata without a
+//! migration path is handed to `recover_from_invalid_metadata`.
 
 use std::fmt::{Debug, Display};
+use std::marker::PhantomData;
 use std::path::Path;
 
 use serde::de::DeserializeOwned;
@@ -127,6 +164,9 @@ use serde::Serialize;
 use crate::build::BuildContext;
 use crate::data::layer_content_metadata::LayerContentMetadata;
 use crate::error::Error;
+use crate::layer::{execute_layer, plan_layer, Layer, LayerPlan};
+use crate::layer_normalization::LayerNormalization;
+use crate::mode::LifecycleMode;
 use crate::platform::Platform;
 use crate::toml_file::TomlFileError;
 
@@ -199,6 +239,27 @@ pub trait LayerLifecycle<P: Platform, BM, LM, O: Default, E: Debug + Display> {
         Ok(layer_content_metadata)
     }
 
+    /// Determines if the layer is part of the build in the given lifecycle mode
+    ///
+    /// When this returns `false`, [`execute_layer_lifecycle`] deletes the layer if it exists and
+    /// returns the default output without calling any other function. Use this for layers that
+    /// only make sense in some modes, like dev-dependencies that are only needed in
+    /// [`LifecycleMode::Dev`] and [`LifecycleMode::Test`].
+    ///
+    /// The default implementation includes the layer in all modes.
+    fn include_in_mode(
+        &self,
+        #[allow(unused_variables)] lifecycle_mode: Option<LifecycleMode>,
+    ) -> bool {
+        true
+    }
+
+    /// Normalizes the layer contents after [`create`](LayerLifecycle::create) and
+    /// [`update`](LayerLifecycle::update), see [`Layer::normalization`].
+    fn normalization(&self) -> Option<LayerNormalization> {
+        None
+    }
+
     fn layer_lifecycle_data(
         &self,
         #[allow(unused_variables)] layer_path: &Path,
@@ -214,35 +275,7 @@ pub trait LayerLifecycle<P: Platform, BM, LM, O: Default, E: Debug + Display> {
     fn on_lifecycle_end(&self) {}
 }
 
-/// The result of the recovery process for invalid layer metadata
-///
-/// See [`LayerLifecycle::recover_from_invalid_metadata`]
-pub enum MetadataRecoveryStrategy<M> {
-    /// Delete the layer entirely
-    DeleteLayer,
-    /// Replace the metadata
-    ReplaceMetadata(M),
-}
-
-/// The result of a layer validation
-///
-/// See [`LayerLifecycle::validate`]
-pub enum ValidateResult {
-    /// Keep the layer just as it is
-    ///
-    /// No [`LayerLifecycle`] functions will be called for this layer
-    KeepLayer,
-
-    /// Delete the layer and create a new one
-    ///
-    /// Only [`create`](LayerLifecycle::create) will be called
-    RecreateLayer,
-
-    /// Update the existing layer
-    ///
-    /// Only [`update`](LayerLifecycle::update) will be called
-    UpdateLayer,
-}
+pub use crate::layer::{MetadataRecoveryStrategy, ValidateResult};
 
 /// Layer lifecycle errors
 #[derive(thiserror::Error, Debug)]
@@ -262,11 +295,26 @@ pub enum LayerLifecycleError {
     #[error("Could not delete layer: {0}")]
     CannotDeleteLayer(std::io::Error),
 
+    #[error("Could not back up existing layer: {0}")]
+    CannotBackUpLayer(std::io::Error),
+
+    #[error("Could not restore layer from backup: {0}")]
+    CannotRestoreLayer(std::io::Error),
+
+    #[error("Could not normalize layer contents: {0}")]
+    CannotNormalizeLayer(std::io::Error),
+
+    #[error("Layer {0} is excluded in the lifecycle mode of the build but has no excluded output")]
+    MissingExcludedLayerOutput(String),
+
     #[error("Layer content metadata is missing after lifecycle")]
     CannotFindLayerMetadataAfterLifecycle(),
 
     #[error("Could not read layer content metadata: {0}")]
     CannotReadLayerContentMetadata(TomlFileError),
+
+    #[error("Could not read layer environment: {0}")]
+    CannotReadLayerEnv(std::io::Error),
 }
 
 /// Executes a layer lifecycle for a given layer name and [`BuildContext`]
@@ -282,72 +330,18 @@ pub fn execute_layer_lifecycle<
     layer_lifecycle: impl LayerLifecycle<P, BM, LM, O, E>,
     context: &BuildContext<P, BM>,
 ) -> Result<O, Error<E>> {
-    layer_lifecycle.on_lifecycle_start();
-
-    let layer_path = context.layer_path(&layer_name);
-    let layer_content_metadata = match context.read_layer_content_metadata(&layer_name) {
-        Ok(value) => value,
-        Err(_) => {
-            // If we cannot read the metadata due to a TOML file error, it's very likely that the
-            // metadata could not be parsed into `LM` due to field/type mismatch(es). Regardless
-            // of the actual error, we run the metadata recovery process here.
-            metadata_recovery(&layer_name, &layer_lifecycle, context)?
-        }
-    };
-
-    match layer_content_metadata {
-        Some(layer_content_metadata) => {
-            let handler =
-                match layer_lifecycle.validate(&layer_path, &layer_content_metadata, context) {
-                    ValidateResult::KeepLayer => handle_layer_keep,
-                    ValidateResult::RecreateLayer => handle_layer_recreate,
-                    ValidateResult::UpdateLayer => handle_layer_update,
-                };
-
-            handler(
-                &layer_name,
-                &layer_path,
-                layer_content_metadata,
-                &layer_lifecycle,
-                context,
-            )?;
-        }
-        None => handle_layer_create(&layer_name, &layer_path, &layer_lifecycle, context)?,
-    };
-
-    layer_lifecycle.on_lifecycle_end();
-
-    match context.read_layer_content_metadata(&layer_name) {
-        Err(toml_file_error) => Err(Error::LayerLifecycleError(
-            LayerLifecycleError::CannotReadLayerContentMetadata(toml_file_error),
-        )),
-        Ok(None) => Err(Error::LayerLifecycleError(
-            LayerLifecycleError::CannotFindLayerMetadataAfterLifecycle(),
-        )),
-        Ok(Some(metadata)) => layer_lifecycle
-            .layer_lifecycle_data(&layer_path, metadata)
-            .map_err(Error::BuildpackError),
-    }
+    execute_layer(
+        layer_name,
+        LayerLifecycleAdapter::new(layer_lifecycle),
+        context,
+    )
 }
 
-fn handle_layer_keep<
-    P: Platform,
-    BM,
-    LM: Serialize + DeserializeOwned,
-    O: Default,
-    E: Debug + Display,
->(
-    _layer_name: impl AsRef<str>,
-    _layer_path: &Path,
-    _layer_content_metadata: LayerContentMetadata<LM>,
-    layer_lifecycle: &impl LayerLifecycle<P, BM, LM, O, E>,
-    _context: &BuildContext<P, BM>,
-) -> Result<(), Error<E>> {
-    layer_lifecycle.on_keep();
-    Ok(())
-}
-
-fn handle_layer_create<
+/// Determines the action [`execute_layer_lifecycle`] would take for a layer, without executing it
+///
+/// See [`plan_layer`], layers excluded via [`LayerLifecycle::include_in_mode`] are planned to be
+/// deleted.
+pub fn plan_layer_lifecycle<
     P: Platform,
     BM,
     LM: Serialize + DeserializeOwned,
@@ -355,131 +349,194 @@ fn handle_layer_create<
     E: Debug + Display,
 >(
     layer_name: impl AsRef<str>,
-    layer_path: &Path,
-    layer_lifecycle: &impl LayerLifecycle<P, BM, LM, O, E>,
+    layer_lifecycle: impl LayerLifecycle<P, BM, LM, O, E>,
     context: &BuildContext<P, BM>,
-) -> Result<(), Error<E>> {
-    std::fs::create_dir_all(&layer_path)
-        .map_err(LayerLifecycleError::CannotCreateLayerDirectoryBeforeCreate)?;
-
-    layer_lifecycle.on_create();
+) -> Result<LayerPlan, Error<E>> {
+    plan_layer(
+        layer_name,
+        LayerLifecycleAdapter::new(layer_lifecycle),
+        context,
+    )
+}
 
-    let layer_content_metadata = layer_lifecycle
-        .create(layer_path, context)
-        .map_err(Error::BuildpackError)?;
+/// Adapts a [`LayerLifecycle`] implementation to the [`Layer`] trait
+///
+/// This allows existing [`LayerLifecycle`] implementations to be used with APIs that expect a
+/// [`Layer`] while they are migrated.
+///
+/// # Example
+/// ```ignore
+/// let output = execute_layer("opt", LayerLifecycleAdapter::new(OptLayerLifecycle {}), &context)?;
+/// ```
+pub struct LayerLifecycleAdapter<L, LM, O, E> {
+    layer_lifecycle: L,
+    _types: PhantomData<(LM, O, E)>,
+}
 
-    context
-        .write_layer_content_metadata(&layer_name, &layer_content_metadata)
-        .map_err(LayerLifecycleError::CannotWriteLayerMetadata)?;
-    Ok(())
+impl<L, LM, O, E> LayerLifecycleAdapter<L, LM, O, E> {
+    pub fn new(layer_lifecycle: L) -> Self {
+        LayerLifecycleAdapter {
+            layer_lifecycle,
+            _types: PhantomData,
+        }
+    }
 }
 
-fn handle_layer_recreate<
+impl<P, BM, LM, O, E, L> Layer<P, BM> for LayerLifecycleAdapter<L, LM, O, E>
+where
     P: Platform,
-    BM,
     LM: Serialize + DeserializeOwned,
     O: Default,
     E: Debug + Display,
->(
-    layer_name: impl AsRef<str>,
-    layer_path: &Path,
-    _layer_content_metadata: LayerContentMetadata<LM>,
-    layer_lifecycle: &impl LayerLifecycle<P, BM, LM, O, E>,
-    context: &BuildContext<P, BM>,
-) -> Result<(), Error<E>> {
-    context
-        .delete_layer(&layer_name)
-        .map_err(LayerLifecycleError::CannotDeleteLayer)?;
+    L: LayerLifecycle<P, BM, LM, O, E>,
+{
+    type Metadata = LM;
+    type Output = O;
+    type Error = E;
+
+    fn create(
+        &self,
+        layer_path: &Path,
+        build_context: &BuildContext<P, BM>,
+    ) -> Result<LayerContentMetadata<LM>, E> {
+        self.layer_lifecycle.create(layer_path, build_context)
+    }
 
-    std::fs::create_dir_all(&layer_path).map_err(LayerLifecycleError::CannotDeleteLayer)?;
+    fn output(
+        &self,
+        layer_path: &Path,
+        layer_content_metadata: LayerContentMetadata<LM>,
+    ) -> Result<O, E> {
+        self.layer_lifecycle
+            .layer_lifecycle_data(layer_path, layer_content_metadata)
+    }
 
-    layer_lifecycle.on_create();
+    fn recover_from_invalid_metadata(
+        &self,
+        layer_metadata: &toml::value::Table,
+        build_context: &BuildContext<P, BM>,
+    ) -> Result<MetadataRecoveryStrategy<LM>, E> {
+        self.layer_lifecycle
+            .recover_from_invalid_metadata(layer_metadata, build_context)
+    }
 
-    let content_metadata = layer_lifecycle
-        .create(layer_path, context)
-        .map_err(Error::BuildpackError)?;
+    fn validate(
+        &self,
+        layer_path: &Path,
+        layer_content_metadata: &LayerContentMetadata<LM>,
+        build_context: &BuildContext<P, BM>,
+    ) -> ValidateResult {
+        self.layer_lifecycle
+            .validate(layer_path, layer_content_metadata, build_context)
+    }
 
-    context
-        .write_layer_content_metadata(&layer_name, &content_metadata)
-        .map_err(|toml_error| {
-            Error::LayerLifecycleError(LayerLifecycleError::CannotWriteLayerMetadata(toml_error))
-        })
-}
+    fn update(
+        &self,
+        layer_path: &Path,
+        layer_content_metadata: LayerContentMetadata<LM>,
+        build_context: &BuildContext<P, BM>,
+    ) -> Result<LayerContentMetadata<LM>, E> {
+        self.layer_lifecycle
+            .update(layer_path, layer_content_metadata, build_context)
+    }
 
-fn handle_layer_update<
-    P: Platform,
-    BM,
-    LM: Serialize + DeserializeOwned,
-    O: Default,
-    E: Debug + Display,
->(
-    layer_name: impl AsRef<str>,
-    layer_path: &Path,
-    layer_content_metadata: LayerContentMetadata<LM>,
-    layer_lifecycle: &impl LayerLifecycle<P, BM, LM, O, E>,
-    context: &BuildContext<P, BM>,
-) -> Result<(), Error<E>> {
-    layer_lifecycle.on_update();
-
-    let content_metadata = layer_lifecycle
-        .update(layer_path, layer_content_metadata, context)
-        .map_err(Error::BuildpackError)?;
-
-    context
-        .write_layer_content_metadata(&layer_name, &content_metadata)
-        .map_err(|toml_error| {
-            Error::LayerLifecycleError(LayerLifecycleError::CannotWriteLayerMetadata(toml_error))
-        })
-}
+    fn normalization(&self) -> Option<LayerNormalization> {
+        self.layer_lifecycle.normalization()
+    }
 
-fn metadata_recovery<
-    P: Platform,
-    BM,
-    LM: Serialize + DeserializeOwned,
-    O: Default,
-    E: Debug + Display,
->(
-    layer_name: impl AsRef<str>,
-    layer_lifecycle: &impl LayerLifecycle<P, BM, LM, O, E>,
-    context: &BuildContext<P, BM>,
-) -> Result<Option<LayerContentMetadata<LM>>, Error<E>> {
-    // Read existing layer content metadata as TOML table, handling potential errors and
-    // non-existent metadata so subsequent steps don't have to deal with either.
-    let mut layer_content_metadata = {
-        let maybe_layer_content_metadata = context
-            .read_layer_content_metadata(&layer_name)
-            .map_err(|toml_file_error| {
-                Error::LayerLifecycleError(LayerLifecycleError::CannotNotReadUntypedLayerMetadata(
-                    toml_file_error,
-                ))
-            })?;
-
-        match maybe_layer_content_metadata {
-            None => return Ok(None),
-            Some(value) => value,
-        }
-    };
+    fn include_in_mode(&self, lifecycle_mode: Option<LifecycleMode>) -> bool {
+        self.layer_lifecycle.include_in_mode(lifecycle_mode)
+    }
+
+    fn excluded_output(&self) -> Option<O> {
+        Some(O::default())
+    }
 
-    let metadata_recovery_strategy = layer_lifecycle
-        .recover_from_invalid_metadata(&layer_content_metadata.metadata, context)
-        .map_err(Error::BuildpackError)?;
+    fn on_lifecycle_start(&self) {
+        self.layer_lifecycle.on_lifecycle_start();
+    }
 
-    match metadata_recovery_strategy {
-        MetadataRecoveryStrategy::DeleteLayer => {
-            context
-                .delete_layer(&layer_name)
-                .map_err(LayerLifecycleError::CannotDeleteLayer)?;
+    fn on_keep(&self) {
+        self.layer_lifecycle.on_keep();
+    }
 
-            Ok(None)
-        }
-        MetadataRecoveryStrategy::ReplaceMetadata(replacement_metadata) => {
-            let updated_metadata = layer_content_metadata.metadata(replacement_metadata);
+    fn on_update(&self) {
+        self.layer_lifecycle.on_update();
+    }
 
-            context
-                .write_layer_content_metadata(&layer_name, &updated_metadata)
-                .map_err(LayerLifecycleError::CannotReplaceLayerMetadata)?;
+    fn on_create(&self) {
+        self.layer_lifecycle.on_create();
+    }
 
-            Ok(Some(updated_metadata))
+    fn on_lifecycle_end(&self) {
+        self.layer_lifecycle.on_lifecycle_end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::{GenericMetadata, GenericPlatform};
+    use crate::layer::{LayerAction, LayerActionReason};
+    use crate::test_support::build_context;
+
+    struct DevDependenciesLayerLifecycle;
+
+    impl LayerLifecycle<GenericPlatform, GenericMetadata, GenericMetadata, (), std::io::Error>
+        for DevDependenciesLayerLifecycle
+    {
+        fn create(
+            &self,
+            _layer_path: &Path,
+            _build_context: &BuildContext<GenericPlatform, GenericMetadata>,
+        ) -> Result<LayerContentMetadata<GenericMetadata>, std::io::Error> {
+            Ok(LayerContentMetadata::default())
+        }
+
+        fn include_in_mode(&self, lifecycle_mode: Option<LifecycleMode>) -> bool {
+            lifecycle_mode.map_or(true, LifecycleMode::includes_dev_dependencies)
         }
     }
+
+    #[test]
+    fn plan_excluded_layer_lifecycle() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let mut context = build_context(&tmpdir);
+
+        let layer_plan =
+            plan_layer_lifecycle("dev", DevDependenciesLayerLifecycle, &context).unwrap();
+        assert_eq!(layer_plan.action, LayerAction::Create);
+
+        context.lifecycle_mode = Some(LifecycleMode::Package);
+        let layer_plan =
+            plan_layer_lifecycle("dev", DevDependenciesLayerLifecycle, &context).unwrap();
+        assert_eq!(layer_plan.action, LayerAction::Delete);
+        assert_eq!(
+            layer_plan.to_string(),
+            "dev: delete (excluded in Package mode)"
+        );
+    }
+
+    #[test]
+    fn adapter_deletes_excluded_layer() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let mut context = build_context(&tmpdir);
+        let adapter = || LayerLifecycleAdapter::new(DevDependenciesLayerLifecycle);
+
+        execute_layer("dev", adapter(), &context).unwrap();
+        assert!(context.layer_path("dev").exists());
+        assert!(context.layer_content_metadata_path("dev").exists());
+
+        context.lifecycle_mode = Some(LifecycleMode::Package);
+        let layer_plan = plan_layer("dev", adapter(), &context).unwrap();
+        assert_eq!(layer_plan.action, LayerAction::Delete);
+        assert_eq!(
+            layer_plan.reason,
+            LayerActionReason::ExcludedInMode(Some(LifecycleMode::Package))
+        );
+
+        execute_layer("dev", adapter(), &context).unwrap();
+        assert!(!context.layer_path("dev").exists());
+        assert!(!context.layer_content_metadata_path("dev").exists());
+    }
 }
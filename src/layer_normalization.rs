@@ -0,0 +1,346 @@
+//! Normalization of layer contents for reproducible layers
+//!
+//! Files in layers usually keep the modification times of when they were downloaded or built and
+//! permissions that depend on the umask of the build. Both end up in the exported image, which
+//! causes layer digests to change between builds with identical inputs.
+//!
+//! Layers opt into normalization by returning a [`LayerNormalization`] from
+//! [`Layer::normalization`](crate::layer::Layer::normalization). It is applied after
+//! [`create`](crate::layer::Layer::create) and [`update`](crate::layer::Layer::update) returned
+//! successfully. Layers are built at their final location, so normalization runs there as well,
+//! before the `<layer>.toml` is written:
+//!
+//! 1. All [`LayerFileFilter`]s are applied, in the order they were added. Filters can remove files
+//!    that are not deterministic or rewrite their contents.
+//! 2. Permissions are normalized: directories and executable files get `0755`, all other files
+//!    get `0644`. Symbolic links are left as is.
+//! 3. The modification times of all files, directories and symbolic links are set to the same
+//!    timestamp, see [`LayerNormalization::mtime`].
+//!
+//! File ownership is not changed, the lifecycle sets it when exporting the layer.
+//!
+//! ```
+//! use libcnb::layer_normalization::{remove_python_bytecode, LayerNormalization};
+//!
+//! let normalization = LayerNormalization::new()
+//!     .filter(remove_python_bytecode())
+//!     .filter(|path: &std::path::Path, _relative_path: &std::path::Path| {
+//!         // Rewrite or inspect `path` here...
+//!         Ok(libcnb::layer_normalization::FileAction::Keep)
+//!     });
+//! ```
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use filetime::FileTime;
+use walkdir::WalkDir;
+
+use crate::platform::PlatformEnv;
+
+/// The name of the environment variable holding the timestamp used for normalized files.
+pub const SOURCE_DATE_EPOCH_ENV_NAME: &str = "SOURCE_DATE_EPOCH";
+
+/// The timestamp used when `SOURCE_DATE_EPOCH` is not set: 1980-01-01T00:00:01Z, the same the
+/// lifecycle uses for files it writes into the image.
+pub const DEFAULT_SOURCE_DATE_EPOCH: u64 = 315_532_801;
+
+/// Determines what happens to a file in a layer during normalization.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FileAction {
+    /// Keep the file. Its permissions and modification time are normalized afterwards.
+    Keep,
+
+    /// Remove the file. Removing a directory removes all of its contents.
+    Remove,
+}
+
+/// A filter applied to every file and directory in a layer during normalization.
+///
+/// Filters receive the absolute path and the path relative to the layer directory. Besides
+/// deciding whether the file is kept, filters may rewrite its contents, for example to remove
+/// embedded timestamps.
+///
+/// Implemented for all `Fn(&Path, &Path) -> Result<FileAction, std::io::Error>` closures.
+pub trait LayerFileFilter {
+    fn filter(&self, path: &Path, relative_path: &Path) -> Result<FileAction, std::io::Error>;
+}
+
+impl<F: Fn(&Path, &Path) -> Result<FileAction, std::io::Error>> LayerFileFilter for F {
+    fn filter(&self, path: &Path, relative_path: &Path) -> Result<FileAction, std::io::Error> {
+        self(path, relative_path)
+    }
+}
+
+/// Removes all files and directories whose path relative to the layer matches the given glob
+/// pattern.
+///
+/// # Panics
+/// If the pattern is invalid.
+pub fn remove_matching(pattern: &str) -> impl LayerFileFilter {
+    let pattern = glob::Pattern::new(pattern).expect("Invalid glob pattern");
+
+    move |_: &Path, relative_path: &Path| {
+        Ok(if pattern.matches_path(relative_path) {
+            FileAction::Remove
+        } else {
+            FileAction::Keep
+        })
+    }
+}
+
+/// Removes Python bytecode caches (`__pycache__` directories and `*.pyc` files).
+///
+/// Bytecode files embed the modification time of their source file and are recreated by Python
+/// when missing.
+pub fn remove_python_bytecode() -> impl LayerFileFilter {
+    |path: &Path, _: &Path| {
+        let is_bytecode = path.file_name().map_or(false, |name| name == "__pycache__")
+            || path
+                .extension()
+                .map_or(false, |extension| extension == "pyc");
+
+        Ok(if is_bytecode {
+            FileAction::Remove
+        } else {
+            FileAction::Keep
+        })
+    }
+}
+
+/// Configuration of the normalization of a layer, see the [module level documentation](self).
+#[derive(Default)]
+pub struct LayerNormalization {
+    mtime: Option<SystemTime>,
+    filters: Vec<Box<dyn LayerFileFilter>>,
+}
+
+impl LayerNormalization {
+    pub fn new() -> Self {
+        LayerNormalization::default()
+    }
+
+    /// Sets the modification time of all files in the layer.
+    ///
+    /// When not set, the timestamp (in seconds since the Unix epoch) is read from the
+    /// `SOURCE_DATE_EPOCH` environment variable. The platform environment takes precedence over
+    /// the process environment. When the variable is not set or invalid,
+    /// [`DEFAULT_SOURCE_DATE_EPOCH`] is used.
+    #[must_use]
+    pub fn mtime(mut self, mtime: SystemTime) -> Self {
+        self.mtime = Some(mtime);
+        self
+    }
+
+    /// Adds a filter that is applied to all files and directories in the layer.
+    #[must_use]
+    pub fn filter(mut self, filter: impl LayerFileFilter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Normalizes the contents of the layer at `layer_path`.
+    pub fn normalize(
+        &self,
+        layer_path: impl AsRef<Path>,
+        platform_env: &PlatformEnv,
+    ) -> Result<(), std::io::Error> {
+        let layer_path = layer_path.as_ref();
+
+        self.apply_filters(layer_path)?;
+
+        let mtime = FileTime::from_system_time(
+            self.mtime
+                .unwrap_or_else(|| resolve_source_date_epoch(Some(platform_env))),
+        );
+
+        // Children are visited before their parent, otherwise normalizing a directory's
+        // contents could change its modification time again.
+        for entry in WalkDir::new(layer_path).contents_first(true) {
+            let entry = entry?;
+            let file_type = entry.file_type();
+
+            if file_type.is_symlink() {
+                filetime::set_symlink_file_times(entry.path(), mtime, mtime)?;
+                continue;
+            }
+
+            normalize_permissions(entry.path(), file_type.is_dir())?;
+            filetime::set_file_times(entry.path(), mtime, mtime)?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_filters(&self, layer_path: &Path) -> Result<(), std::io::Error> {
+        if self.filters.is_empty() {
+            return Ok(());
+        }
+
+        let mut entries = WalkDir::new(layer_path).min_depth(1).into_iter();
+        while let Some(entry) = entries.next() {
+            let entry = entry?;
+            let relative_path = entry
+                .path()
+                .strip_prefix(layer_path)
+                .unwrap_or_else(|_| entry.path());
+
+            let mut action = FileAction::Keep;
+            for filter in &self.filters {
+                action = filter.filter(entry.path(), relative_path)?;
+                if action == FileAction::Remove {
+                    break;
+                }
+            }
+
+            if action == FileAction::Remove {
+                if entry.file_type().is_dir() {
+                    fs::remove_dir_all(entry.path())?;
+                    entries.skip_current_dir();
+                } else {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads `SOURCE_DATE_EPOCH` from the platform environment, if given, then from the process
+/// environment, falling back to [`DEFAULT_SOURCE_DATE_EPOCH`].
+pub(crate) fn resolve_source_date_epoch(platform_env: Option<&PlatformEnv>) -> SystemTime {
+    let seconds = platform_env
+        .and_then(|platform_env| platform_env.var(SOURCE_DATE_EPOCH_ENV_NAME).ok())
+        .or_else(|| std::env::var(SOURCE_DATE_EPOCH_ENV_NAME).ok())
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_SOURCE_DATE_EPOCH);
+
+    SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+#[cfg(unix)]
+fn normalize_permissions(path: &Path, is_dir: bool) -> Result<(), std::io::Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let permissions = fs::metadata(path)?.permissions();
+    let mode = if is_dir || permissions.mode() & 0o111 != 0 {
+        0o755
+    } else {
+        0o644
+    };
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn normalize_permissions(_path: &Path, _is_dir: bool) -> Result<(), std::io::Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    fn modified(path: &Path) -> SystemTime {
+        fs::symlink_metadata(path).unwrap().modified().unwrap()
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn it_normalizes_mtimes_and_permissions() {
+        let tmpdir = tempdir().unwrap();
+        let layer_path = tmpdir.path().join("layer");
+        fs::create_dir_all(layer_path.join("bin")).unwrap();
+        fs::write(layer_path.join("bin/ruby"), "").unwrap();
+        fs::write(layer_path.join("README"), "").unwrap();
+        fs::set_permissions(
+            layer_path.join("bin/ruby"),
+            fs::Permissions::from_mode(0o700),
+        )
+        .unwrap();
+        fs::set_permissions(layer_path.join("README"), fs::Permissions::from_mode(0o600)).unwrap();
+        std::os::unix::fs::symlink("bin/ruby", layer_path.join("ruby")).unwrap();
+
+        let platform_env = PlatformEnv::from_path(tmpdir.path().join("platform")).unwrap();
+        LayerNormalization::new()
+            .mtime(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000))
+            .normalize(&layer_path, &platform_env)
+            .unwrap();
+
+        for path in ["", "bin", "bin/ruby", "README", "ruby"] {
+            assert_eq!(
+                modified(&layer_path.join(path)),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000)
+            );
+        }
+
+        assert_eq!(mode(&layer_path.join("bin")), 0o755);
+        assert_eq!(mode(&layer_path.join("bin/ruby")), 0o755);
+        assert_eq!(mode(&layer_path.join("README")), 0o644);
+    }
+
+    #[test]
+    fn it_reads_source_date_epoch_from_platform_env() {
+        let tmpdir = tempdir().unwrap();
+        let layer_path = tmpdir.path().join("layer");
+        fs::create_dir_all(&layer_path).unwrap();
+        fs::create_dir_all(tmpdir.path().join("platform/env")).unwrap();
+        fs::write(
+            tmpdir.path().join("platform/env/SOURCE_DATE_EPOCH"),
+            "1600000000",
+        )
+        .unwrap();
+
+        let platform_env = PlatformEnv::from_path(tmpdir.path().join("platform")).unwrap();
+        LayerNormalization::new()
+            .normalize(&layer_path, &platform_env)
+            .unwrap();
+
+        assert_eq!(
+            modified(&layer_path),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+        );
+    }
+
+    #[test]
+    fn it_applies_filters() {
+        let tmpdir = tempdir().unwrap();
+        let layer_path = tmpdir.path().join("layer");
+        fs::create_dir_all(layer_path.join("lib/__pycache__")).unwrap();
+        fs::write(layer_path.join("lib/__pycache__/app.cpython-39.pyc"), "").unwrap();
+        fs::write(layer_path.join("lib/app.py"), "").unwrap();
+        fs::write(layer_path.join("lib/legacy.pyc"), "").unwrap();
+        fs::write(layer_path.join("build.log"), "").unwrap();
+        fs::write(layer_path.join("spec.gemspec"), "date = 2021-12-01").unwrap();
+
+        let platform_env = PlatformEnv::from_path(tmpdir.path().join("platform")).unwrap();
+        LayerNormalization::new()
+            .filter(remove_python_bytecode())
+            .filter(remove_matching("*.log"))
+            .filter(|path: &Path, relative_path: &Path| {
+                if relative_path == Path::new("spec.gemspec") {
+                    fs::write(path, "date = 1980-01-01")?;
+                }
+
+                Ok(FileAction::Keep)
+            })
+            .normalize(&layer_path, &platform_env)
+            .unwrap();
+
+        assert!(!layer_path.join("lib/__pycache__").exists());
+        assert!(!layer_path.join("lib/legacy.pyc").exists());
+        assert!(!layer_path.join("build.log").exists());
+        assert!(layer_path.join("lib/app.py").exists());
+        assert_eq!(
+            fs::read_to_string(layer_path.join("spec.gemspec")).unwrap(),
+            "date = 1980-01-01"
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! Detection and removal of stale layers
+//!
+//! Layers restored from a previous build stay in the layers directory until they are deleted
+//! explicitly. When the current build no longer produces a layer (a feature was removed, a layer
+//! was renamed), it would otherwise be restored and exported forever.
+//!
+//! libcnb records which layers were touched during the build phase in
+//! [`BuildContext::layer_usage`](crate::BuildContext::layer_usage). A layer counts as touched when
+//! it is executed with [`execute_layer`](crate::layer::execute_layer) (or any API built on top of
+//! it), when its metadata is written with
+//! [`BuildContext::write_layer_content_metadata`](crate::BuildContext::write_layer_content_metadata)
+//! or when it is deleted with [`BuildContext::delete_layer`](crate::BuildContext::delete_layer).
+//!
+//! After the build function returned successfully, all other layers are handled according to the
+//! configured [`StaleLayerPolicy`]:
+//!
+//! ```
+//! use libcnb::layer_pruning::StaleLayerPolicy;
+//! use libcnb::{GenericBuildContext, Result};
+//!
+//! fn build(context: GenericBuildContext) -> Result<(), std::io::Error> {
+//!     context.layer_usage.set_stale_layer_policy(StaleLayerPolicy::Delete);
+//!
+//!     // The contents of this layer are managed manually, keep it even when it is not touched.
+//!     context.layer_usage.exclude("downloads");
+//!
+//!     // ...
+//!     Ok(())
+//! }
+//! ```
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Files in the layers directory that are not layer content metadata.
+const RESERVED_TOML_FILES: [&str; 3] = ["launch.toml", "build.toml", "store.toml"];
+
+/// Determines what happens to stale layers at the end of the build phase.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StaleLayerPolicy {
+    /// Leave stale layers untouched.
+    Keep,
+
+    /// Print a warning for each stale layer, but leave it untouched.
+    Report,
+
+    /// Delete stale layers.
+    Delete,
+}
+
+impl Default for StaleLayerPolicy {
+    fn default() -> Self {
+        StaleLayerPolicy::Keep
+    }
+}
+
+/// Tracks which layers were touched during the build phase.
+///
+/// Clones share the same state, all methods take `&self` so layers can be tracked from any thread
+/// that has access to the [`BuildContext`](crate::BuildContext).
+#[derive(Debug, Clone, Default)]
+pub struct LayerUsage {
+    state: Arc<Mutex<LayerUsageState>>,
+}
+
+#[derive(Debug, Default)]
+struct LayerUsageState {
+    touched: BTreeSet<String>,
+    excluded: BTreeSet<String>,
+    policy: StaleLayerPolicy,
+    warned: BTreeSet<String>,
+}
+
+impl LayerUsage {
+    /// Marks the given layer as touched by the current build.
+    pub fn touch(&self, layer_name: impl AsRef<str>) {
+        self.state()
+            .touched
+            .insert(String::from(layer_name.as_ref()));
+    }
+
+    /// Excludes the given layer from pruning, regardless of whether it is touched.
+    pub fn exclude(&self, layer_name: impl AsRef<str>) {
+        self.state()
+            .excluded
+            .insert(String::from(layer_name.as_ref()));
+    }
+
+    /// Records that a warning about the given layer was printed, returns `false` if one had
+    /// already been printed during this build.
+    pub(crate) fn mark_warned(&self, layer_name: impl AsRef<str>) -> bool {
+        self.state()
+            .warned
+            .insert(String::from(layer_name.as_ref()))
+    }
+
+    pub fn set_stale_layer_policy(&self, policy: StaleLayerPolicy) {
+        self.state().policy = policy;
+    }
+
+    pub fn stale_layer_policy(&self) -> StaleLayerPolicy {
+        self.state().policy
+    }
+
+    /// Names of all layers touched so far, in alphabetical order.
+    pub fn touched_layers(&self) -> Vec<String> {
+        self.state().touched.iter().cloned().collect()
+    }
+
+    /// Names of all layers in `layers_dir` that were neither touched nor excluded, in
+    /// alphabetical order.
+    ///
+    /// A layer is found if either its directory or its content metadata file exists. Hidden
+    /// entries, like the backups of layers being executed, are ignored.
+    pub fn stale_layers(
+        &self,
+        layers_dir: impl AsRef<Path>,
+    ) -> Result<Vec<String>, std::io::Error> {
+        let mut layer_names = BTreeSet::new();
+
+        for entry in fs::read_dir(layers_dir.as_ref())? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let file_name = match file_name.to_str() {
+                Some(file_name) if !file_name.starts_with('.') => file_name,
+                _ => continue,
+            };
+
+            if entry.file_type()?.is_dir() {
+                layer_names.insert(String::from(file_name));
+            } else if let Some(layer_name) = file_name.strip_suffix(".toml") {
+                if !RESERVED_TOML_FILES.contains(&file_name) {
+                    layer_names.insert(String::from(layer_name));
+                }
+            }
+        }
+
+        let state = self.state();
+        Ok(layer_names
+            .into_iter()
+            .filter(|layer_name| {
+                !state.touched.contains(layer_name) && !state.excluded.contains(layer_name)
+            })
+            .collect())
+    }
+
+    /// Applies the configured [`StaleLayerPolicy`] to all stale layers in `layers_dir`.
+    ///
+    /// Returns the names of the stale layers.
+    pub fn prune_stale_layers(
+        &self,
+        layers_dir: impl AsRef<Path>,
+    ) -> Result<Vec<String>, std::io::Error> {
+        let layers_dir = layers_dir.as_ref();
+
+        let policy = self.stale_layer_policy();
+        if policy == StaleLayerPolicy::Keep {
+            return Ok(vec![]);
+        }
+
+        let stale_layers = self.stale_layers(layers_dir)?;
+        for layer_name in &stale_layers {
+            match policy {
+                StaleLayerPolicy::Keep => {}
+                StaleLayerPolicy::Report => {
+                    eprintln!(
+                        "Warning: Layer '{}' was not used by this build and will be kept",
+                        layer_name
+                    );
+                }
+                StaleLayerPolicy::Delete => {
+                    delete_file_or_dir(&layers_dir.join(format!("{}.toml", layer_name)))?;
+                    delete_file_or_dir(&layers_dir.join(layer_name))?;
+                }
+            }
+        }
+
+        Ok(stale_layers)
+    }
+
+    fn state(&self) -> MutexGuard<'_, LayerUsageState> {
+        // The state cannot be left inconsistent by a panicking thread, all updates are atomic.
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+fn delete_file_or_dir(path: &Path) -> Result<(), std::io::Error> {
+    let result = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+
+    match result {
+        Err(io_error) if io_error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::layer_content_metadata::LayerContentMetadata;
+    use crate::test_support::build_context;
+    use tempfile::tempdir;
+
+    fn create_layer(layers_dir: &Path, layer_name: &str) {
+        fs::create_dir_all(layers_dir.join(layer_name)).unwrap();
+        fs::write(layers_dir.join(format!("{}.toml", layer_name)), "").unwrap();
+    }
+
+    #[test]
+    fn it_finds_untouched_layers() {
+        let tmpdir = tempdir().unwrap();
+        let context = build_context(&tmpdir);
+        let layers_dir = &context.layers_dir;
+
+        create_layer(layers_dir, "ruby");
+        create_layer(layers_dir, "bundler");
+        create_layer(layers_dir, "node");
+        fs::create_dir(layers_dir.join("gems")).unwrap();
+        fs::write(layers_dir.join("yarn.toml"), "").unwrap();
+        fs::write(layers_dir.join("launch.toml"), "").unwrap();
+        fs::create_dir(layers_dir.join(".ruby.backup")).unwrap();
+
+        context.layer_usage.touch("ruby");
+        context
+            .write_layer_content_metadata("bundler", &LayerContentMetadata::default())
+            .unwrap();
+        context.layer_usage.exclude("gems");
+
+        assert_eq!(
+            context.layer_usage.touched_layers(),
+            vec!["bundler", "ruby"]
+        );
+        assert_eq!(
+            context.layer_usage.stale_layers(layers_dir).unwrap(),
+            vec!["node", "yarn"]
+        );
+    }
+
+    #[test]
+    fn it_marks_layers_warned_once() {
+        let layer_usage = LayerUsage::default();
+
+        assert!(layer_usage.mark_warned("ruby"));
+        assert!(!layer_usage.clone().mark_warned("ruby"));
+        assert!(layer_usage.mark_warned("bundler"));
+    }
+
+    #[test]
+    fn it_keeps_stale_layers_by_default() {
+        let tmpdir = tempdir().unwrap();
+        let context = build_context(&tmpdir);
+        create_layer(&context.layers_dir, "node");
+
+        assert_eq!(
+            context
+                .layer_usage
+                .prune_stale_layers(&context.layers_dir)
+                .unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(context.layer_exists("node"));
+
+        context
+            .layer_usage
+            .set_stale_layer_policy(StaleLayerPolicy::Report);
+        assert_eq!(
+            context
+                .layer_usage
+                .prune_stale_layers(&context.layers_dir)
+                .unwrap(),
+            vec!["node"]
+        );
+        assert!(context.layer_exists("node"));
+    }
+
+    #[test]
+    fn it_deletes_stale_layers() {
+        let tmpdir = tempdir().unwrap();
+        let context = build_context(&tmpdir);
+        let layer_usage = context.layer_usage.clone();
+        create_layer(&context.layers_dir, "ruby");
+        create_layer(&context.layers_dir, "node");
+        create_layer(&context.layers_dir, "old");
+
+        layer_usage.set_stale_layer_policy(StaleLayerPolicy::Delete);
+        context.layer_usage.touch("ruby");
+        context.delete_layer("old").unwrap();
+
+        assert_eq!(
+            layer_usage.prune_stale_layers(&context.layers_dir).unwrap(),
+            vec!["node"]
+        );
+        assert!(context.layer_exists("ruby"));
+        assert!(!context.layer_path("node").exists());
+        assert!(!context.layer_content_metadata_path("node").exists());
+    }
+}
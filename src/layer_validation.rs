@@ -0,0 +1,700 @@
+//! Reusable validation strategies for layers
+//!
+//! Most implementations of [`Layer::validate`](crate::layer::Layer::validate) compare a value of
+//! the current build, like a checksum of some app files or the stack id, to a value recorded in
+//! the layer metadata. The validators in this module implement common comparisons of that kind.
+//! They record their values in [`CacheKeys`], which layers embed in their metadata, and can be
+//! combined with [`LayerValidator::and`] and [`LayerValidator::or`].
+//!
+//! ```
+//! use std::path::Path;
+//! use libcnb::{BuildContext, Platform};
+//! use libcnb::data::layer_content_metadata::LayerContentMetadata;
+//! use libcnb::layer::{Layer, ValidateResult};
+//! use libcnb::layer_validation::{CacheKeys, FileChecksum, LayerValidator, StackId};
+//! use serde::{Deserialize, Serialize};
+//!
+//! struct BundlerLayer;
+//!
+//! #[derive(Deserialize, Serialize)]
+//! struct BundlerLayerMetadata {
+//!     cache_keys: CacheKeys,
+//! }
+//!
+//! impl BundlerLayer {
+//!     fn validator(&self) -> impl LayerValidator {
+//!         FileChecksum::new("Gemfile.lock")
+//!             .on_change(ValidateResult::UpdateLayer)
+//!             .and(StackId)
+//!     }
+//! }
+//!
+//! impl<P: Platform, BM> Layer<P, BM> for BundlerLayer {
+//!     type Metadata = BundlerLayerMetadata;
+//!     type Output = ();
+//!     type Error = anyhow::Error;
+//!
+//!     fn create(
+//!         &self,
+//!         layer_path: &Path,
+//!         build_context: &BuildContext<P, BM>,
+//!     ) -> Result<LayerContentMetadata<Self::Metadata>, Self::Error> {
+//!         // bundle install ...
+//!
+//!         Ok(LayerContentMetadata::default()
+//!             .cache(true)
+//!             .metadata(BundlerLayerMetadata {
+//!                 cache_keys: self.validator().cache_keys(build_context)?,
+//!             }))
+//!     }
+//!
+//!     fn update(
+//!         &self,
+//!         _layer_path: &Path,
+//!         mut layer_content_metadata: LayerContentMetadata<Self::Metadata>,
+//!         build_context: &BuildContext<P, BM>,
+//!     ) -> Result<LayerContentMetadata<Self::Metadata>, Self::Error> {
+//!         // bundle install ...
+//!
+//!         let cache_keys = self
+//!             .validator()
+//!             .updated_cache_keys(&layer_content_metadata.metadata.cache_keys, build_context)?;
+//!
+//!         Ok(layer_content_metadata.metadata(BundlerLayerMetadata { cache_keys }))
+//!     }
+//!
+//!     fn validate(
+//!         &self,
+//!         _layer_path: &Path,
+//!         layer_content_metadata: &LayerContentMetadata<Self::Metadata>,
+//!         build_context: &BuildContext<P, BM>,
+//!     ) -> ValidateResult {
+//!         self.validator()
+//!             .validate(&layer_content_metadata.metadata.cache_keys, build_context)
+//!     }
+//!
+//!     fn output(
+//!         &self,
+//!         _layer_path: &Path,
+//!         _layer_content_metadata: LayerContentMetadata<Self::Metadata>,
+//!     ) -> Result<Self::Output, Self::Error> {
+//!         Ok(())
+//!     }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::build::BuildContext;
+use crate::layer::ValidateResult;
+use crate::platform::Platform;
+
+/// Values recorded by [`LayerValidator`]s, to be stored in the layer metadata.
+///
+/// Using the same structure across buildpacks keeps cache keys consistent.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Eq, PartialEq)]
+pub struct CacheKeys {
+    /// Checksums recorded by [`FileChecksum`], keyed by the file patterns.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>,
+
+    /// Dependency versions recorded by [`DependencyVersion`], keyed by the dependency name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub versions: BTreeMap<String, Version>,
+
+    /// The stack id recorded by [`StackId`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack_id: Option<String>,
+
+    /// The buildpack version recorded by [`BuildpackVersion`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buildpack_version: Option<Version>,
+
+    /// The creation time in seconds since the Unix epoch, recorded by [`MaxAge`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+}
+
+/// A reusable strategy to validate a layer based on its [`CacheKeys`].
+pub trait LayerValidator {
+    /// Determines how the layer will be processed, see [`Layer::validate`](crate::layer::Layer::validate).
+    fn validate<P: Platform, BM>(
+        &self,
+        cache_keys: &CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> ValidateResult;
+
+    /// Records the values of the current build this validator compares against.
+    ///
+    /// Fails if the values cannot be determined, for example when files cannot be read.
+    fn record<P: Platform, BM>(
+        &self,
+        cache_keys: &mut CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> Result<(), LayerValidationError>;
+
+    /// Returns new [`CacheKeys`] with the values of the current build recorded.
+    fn cache_keys<P: Platform, BM>(
+        &self,
+        context: &BuildContext<P, BM>,
+    ) -> Result<CacheKeys, LayerValidationError> {
+        self.updated_cache_keys(&CacheKeys::default(), context)
+    }
+
+    /// Returns a copy of the given [`CacheKeys`] with the values of the current build recorded.
+    ///
+    /// Use this when updating a layer, so values that are only recorded once, like the creation
+    /// time of [`MaxAge`], are kept.
+    fn updated_cache_keys<P: Platform, BM>(
+        &self,
+        previous: &CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> Result<CacheKeys, LayerValidationError> {
+        let mut cache_keys = previous.clone();
+        self.record(&mut cache_keys, context)?;
+        Ok(cache_keys)
+    }
+
+    /// Combines two validators, the layer is only kept if both validators keep it.
+    ///
+    /// Otherwise, the more destructive result wins: [`ValidateResult::RecreateLayer`] takes
+    /// precedence over [`ValidateResult::UpdateLayer`].
+    fn and<V: LayerValidator>(self, other: V) -> And<Self, V>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    /// Combines two validators, the layer is kept if any of the validators keeps it.
+    ///
+    /// Otherwise, the less destructive result wins: [`ValidateResult::UpdateLayer`] takes
+    /// precedence over [`ValidateResult::RecreateLayer`].
+    fn or<V: LayerValidator>(self, other: V) -> Or<Self, V>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LayerValidationError {
+    #[error("Invalid file pattern: {0}")]
+    InvalidPattern(#[from] glob::PatternError),
+
+    #[error("Could not list files: {0}")]
+    CannotListFiles(#[from] glob::GlobError),
+
+    #[error("Could not read file {0}: {1}")]
+    CannotReadFile(PathBuf, std::io::Error),
+}
+
+/// See [`LayerValidator::and`]
+pub struct And<A, B>(A, B);
+
+impl<A: LayerValidator, B: LayerValidator> LayerValidator for And<A, B> {
+    fn validate<P: Platform, BM>(
+        &self,
+        cache_keys: &CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> ValidateResult {
+        let a = self.0.validate(cache_keys, context);
+        let b = self.1.validate(cache_keys, context);
+
+        if destructiveness(a) >= destructiveness(b) {
+            a
+        } else {
+            b
+        }
+    }
+
+    fn record<P: Platform, BM>(
+        &self,
+        cache_keys: &mut CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> Result<(), LayerValidationError> {
+        self.0.record(cache_keys, context)?;
+        self.1.record(cache_keys, context)
+    }
+}
+
+/// See [`LayerValidator::or`]
+pub struct Or<A, B>(A, B);
+
+impl<A: LayerValidator, B: LayerValidator> LayerValidator for Or<A, B> {
+    fn validate<P: Platform, BM>(
+        &self,
+        cache_keys: &CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> ValidateResult {
+        let a = self.0.validate(cache_keys, context);
+        let b = self.1.validate(cache_keys, context);
+
+        if destructiveness(a) <= destructiveness(b) {
+            a
+        } else {
+            b
+        }
+    }
+
+    fn record<P: Platform, BM>(
+        &self,
+        cache_keys: &mut CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> Result<(), LayerValidationError> {
+        self.0.record(cache_keys, context)?;
+        self.1.record(cache_keys, context)
+    }
+}
+
+fn destructiveness(validate_result: ValidateResult) -> u8 {
+    match validate_result {
+        ValidateResult::KeepLayer => 0,
+        ValidateResult::UpdateLayer => 1,
+        ValidateResult::RecreateLayer => 2,
+    }
+}
+
+/// Validates a checksum of the contents of app files.
+///
+/// Patterns are glob patterns relative to the app directory. All matching files are hashed in a
+/// stable order, along with their paths. Files that cannot be read count as a change.
+///
+/// # Examples
+/// ```
+/// use libcnb::layer::ValidateResult;
+/// use libcnb::layer_validation::FileChecksum;
+///
+/// let validator = FileChecksum::new("package.json")
+///     .pattern("package-lock.json")
+///     .on_change(ValidateResult::UpdateLayer);
+/// ```
+pub struct FileChecksum {
+    patterns: Vec<String>,
+    on_change: ValidateResult,
+}
+
+impl FileChecksum {
+    pub fn new(pattern: impl Into<String>) -> Self {
+        FileChecksum {
+            patterns: vec![pattern.into()],
+            on_change: ValidateResult::RecreateLayer,
+        }
+    }
+
+    /// Adds another pattern, the checksum covers the files of all patterns.
+    #[must_use]
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    /// Sets the result for a changed checksum, defaults to [`ValidateResult::RecreateLayer`].
+    #[must_use]
+    pub fn on_change(mut self, on_change: ValidateResult) -> Self {
+        self.on_change = on_change;
+        self
+    }
+
+    fn key(&self) -> String {
+        self.patterns.join(",")
+    }
+
+    fn checksum<P: Platform, BM>(
+        &self,
+        context: &BuildContext<P, BM>,
+    ) -> Result<String, LayerValidationError> {
+        let mut paths = Vec::new();
+        for pattern in &self.patterns {
+            let absolute_pattern =
+                PathBuf::from(glob::Pattern::escape(&context.app_dir.to_string_lossy()))
+                    .join(pattern);
+
+            for path in glob::glob(&absolute_pattern.to_string_lossy())? {
+                let path = path?;
+                if path.is_file() {
+                    paths.push(path);
+                }
+            }
+        }
+
+        paths.sort();
+        paths.dedup();
+
+        let mut hasher = Sha256::new();
+        for path in paths {
+            let contents = fs::read(&path)
+                .map_err(|io_error| LayerValidationError::CannotReadFile(path.clone(), io_error))?;
+
+            let relative_path = path.strip_prefix(&context.app_dir).unwrap_or(&path);
+            hasher.update(relative_path.to_string_lossy().as_bytes());
+            hasher.update([0]);
+            hasher.update(&contents);
+        }
+
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+impl LayerValidator for FileChecksum {
+    fn validate<P: Platform, BM>(
+        &self,
+        cache_keys: &CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> ValidateResult {
+        match (
+            cache_keys.checksums.get(&self.key()),
+            self.checksum(context),
+        ) {
+            (Some(recorded), Ok(current)) if recorded == &current => ValidateResult::KeepLayer,
+            _ => self.on_change,
+        }
+    }
+
+    fn record<P: Platform, BM>(
+        &self,
+        cache_keys: &mut CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> Result<(), LayerValidationError> {
+        cache_keys
+            .checksums
+            .insert(self.key(), self.checksum(context)?);
+        Ok(())
+    }
+}
+
+/// Validates the version of a dependency installed into the layer.
+///
+/// By default, the recorded version has to match the version exactly. With
+/// [`DependencyVersion::compatible_with`], any recorded version that matches the requirement is
+/// accepted.
+///
+/// # Examples
+/// ```
+/// use libcnb::layer_validation::DependencyVersion;
+/// use semver::{Version, VersionReq};
+///
+/// let validator = DependencyVersion::new("node", Version::new(16, 13, 1))
+///     .compatible_with(VersionReq::parse("^16.13").unwrap());
+/// ```
+pub struct DependencyVersion {
+    name: String,
+    version: Version,
+    requirement: Option<VersionReq>,
+    on_change: ValidateResult,
+}
+
+impl DependencyVersion {
+    pub fn new(name: impl Into<String>, version: Version) -> Self {
+        DependencyVersion {
+            name: name.into(),
+            version,
+            requirement: None,
+            on_change: ValidateResult::RecreateLayer,
+        }
+    }
+
+    /// Accepts any recorded version that matches the given requirement.
+    #[must_use]
+    pub fn compatible_with(mut self, requirement: VersionReq) -> Self {
+        self.requirement = Some(requirement);
+        self
+    }
+
+    /// Sets the result for a mismatching version, defaults to [`ValidateResult::RecreateLayer`].
+    #[must_use]
+    pub fn on_change(mut self, on_change: ValidateResult) -> Self {
+        self.on_change = on_change;
+        self
+    }
+}
+
+impl LayerValidator for DependencyVersion {
+    fn validate<P: Platform, BM>(
+        &self,
+        cache_keys: &CacheKeys,
+        _context: &BuildContext<P, BM>,
+    ) -> ValidateResult {
+        let matches = cache_keys
+            .versions
+            .get(&self.name)
+            .map_or(false, |recorded| match &self.requirement {
+                Some(requirement) => requirement.matches(recorded),
+                None => recorded == &self.version,
+            });
+
+        if matches {
+            ValidateResult::KeepLayer
+        } else {
+            self.on_change
+        }
+    }
+
+    fn record<P: Platform, BM>(
+        &self,
+        cache_keys: &mut CacheKeys,
+        _context: &BuildContext<P, BM>,
+    ) -> Result<(), LayerValidationError> {
+        cache_keys
+            .versions
+            .insert(self.name.clone(), self.version.clone());
+        Ok(())
+    }
+}
+
+/// Recreates the layer when the stack id changed.
+pub struct StackId;
+
+impl LayerValidator for StackId {
+    fn validate<P: Platform, BM>(
+        &self,
+        cache_keys: &CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> ValidateResult {
+        if cache_keys.stack_id.as_ref() == Some(&context.stack_id) {
+            ValidateResult::KeepLayer
+        } else {
+            ValidateResult::RecreateLayer
+        }
+    }
+
+    fn record<P: Platform, BM>(
+        &self,
+        cache_keys: &mut CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> Result<(), LayerValidationError> {
+        cache_keys.stack_id = Some(context.stack_id.clone());
+        Ok(())
+    }
+}
+
+/// Recreates the layer when the version of the buildpack changed.
+pub struct BuildpackVersion;
+
+impl LayerValidator for BuildpackVersion {
+    fn validate<P: Platform, BM>(
+        &self,
+        cache_keys: &CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> ValidateResult {
+        if cache_keys.buildpack_version.as_ref()
+            == Some(&context.buildpack_descriptor.buildpack.version)
+        {
+            ValidateResult::KeepLayer
+        } else {
+            ValidateResult::RecreateLayer
+        }
+    }
+
+    fn record<P: Platform, BM>(
+        &self,
+        cache_keys: &mut CacheKeys,
+        context: &BuildContext<P, BM>,
+    ) -> Result<(), LayerValidationError> {
+        cache_keys.buildpack_version = Some(context.buildpack_descriptor.buildpack.version.clone());
+        Ok(())
+    }
+}
+
+/// Recreates the layer once it is older than the given duration.
+///
+/// The creation time is only recorded if none is recorded yet. Updating a layer does not reset
+/// its age as long as the cache keys are recorded with
+/// [`LayerValidator::updated_cache_keys`], [`LayerValidator::cache_keys`] starts over.
+pub struct MaxAge(pub Duration);
+
+impl LayerValidator for MaxAge {
+    fn validate<P: Platform, BM>(
+        &self,
+        cache_keys: &CacheKeys,
+        _context: &BuildContext<P, BM>,
+    ) -> ValidateResult {
+        let expired = cache_keys.created_at.map_or(true, |created_at| {
+            unix_time().saturating_sub(created_at) > self.0.as_secs()
+        });
+
+        if expired {
+            ValidateResult::RecreateLayer
+        } else {
+            ValidateResult::KeepLayer
+        }
+    }
+
+    fn record<P: Platform, BM>(
+        &self,
+        cache_keys: &mut CacheKeys,
+        _context: &BuildContext<P, BM>,
+    ) -> Result<(), LayerValidationError> {
+        cache_keys.created_at.get_or_insert_with(unix_time);
+        Ok(())
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::build_context;
+
+    #[test]
+    fn file_checksum_detects_changes() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+        fs::create_dir_all(context.app_dir.join("config")).unwrap();
+        fs::write(context.app_dir.join("Gemfile.lock"), "rack (2.2.3)").unwrap();
+        fs::write(context.app_dir.join("config/a.yml"), "a").unwrap();
+
+        let validator = FileChecksum::new("Gemfile.lock")
+            .pattern("config/*.yml")
+            .on_change(ValidateResult::UpdateLayer);
+        let cache_keys = validator.cache_keys(&context).unwrap();
+
+        assert!(cache_keys
+            .checksums
+            .contains_key("Gemfile.lock,config/*.yml"));
+        assert_eq!(
+            validator.validate(&cache_keys, &context),
+            ValidateResult::KeepLayer
+        );
+
+        fs::write(context.app_dir.join("config/b.yml"), "b").unwrap();
+        assert_eq!(
+            validator.validate(&cache_keys, &context),
+            ValidateResult::UpdateLayer
+        );
+    }
+
+    #[test]
+    fn file_checksum_escapes_app_dir() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let mut context = build_context(&tmpdir);
+        context.app_dir = tmpdir.path().join("app[1]");
+        fs::create_dir_all(&context.app_dir).unwrap();
+        fs::write(context.app_dir.join("Gemfile.lock"), "rack (2.2.3)").unwrap();
+
+        let validator = FileChecksum::new("Gemfile.lock");
+        let cache_keys = validator.cache_keys(&context).unwrap();
+
+        fs::write(context.app_dir.join("Gemfile.lock"), "rack (2.2.4)").unwrap();
+        assert_eq!(
+            validator.validate(&cache_keys, &context),
+            ValidateResult::RecreateLayer
+        );
+    }
+
+    #[test]
+    fn dependency_version_matches_exactly_or_by_requirement() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let cache_keys = DependencyVersion::new("node", Version::new(16, 13, 0))
+            .cache_keys(&context)
+            .unwrap();
+
+        let newer = DependencyVersion::new("node", Version::new(16, 13, 1));
+        assert_eq!(
+            newer.validate(&cache_keys, &context),
+            ValidateResult::RecreateLayer
+        );
+
+        let compatible = newer.compatible_with(VersionReq::parse("^16.13").unwrap());
+        assert_eq!(
+            compatible.validate(&cache_keys, &context),
+            ValidateResult::KeepLayer
+        );
+    }
+
+    #[test]
+    fn stack_id_and_buildpack_version() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let mut context = build_context(&tmpdir);
+
+        let validator = StackId.and(BuildpackVersion);
+        let cache_keys = validator.cache_keys(&context).unwrap();
+        assert_eq!(cache_keys.stack_id.as_deref(), Some("heroku-20"));
+        assert_eq!(cache_keys.buildpack_version, Some(Version::new(0, 0, 1)));
+        assert_eq!(
+            validator.validate(&cache_keys, &context),
+            ValidateResult::KeepLayer
+        );
+
+        context.stack_id = String::from("heroku-22");
+        assert_eq!(
+            validator.validate(&cache_keys, &context),
+            ValidateResult::RecreateLayer
+        );
+    }
+
+    #[test]
+    fn max_age_keeps_creation_time() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let mut cache_keys = CacheKeys {
+            created_at: Some(unix_time() - 7200),
+            ..CacheKeys::default()
+        };
+        MaxAge(Duration::from_secs(3600))
+            .record(&mut cache_keys, &context)
+            .unwrap();
+
+        assert_eq!(
+            MaxAge(Duration::from_secs(3600)).validate(&cache_keys, &context),
+            ValidateResult::RecreateLayer
+        );
+        assert_eq!(
+            MaxAge(Duration::from_secs(86400)).validate(&cache_keys, &context),
+            ValidateResult::KeepLayer
+        );
+
+        let updated_cache_keys = MaxAge(Duration::from_secs(3600))
+            .updated_cache_keys(&cache_keys, &context)
+            .unwrap();
+        assert_eq!(updated_cache_keys.created_at, cache_keys.created_at);
+    }
+
+    #[test]
+    fn combinators_pick_results_by_destructiveness() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = build_context(&tmpdir);
+
+        let cache_keys = CacheKeys {
+            stack_id: Some(String::from("heroku-18")),
+            ..CacheKeys::default()
+        };
+
+        let outdated = DependencyVersion::new("node", Version::new(16, 13, 0))
+            .on_change(ValidateResult::UpdateLayer);
+
+        assert_eq!(
+            StackId
+                .or(MaxAge(Duration::from_secs(60)))
+                .validate(&cache_keys, &context),
+            ValidateResult::RecreateLayer
+        );
+        assert_eq!(
+            StackId.and(outdated).validate(&cache_keys, &context),
+            ValidateResult::RecreateLayer
+        );
+
+        let outdated = DependencyVersion::new("node", Version::new(16, 13, 0))
+            .on_change(ValidateResult::UpdateLayer);
+        assert_eq!(
+            StackId.or(outdated).validate(&cache_keys, &context),
+            ValidateResult::UpdateLayer
+        );
+    }
+}
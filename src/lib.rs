@@ -36,11 +36,18 @@
 #![allow(clippy::unnecessary_wraps)]
 
 pub mod data;
+pub mod layer;
 pub mod layer_env;
+pub mod layer_events;
+pub mod layer_graph;
 
 pub mod layer_lifecycle;
-
-use crate::data::buildpack::BuildpackApi;
+pub mod layer_normalization;
+pub mod layer_pruning;
+pub mod layer_validation;
+pub mod stack;
+pub mod transfer;
+pub use crate::data::buildpack::BuildpackApi;
 pub use build::BuildContext;
 pub use detect::DetectContext;
 pub use detect::DetectOutcome;
@@ -58,6 +65,7 @@ pub use platform::*;
 pub use publish::PublishContext;
 pub use runtime::cnb_runtime;
 pub use runtime::cnb_runtime_all;
+pub use target::Target;
 pub use test::TestContext;
 pub use test::TestOutcome;
 pub use test::TestResult;
@@ -79,8 +87,10 @@ mod mode;
 mod platform;
 mod publish;
 mod runtime;
+mod target;
 mod test;
+#[cfg(test)]
+mod test_support;
 mod toml_file;
-mod transfer;
 
 const LIBCNB_SUPPORTED_BUILDPACK_API: BuildpackApi = BuildpackApi { major: 0, minor: 6 };
@@ -2,8 +2,12 @@ use std::borrow::Borrow;
 use std::ffi::OsStr;
 use std::fmt::Display;
 use std::str::FromStr;
+
 use anyhow::anyhow;
 
+use crate::platform::PlatformEnv;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum LifecycleMode {
     Dev,
     CI,
@@ -11,6 +15,18 @@ pub enum LifecycleMode {
     Package,
 }
 
+impl LifecycleMode {
+    /// Whether development-only contents, such as dev-dependencies, belong in the build.
+    pub fn includes_dev_dependencies(self) -> bool {
+        matches!(self, LifecycleMode::Dev | LifecycleMode::Test)
+    }
+
+    /// Whether layers can be made available at launch. CI builds never launch the resulting image.
+    pub fn allows_launch_layers(self) -> bool {
+        !matches!(self, LifecycleMode::CI)
+    }
+}
+
 impl FromStr for LifecycleMode {
     type Err = anyhow::Error;
 
@@ -27,8 +43,8 @@ impl FromStr for LifecycleMode {
 
 impl Display for LifecycleMode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let name = self.as_ref();
-        write!(f, "{:?}", name)
+        let name: &OsStr = self.as_ref();
+        write!(f, "{}", name.to_string_lossy())
     }
 }
 
@@ -53,23 +69,46 @@ const MODE_ENV_NAME: &str = "CNB_LIFECYCLE_MODE";
 /// ```
 /// use libcnb::{get_lifecycle_mode, LifecycleMode};
 /// match get_lifecycle_mode() {
-///     Ok(mode) => println!("Current mode is {}", mode),
-///     Err(e) => eprintln!("Configured mode is invalid. {}", e)
+///     Some(mode) => println!("Current mode is {}", mode),
+///     None => eprintln!("No valid mode is configured"),
 /// }
 /// ```
-/// Returns an error only if the environment variable is set, but set to an invalid mode.
+/// Returns `None` if the environment variable is not set or set to an invalid mode.
 pub fn get_lifecycle_mode() -> Option<LifecycleMode> {
-    match std::env::var_os(MODE_ENV_NAME) {
-        Some(os_str) => {
-            match LifecycleMode::from_str(os_str.to_str().unwrap()) {
-                Ok(mode) => Some(mode),
-                Err(e) => {
-                    eprintln!("Warning: invalid lifecycle mode is set but ignored: {:?}.\n{}", os_str.to_str().unwrap(), e);
-                    None
-                }
-            }
+    std::env::var_os(MODE_ENV_NAME)
+        .and_then(|os_str| parse_lifecycle_mode(&os_str.to_string_lossy()))
+}
+
+/// Resolves the LifecycleMode for a buildpack execution
+///
+/// The platform environment (`<platform>/env/CNB_LIFECYCLE_MODE`) takes precedence over the
+/// process environment. Buildpack authors usually do not need to call this and instead use the
+/// `lifecycle_mode` field of the context structs.
+///
+/// # Example
+///
+/// ```no_run
+/// use libcnb::{resolve_lifecycle_mode, PlatformEnv};
+/// let platform_env = PlatformEnv::from_path("/platform").unwrap();
+/// let mode = resolve_lifecycle_mode(&platform_env);
+/// ```
+pub fn resolve_lifecycle_mode(platform_env: &PlatformEnv) -> Option<LifecycleMode> {
+    match platform_env.var(MODE_ENV_NAME) {
+        Ok(value) => parse_lifecycle_mode(&value),
+        Err(_) => get_lifecycle_mode(),
+    }
+}
+
+fn parse_lifecycle_mode(value: &str) -> Option<LifecycleMode> {
+    match LifecycleMode::from_str(value.trim()) {
+        Ok(mode) => Some(mode),
+        Err(e) => {
+            eprintln!(
+                "Warning: invalid lifecycle mode is set but ignored: {:?}.\n{}",
+                value, e
+            );
+            None
         }
-        _ => None
     }
 }
 
@@ -80,6 +119,56 @@ pub fn set_lifecycle_mode(mode: &str) -> Result<LifecycleMode, anyhow::Error> {
             std::env::set_var(MODE_ENV_NAME, mode.borrow());
             Ok(mode)
         }
-        Err(e) => Err(anyhow!(e))
+        Err(e) => Err(anyhow!(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::{Mutex, PoisonError};
+
+    #[test]
+    fn display_does_not_quote() {
+        assert_eq!(LifecycleMode::Dev.to_string(), "Dev");
+        assert_eq!(LifecycleMode::CI.to_string(), "CI");
+    }
+
+    /// Serializes the tests that modify `CNB_LIFECYCLE_MODE` in the process environment.
+    static PROCESS_ENV: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn resolve_prefers_platform_env() {
+        let _guard = PROCESS_ENV.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let env_dir = tmpdir.path().join("env");
+        fs::create_dir(&env_dir).unwrap();
+        let platform_env = PlatformEnv::from_path(tmpdir.path()).unwrap();
+
+        std::env::set_var(MODE_ENV_NAME, "dev");
+        let without_platform_value = resolve_lifecycle_mode(&platform_env);
+
+        fs::write(env_dir.join(MODE_ENV_NAME), "ci\n").unwrap();
+        let platform_env = PlatformEnv::from_path(tmpdir.path()).unwrap();
+        let with_platform_value = resolve_lifecycle_mode(&platform_env);
+        std::env::remove_var(MODE_ENV_NAME);
+
+        assert_eq!(without_platform_value, Some(LifecycleMode::Dev));
+        assert_eq!(with_platform_value, Some(LifecycleMode::CI));
+    }
+
+    #[test]
+    fn resolve_ignores_invalid_platform_env() {
+        let _guard = PROCESS_ENV.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let env_dir = tmpdir.path().join("env");
+        fs::create_dir(&env_dir).unwrap();
+        fs::write(env_dir.join(MODE_ENV_NAME), "production").unwrap();
+
+        let platform_env = PlatformEnv::from_path(tmpdir.path()).unwrap();
+        assert_eq!(resolve_lifecycle_mode(&platform_env), None);
     }
 }
@@ -1,12 +1,16 @@
 use std::path::PathBuf;
 
-use crate::{data::buildpack::BuildpackToml, platform::Platform};
+use crate::{
+    data::buildpack::BuildpackToml, mode::LifecycleMode, platform::Platform, target::Target,
+};
 
 /// Context for a buildpack's test phase execution.
 pub struct PublishContext<P: Platform, BM> {
     pub app_dir: PathBuf,
     pub buildpack_dir: PathBuf,
     pub stack_id: String,
+    pub target: Target,
     pub platform: P,
+    pub lifecycle_mode: Option<LifecycleMode>,
     pub buildpack_descriptor: BuildpackToml<BM>,
 }
@@ -3,17 +3,23 @@ use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::process;
 use std::process::exit;
+use std::sync::Mutex;
 
 use serde::de::DeserializeOwned;
 
 use crate::build::BuildContext;
 use crate::data::buildpack::BuildpackToml;
+use crate::data::project::ProjectToml;
 use crate::detect::{DetectContext, DetectOutcome};
 use crate::error::{Error, ErrorHandler};
+use crate::layer_pruning::LayerUsage;
+use crate::mode::resolve_lifecycle_mode;
 use crate::platform::Platform;
 use crate::publish::PublishContext;
+use crate::target::{read_target, Target};
 use crate::test::write_test_results;
 use crate::toml_file::{read_toml_file, write_toml_file};
+use crate::transfer::{DownloadCache, DOWNLOAD_CACHE_LAYER_NAME};
 use crate::{Result, TestContext, TestOutcome, LIBCNB_SUPPORTED_BUILDPACK_API};
 use std::fmt::{Debug, Display};
 
@@ -221,19 +227,26 @@ fn cnb_runtime_detect<
 
     let app_dir = env::current_dir().map_err(Error::CannotDetermineAppDirectory)?;
 
-    let stack_id: String = env::var("CNB_STACK_ID").map_err(Error::CannotDetermineStackId)?;
+    let (stack_id, target) = read_stack_id_and_target(|key| env::var(key).ok())?;
 
     let platform =
         P::from_path(&args.platform_dir_path).map_err(Error::CannotCreatePlatformFromPath)?;
 
+    let lifecycle_mode = resolve_lifecycle_mode(platform.env());
+
     let build_plan_path = args.build_plan_path;
 
+    let project_descriptor = read_project_toml(&app_dir)?;
+
     let detect_context = DetectContext {
         app_dir,
         stack_id,
+        target,
         platform,
+        lifecycle_mode,
         buildpack_dir: read_buildpack_dir()?,
         buildpack_descriptor: read_buildpack_toml()?,
+        project_descriptor,
     };
 
     match detect_fn(detect_context)? {
@@ -259,25 +272,51 @@ fn cnb_runtime_build<
 
     let app_dir = env::current_dir().map_err(Error::CannotDetermineAppDirectory)?;
 
-    let stack_id: String = env::var("CNB_STACK_ID").map_err(Error::CannotDetermineStackId)?;
+    let (stack_id, target) = read_stack_id_and_target(|key| env::var(key).ok())?;
 
     let platform =
         P::from_path(&args.platform_dir_path).map_err(Error::CannotCreatePlatformFromPath)?;
 
+    let lifecycle_mode = resolve_lifecycle_mode(platform.env());
+
     let buildpack_plan =
         read_toml_file(&args.buildpack_plan_path).map_err(Error::CannotReadBuildpackPlan)?;
 
+    let project_descriptor = read_project_toml(&app_dir)?;
+
+    let download_cache = DownloadCache::new(&layers_dir);
+
     let context = BuildContext {
         layers_dir,
         app_dir,
         stack_id,
+        target,
         platform,
+        lifecycle_mode,
         buildpack_plan,
         buildpack_dir: read_buildpack_dir()?,
         buildpack_descriptor: read_buildpack_toml()?,
+        project_descriptor,
+        layer_usage: LayerUsage::default(),
+        download_cache: download_cache.clone(),
+        transfer_client: Mutex::default(),
     };
 
-    build_fn(context)
+    let layer_usage = context.layer_usage.clone();
+    let layers_dir = context.layers_dir.clone();
+
+    // The download cache is managed by libcnb and never stale.
+    layer_usage.exclude(DOWNLOAD_CACHE_LAYER_NAME);
+
+    build_fn(context)?;
+
+    download_cache.report();
+
+    layer_usage
+        .prune_stale_layers(&layers_dir)
+        .map_err(Error::CannotPruneStaleLayers)?;
+
+    Ok(())
 }
 
 fn cnb_runtime_test<
@@ -294,16 +333,20 @@ fn cnb_runtime_test<
 
     let app_dir = env::current_dir().map_err(Error::CannotDetermineAppDirectory)?;
 
-    let stack_id: String = env::var("CNB_STACK_ID").map_err(Error::CannotDetermineStackId)?;
+    let (stack_id, target) = read_stack_id_and_target(|key| env::var(key).ok())?;
 
     let platform =
         P::from_path(&args.platform_dir_path).map_err(Error::CannotCreatePlatformFromPath)?;
 
+    let lifecycle_mode = resolve_lifecycle_mode(platform.env());
+
     let test_context = TestContext {
         layers_dir,
         app_dir,
         stack_id,
+        target,
         platform,
+        lifecycle_mode,
         buildpack_dir: read_buildpack_dir()?,
         buildpack_descriptor: read_buildpack_toml()?,
     };
@@ -335,15 +378,19 @@ fn cnb_runtime_publish<
 
     let app_dir = env::current_dir().map_err(Error::CannotDetermineAppDirectory)?;
 
-    let stack_id: String = env::var("CNB_STACK_ID").map_err(Error::CannotDetermineStackId)?;
+    let (stack_id, target) = read_stack_id_and_target(|key| env::var(key).ok())?;
 
     let platform =
         P::from_path(&args.platform_dir_path).map_err(Error::CannotCreatePlatformFromPath)?;
 
+    let lifecycle_mode = resolve_lifecycle_mode(platform.env());
+
     let context = PublishContext {
         app_dir,
         stack_id,
+        target,
         platform,
+        lifecycle_mode,
         buildpack_dir: read_buildpack_dir()?,
         buildpack_descriptor: read_buildpack_toml()?,
     };
@@ -431,6 +478,23 @@ fn parse_publish_args_or_exit() -> PublishArgs {
     }
 }
 
+/// Reads the stack id and target of the current build. Platforms without stacks only provide a
+/// target and the stack id is derived from it, platforms without targets only provide a stack id
+/// and the target is derived from it.
+fn read_stack_id_and_target<E: Display + Debug>(
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<(String, Target), E> {
+    match (var("CNB_STACK_ID"), read_target(&var)) {
+        (Some(stack_id), Some(target)) => Ok((stack_id, target)),
+        (Some(stack_id), None) => {
+            let target = Target::from_stack_id(&stack_id);
+            Ok((stack_id, target))
+        }
+        (None, Some(target)) => Ok((target.stack_id(), target)),
+        (None, None) => Err(Error::CannotDetermineTarget),
+    }
+}
+
 fn read_buildpack_dir<E: Display + Debug>() -> Result<PathBuf, E> {
     env::var("CNB_BUILDPACK_DIR")
         .map_err(Error::CannotDetermineBuildpackDirectory)
@@ -443,3 +507,67 @@ fn read_buildpack_toml<BM: DeserializeOwned, E: Display + Debug>() -> Result<Bui
             .map_err(Error::CannotReadBuildpackDescriptor)
     })
 }
+
+fn read_project_toml<E: Display + Debug>(app_dir: &Path) -> Result<Option<ProjectToml>, E> {
+    let project_toml_path = app_dir.join("project.toml");
+
+    if project_toml_path.exists() {
+        read_toml_file(project_toml_path)
+            .map(Some)
+            .map_err(Error::CannotReadProjectDescriptor)
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn read(vars: &[(&str, &str)]) -> Result<(String, Target), std::io::Error> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        read_stack_id_and_target(|key| vars.get(key).map(|value| String::from(*value)))
+    }
+
+    #[test]
+    fn reads_target_without_stack_id() {
+        let (stack_id, target) =
+            read(&[("CNB_TARGET_OS", "linux"), ("CNB_TARGET_ARCH", "arm64")]).unwrap();
+
+        assert_eq!(stack_id, "linux/arm64");
+        assert_eq!(target.os, "linux");
+        assert_eq!(target.arch, "arm64");
+        assert_eq!(target.distro_name, None);
+    }
+
+    #[test]
+    fn reads_stack_id_without_target() {
+        let (stack_id, target) = read(&[("CNB_STACK_ID", "heroku-20")]).unwrap();
+
+        assert_eq!(stack_id, "heroku-20");
+        assert_eq!(target, Target::from_stack_id("heroku-20"));
+    }
+
+    #[test]
+    fn prefers_platform_provided_stack_id_and_target() {
+        let (stack_id, target) = read(&[
+            ("CNB_STACK_ID", "heroku-22"),
+            ("CNB_TARGET_OS", "linux"),
+            ("CNB_TARGET_ARCH", "amd64"),
+        ])
+        .unwrap();
+
+        assert_eq!(stack_id, "heroku-22");
+        assert_eq!(target.distro_version, None);
+    }
+
+    #[test]
+    fn fails_without_stack_id_and_target() {
+        assert!(matches!(read(&[]), Err(Error::CannotDetermineTarget)));
+        assert!(matches!(
+            read(&[("CNB_TARGET_OS", "linux")]),
+            Err(Error::CannotDetermineTarget)
+        ));
+    }
+}
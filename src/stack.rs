@@ -0,0 +1,407 @@
+//! Stack and mixin compatibility checks
+//!
+//! A buildpack declares the stacks it supports in the `[[stacks]]` table of its `buildpack.toml`,
+//! optionally with a list of mixins it requires from the stack images. This module checks such
+//! declarations against the stack a build actually runs on, as identified by `CNB_STACK_ID`, and
+//! the mixins provided by the build and run images.
+//!
+//! Mixins follow the rules of the [Cloud Native Buildpack specification](https://github.com/buildpacks/spec/blob/main/platform.md#mixins):
+//!
+//! - A mixin prefixed with `build:` is only required on the build image
+//! - A mixin prefixed with `run:` is only required on the run image
+//! - A mixin without a prefix is required on both images
+//!
+//! A stack with the id `*` matches any stack and cannot require mixins.
+//!
+//! # Example
+//! ```
+//! use libcnb::data::buildpack::BuildpackToml;
+//! use libcnb::stack::{check_stack_compatibility, StackInfo};
+//!
+//! let buildpack_toml = toml::from_str::<BuildpackToml<Option<toml::value::Table>>>(r#"
+//! api = "0.6"
+//!
+//! [buildpack]
+//! id = "foo/bar"
+//! name = "Bar Buildpack"
+//! version = "0.0.1"
+//!
+//! [[stacks]]
+//! id = "io.buildpacks.stacks.bionic"
+//! mixins = ["build:git", "libpq"]
+//! "#).unwrap();
+//!
+//! let stack = StackInfo::new("io.buildpacks.stacks.bionic")
+//!     .build_mixins(vec!["git", "libpq"])
+//!     .run_mixins(vec!["libpq"]);
+//!
+//! assert!(check_stack_compatibility(&buildpack_toml, &stack).is_ok());
+//!
+//! let stack = StackInfo::new("io.buildpacks.stacks.bionic").build_mixins(vec!["git"]);
+//! let error = check_stack_compatibility(&buildpack_toml, &stack).unwrap_err();
+//! assert!(error.to_string().contains("libpq"));
+//! ```
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::data::buildpack::{BuildpackToml, Stack};
+
+/// The stack a build runs on, including the mixins provided by its build and run images.
+#[derive(Debug, Clone)]
+pub struct StackInfo {
+    pub id: String,
+    pub build_mixins: Vec<String>,
+    pub run_mixins: Vec<String>,
+}
+
+impl StackInfo {
+    /// Creates a `StackInfo` for the given stack id (usually the value of `CNB_STACK_ID`) without
+    /// any mixins.
+    pub fn new(id: impl Into<String>) -> Self {
+        StackInfo {
+            id: id.into(),
+            build_mixins: vec![],
+            run_mixins: vec![],
+        }
+    }
+
+    /// Sets the mixins provided by the build image. Mixins may carry a `build:` prefix.
+    #[must_use]
+    pub fn build_mixins(mut self, mixins: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.build_mixins = mixins.into_iter().map(|mixin| mixin.into()).collect();
+        self
+    }
+
+    /// Sets the mixins provided by the run image. Mixins may carry a `run:` prefix.
+    #[must_use]
+    pub fn run_mixins(mut self, mixins: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.run_mixins = mixins.into_iter().map(|mixin| mixin.into()).collect();
+        self
+    }
+
+    fn provides_build_mixin(&self, name: &str) -> bool {
+        provides_mixin(&self.build_mixins, "build:", name)
+    }
+
+    fn provides_run_mixin(&self, name: &str) -> bool {
+        provides_mixin(&self.run_mixins, "run:", name)
+    }
+}
+
+fn provides_mixin(mixins: &[String], prefix: &str, name: &str) -> bool {
+    mixins
+        .iter()
+        .any(|mixin| mixin == name || mixin.strip_prefix(prefix) == Some(name))
+}
+
+/// A mixin requirement as declared in `buildpack.toml`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Mixin {
+    /// Required on both build and run images
+    Any(String),
+    /// Required on the build image only (`build:` prefix)
+    Build(String),
+    /// Required on the run image only (`run:` prefix)
+    Run(String),
+}
+
+impl Mixin {
+    pub fn parse(value: &str) -> Self {
+        if let Some(name) = value.strip_prefix("build:") {
+            Mixin::Build(String::from(name))
+        } else if let Some(name) = value.strip_prefix("run:") {
+            Mixin::Run(String::from(name))
+        } else {
+            Mixin::Any(String::from(value))
+        }
+    }
+}
+
+impl Display for Mixin {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Mixin::Any(name) => formatter.write_str(name),
+            Mixin::Build(name) => write!(formatter, "build:{}", name),
+            Mixin::Run(name) => write!(formatter, "run:{}", name),
+        }
+    }
+}
+
+/// Mixins a stack lacks, split by the image that lacks them.
+#[derive(Debug, Eq, PartialEq, Default)]
+pub struct MissingMixins {
+    pub build: Vec<String>,
+    pub run: Vec<String>,
+}
+
+impl MissingMixins {
+    pub fn is_empty(&self) -> bool {
+        self.build.is_empty() && self.run.is_empty()
+    }
+}
+
+impl Display for MissingMixins {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+
+        if !self.build.is_empty() {
+            parts.push(format!(
+                "build image is missing [{}]",
+                self.build.join(", ")
+            ));
+        }
+
+        if !self.run.is_empty() {
+            parts.push(format!("run image is missing [{}]", self.run.join(", ")));
+        }
+
+        formatter.write_str(&parts.join("; "))
+    }
+}
+
+/// Stack compatibility errors
+#[derive(thiserror::Error, Debug)]
+pub enum StackCompatibilityError {
+    #[error("This buildpack does not support stack `{stack_id}`. Supported stacks: [{}]", .supported_stacks.join(", "))]
+    UnsupportedStack {
+        stack_id: String,
+        supported_stacks: Vec<String>,
+    },
+
+    #[error("Stack `{stack_id}` does not provide all mixins required by this buildpack: {missing_mixins}")]
+    MissingMixins {
+        stack_id: String,
+        missing_mixins: MissingMixins,
+    },
+}
+
+/// Checks if the given buildpack can run on the given stack
+///
+/// The buildpack is compatible if one of its declared stacks has the same id as the given stack,
+/// or is `*`, and all mixins required by that declaration are provided by the stack images.
+///
+/// A buildpack that declares no stacks, usually because it declares `[[targets]]` instead, is
+/// compatible with every stack.
+pub fn check_stack_compatibility<BM>(
+    buildpack_toml: &BuildpackToml<BM>,
+    stack: &StackInfo,
+) -> Result<(), StackCompatibilityError> {
+    if buildpack_toml.stacks.is_empty() {
+        return Ok(());
+    }
+
+    let declared_stack = buildpack_toml
+        .stacks
+        .iter()
+        .find(|declared_stack| declared_stack.id.as_str() == stack.id)
+        .or_else(|| {
+            buildpack_toml
+                .stacks
+                .iter()
+                .find(|declared_stack| declared_stack.id.as_str() == "*")
+        })
+        .ok_or_else(|| StackCompatibilityError::UnsupportedStack {
+            stack_id: stack.id.clone(),
+            supported_stacks: buildpack_toml
+                .stacks
+                .iter()
+                .map(|declared_stack| String::from(declared_stack.id.as_str()))
+                .collect(),
+        })?;
+
+    let missing_mixins = missing_mixins(declared_stack, stack);
+
+    if missing_mixins.is_empty() {
+        Ok(())
+    } else {
+        Err(StackCompatibilityError::MissingMixins {
+            stack_id: stack.id.clone(),
+            missing_mixins,
+        })
+    }
+}
+
+/// Determines the mixins required by the given stack declaration that the given stack lacks.
+pub fn missing_mixins(declared_stack: &Stack, stack: &StackInfo) -> MissingMixins {
+    let mut missing_mixins = MissingMixins::default();
+
+    for mixin in declared_stack
+        .mixins
+        .iter()
+        .map(|mixin| Mixin::parse(mixin))
+    {
+        match &mixin {
+            Mixin::Any(name) => {
+                if !stack.provides_build_mixin(name) {
+                    missing_mixins.build.push(mixin.to_string());
+                }
+
+                if !stack.provides_run_mixin(name) {
+                    missing_mixins.run.push(mixin.to_string());
+                }
+            }
+            Mixin::Build(name) => {
+                if !stack.provides_build_mixin(name) {
+                    missing_mixins.build.push(mixin.to_string());
+                }
+            }
+            Mixin::Run(name) => {
+                if !stack.provides_run_mixin(name) {
+                    missing_mixins.run.push(mixin.to_string());
+                }
+            }
+        }
+    }
+
+    missing_mixins
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::GenericMetadata;
+
+    fn buildpack_toml(stacks: &str) -> BuildpackToml<GenericMetadata> {
+        toml::from_str(&format!(
+            r#"
+api = "0.6"
+
+[buildpack]
+id = "foo/bar"
+name = "Bar Buildpack"
+version = "0.0.1"
+
+{}
+"#,
+            stacks
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn it_accepts_matching_stack_without_mixins() {
+        let buildpack_toml = buildpack_toml(
+            r#"
+[[stacks]]
+id = "heroku-20"
+"#,
+        );
+
+        assert!(check_stack_compatibility(&buildpack_toml, &StackInfo::new("heroku-20")).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_unsupported_stack() {
+        let buildpack_toml = buildpack_toml(
+            r#"
+[[stacks]]
+id = "heroku-18"
+
+[[stacks]]
+id = "heroku-20"
+"#,
+        );
+
+        match check_stack_compatibility(&buildpack_toml, &StackInfo::new("heroku-22")) {
+            Err(StackCompatibilityError::UnsupportedStack {
+                stack_id,
+                supported_stacks,
+            }) => {
+                assert_eq!(stack_id, "heroku-22");
+                assert_eq!(supported_stacks, vec!["heroku-18", "heroku-20"]);
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_accepts_any_stack_without_declared_stacks() {
+        let buildpack_toml = buildpack_toml(
+            r#"
+[[targets]]
+os = "linux"
+arch = "amd64"
+"#,
+        );
+
+        assert!(check_stack_compatibility(&buildpack_toml, &StackInfo::new("heroku-22")).is_ok());
+    }
+
+    #[test]
+    fn it_accepts_any_stack_with_star() {
+        let buildpack_toml = buildpack_toml(
+            r#"
+[[stacks]]
+id = "*"
+"#,
+        );
+
+        assert!(check_stack_compatibility(&buildpack_toml, &StackInfo::new("heroku-22")).is_ok());
+    }
+
+    #[test]
+    fn it_prefers_exact_stack_over_star() {
+        let buildpack_toml = buildpack_toml(
+            r#"
+[[stacks]]
+id = "*"
+
+[[stacks]]
+id = "heroku-20"
+mixins = ["git"]
+"#,
+        );
+
+        assert!(check_stack_compatibility(&buildpack_toml, &StackInfo::new("heroku-20")).is_err());
+        assert!(check_stack_compatibility(&buildpack_toml, &StackInfo::new("heroku-22")).is_ok());
+    }
+
+    #[test]
+    fn it_reports_missing_mixins_per_image() {
+        let buildpack_toml = buildpack_toml(
+            r#"
+[[stacks]]
+id = "heroku-20"
+mixins = ["libpq", "build:git", "run:tzdata", "curl"]
+"#,
+        );
+
+        let stack = StackInfo::new("heroku-20")
+            .build_mixins(vec!["build:git", "curl"])
+            .run_mixins(vec!["run:curl"]);
+
+        match check_stack_compatibility(&buildpack_toml, &stack) {
+            Err(StackCompatibilityError::MissingMixins { missing_mixins, .. }) => {
+                assert_eq!(missing_mixins.build, vec!["libpq"]);
+                assert_eq!(missing_mixins.run, vec!["libpq", "run:tzdata"]);
+                assert_eq!(
+                    missing_mixins.to_string(),
+                    "build image is missing [libpq]; run image is missing [libpq, run:tzdata]"
+                );
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_does_not_accept_mixins_with_other_stage_prefix() {
+        let buildpack_toml = buildpack_toml(
+            r#"
+[[stacks]]
+id = "heroku-20"
+mixins = ["build:git"]
+"#,
+        );
+
+        let stack = StackInfo::new("heroku-20").build_mixins(vec!["run:git"]);
+        assert!(check_stack_compatibility(&buildpack_toml, &stack).is_err());
+    }
+
+    #[test]
+    fn it_parses_mixins() {
+        assert_eq!(Mixin::parse("git"), Mixin::Any(String::from("git")));
+        assert_eq!(Mixin::parse("build:git"), Mixin::Build(String::from("git")));
+        assert_eq!(Mixin::parse("run:git"), Mixin::Run(String::from("git")));
+        assert_eq!(Mixin::parse("run:git").to_string(), "run:git");
+    }
+}
@@ -0,0 +1,276 @@
+use std::env;
+
+use crate::data::buildpack;
+
+/// The target (os, architecture and distribution) of the current build.
+///
+/// Read from the `CNB_TARGET_*` environment variables the lifecycle provides. When those are not
+/// set, the target is derived from `CNB_STACK_ID` instead. On platforms without stacks, the stack
+/// id of the contexts is derived from the target, see [`Target::stack_id`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Target {
+    pub os: String,
+    pub arch: String,
+    pub arch_variant: Option<String>,
+    pub distro_name: Option<String>,
+    pub distro_version: Option<String>,
+}
+
+impl Target {
+    /// Derives a target from a stack id, for platforms that do not provide `CNB_TARGET_*`
+    /// environment variables.
+    ///
+    /// Well-known stacks map to their distribution, the architecture is the one of the current
+    /// process.
+    ///
+    /// # Examples
+    /// ```
+    /// use libcnb::Target;
+    ///
+    /// let target = Target::from_stack_id("heroku-20");
+    /// assert_eq!(target.os, "linux");
+    /// assert_eq!(target.distro_name.as_deref(), Some("ubuntu"));
+    /// assert_eq!(target.distro_version.as_deref(), Some("20.04"));
+    /// ```
+    pub fn from_stack_id(stack_id: impl AsRef<str>) -> Self {
+        let distro_version = match stack_id.as_ref() {
+            "heroku-18" | "io.buildpacks.stacks.bionic" => Some("18.04"),
+            "heroku-20" | "io.buildpacks.stacks.focal" => Some("20.04"),
+            "heroku-22" | "io.buildpacks.stacks.jammy" => Some("22.04"),
+            _ => None,
+        };
+
+        Target {
+            os: String::from("linux"),
+            arch: String::from(match env::consts::ARCH {
+                "x86_64" => "amd64",
+                "aarch64" => "arm64",
+                other => other,
+            }),
+            arch_variant: None,
+            distro_name: distro_version.map(|_| String::from("ubuntu")),
+            distro_version: distro_version.map(String::from),
+        }
+    }
+
+    /// Derives a stack id from this target, for platforms that do not provide `CNB_STACK_ID`.
+    ///
+    /// Well-known Ubuntu releases map to their `io.buildpacks.stacks.*` stack, all other targets
+    /// to `<os>/<arch>`.
+    ///
+    /// # Examples
+    /// ```
+    /// use libcnb::Target;
+    ///
+    /// let target = Target {
+    ///     os: String::from("linux"),
+    ///     arch: String::from("arm64"),
+    ///     arch_variant: None,
+    ///     distro_name: Some(String::from("ubuntu")),
+    ///     distro_version: Some(String::from("22.04")),
+    /// };
+    /// assert_eq!(target.stack_id(), "io.buildpacks.stacks.jammy");
+    ///
+    /// let target = Target {
+    ///     distro_name: None,
+    ///     distro_version: None,
+    ///     ..target
+    /// };
+    /// assert_eq!(target.stack_id(), "linux/arm64");
+    /// ```
+    pub fn stack_id(&self) -> String {
+        let stack_name = match (self.distro_name.as_deref(), self.distro_version.as_deref()) {
+            (Some("ubuntu"), Some("18.04")) => Some("bionic"),
+            (Some("ubuntu"), Some("20.04")) => Some("focal"),
+            (Some("ubuntu"), Some("22.04")) => Some("jammy"),
+            _ => None,
+        };
+
+        match stack_name {
+            Some(stack_name) => format!("io.buildpacks.stacks.{}", stack_name),
+            None => format!("{}/{}", self.os, self.arch),
+        }
+    }
+
+    /// Selects the artifact best suited for this target.
+    ///
+    /// Of all artifacts whose target declaration [matches](buildpack::Target::matches) this
+    /// target, the one with the most specific declaration is returned. If multiple artifacts are
+    /// equally specific, the first one wins.
+    ///
+    /// # Examples
+    /// ```
+    /// use libcnb::Target;
+    /// use libcnb::data::buildpack;
+    ///
+    /// struct Artifact {
+    ///     url: &'static str,
+    ///     target: buildpack::Target,
+    /// }
+    ///
+    /// let artifacts = vec![
+    ///     Artifact {
+    ///         url: "https://example.com/node-linux-x64.tar.xz",
+    ///         target: buildpack::Target {
+    ///             os: Some(String::from("linux")),
+    ///             arch: Some(String::from("amd64")),
+    ///             ..buildpack::Target::default()
+    ///         },
+    ///     },
+    ///     Artifact {
+    ///         url: "https://example.com/node-linux-arm64.tar.xz",
+    ///         target: buildpack::Target {
+    ///             os: Some(String::from("linux")),
+    ///             arch: Some(String::from("arm64")),
+    ///             ..buildpack::Target::default()
+    ///         },
+    ///     },
+    /// ];
+    ///
+    /// let target = Target {
+    ///     os: String::from("linux"),
+    ///     arch: String::from("arm64"),
+    ///     arch_variant: None,
+    ///     distro_name: None,
+    ///     distro_version: None,
+    /// };
+    ///
+    /// let artifact = target.select(&artifacts, |artifact| &artifact.target).unwrap();
+    /// assert_eq!(artifact.url, "https://example.com/node-linux-arm64.tar.xz");
+    /// ```
+    pub fn select<'a, A>(
+        &self,
+        artifacts: impl IntoIterator<Item = &'a A>,
+        artifact_target: impl Fn(&A) -> &buildpack::Target,
+    ) -> Option<&'a A> {
+        let mut selected: Option<(&A, usize)> = None;
+
+        for artifact in artifacts {
+            let declaration = artifact_target(artifact);
+
+            if declaration.matches(self) {
+                let specificity = declaration.specificity();
+
+                if selected.map_or(true, |(_, selected_specificity)| {
+                    specificity > selected_specificity
+                }) {
+                    selected = Some((artifact, specificity));
+                }
+            }
+        }
+
+        selected.map(|(artifact, _)| artifact)
+    }
+}
+
+/// Reads the target of the current build from the given environment variable lookup, `None` when
+/// `CNB_TARGET_OS` or `CNB_TARGET_ARCH` are not set.
+pub(crate) fn read_target(var: impl Fn(&str) -> Option<String>) -> Option<Target> {
+    match (var("CNB_TARGET_OS"), var("CNB_TARGET_ARCH")) {
+        (Some(os), Some(arch)) => Some(Target {
+            os,
+            arch,
+            arch_variant: var("CNB_TARGET_ARCH_VARIANT"),
+            distro_name: var("CNB_TARGET_DISTRO_NAME"),
+            distro_version: var("CNB_TARGET_DISTRO_VERSION"),
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn read_target_from_env() {
+        let vars: HashMap<&str, &str> = [
+            ("CNB_TARGET_OS", "linux"),
+            ("CNB_TARGET_ARCH", "arm64"),
+            ("CNB_TARGET_ARCH_VARIANT", "v8"),
+            ("CNB_TARGET_DISTRO_NAME", "ubuntu"),
+            ("CNB_TARGET_DISTRO_VERSION", "22.04"),
+        ]
+        .into_iter()
+        .collect();
+
+        let target = read_target(|key| vars.get(key).map(|s| String::from(*s)));
+        assert_eq!(
+            target.unwrap(),
+            Target {
+                os: String::from("linux"),
+                arch: String::from("arm64"),
+                arch_variant: Some(String::from("v8")),
+                distro_name: Some(String::from("ubuntu")),
+                distro_version: Some(String::from("22.04")),
+            }
+        );
+    }
+
+    #[test]
+    fn read_target_requires_os_and_arch() {
+        assert_eq!(read_target(|_| None), None);
+        assert_eq!(
+            read_target(|key| (key == "CNB_TARGET_OS").then(|| String::from("linux"))),
+            None
+        );
+    }
+
+    #[test]
+    fn stack_id_round_trips_well_known_stacks() {
+        for stack_id in [
+            "io.buildpacks.stacks.bionic",
+            "io.buildpacks.stacks.focal",
+            "io.buildpacks.stacks.jammy",
+        ] {
+            assert_eq!(Target::from_stack_id(stack_id).stack_id(), stack_id);
+        }
+    }
+
+    #[test]
+    fn from_unknown_stack_id() {
+        let target = Target::from_stack_id("com.example.stack");
+        assert_eq!(target.os, "linux");
+        assert_eq!(target.distro_name, None);
+        assert_eq!(target.distro_version, None);
+    }
+
+    #[test]
+    fn select_prefers_most_specific_match() {
+        let generic = buildpack::Target::default();
+        let arm64 = buildpack::Target {
+            arch: Some(String::from("arm64")),
+            ..buildpack::Target::default()
+        };
+        let amd64 = buildpack::Target {
+            arch: Some(String::from("amd64")),
+            ..buildpack::Target::default()
+        };
+
+        let artifacts = vec![generic, arm64, amd64];
+        let target = Target {
+            os: String::from("linux"),
+            arch: String::from("arm64"),
+            arch_variant: None,
+            distro_name: None,
+            distro_version: None,
+        };
+
+        assert_eq!(
+            target.select(&artifacts, |artifact| artifact),
+            Some(&artifacts[1])
+        );
+
+        let target = Target {
+            arch: String::from("ppc64le"),
+            ..target
+        };
+
+        assert_eq!(
+            target.select(&artifacts, |artifact| artifact),
+            Some(&artifacts[0])
+        );
+        assert_eq!(target.select(&artifacts[1..], |artifact| artifact), None);
+    }
+}
@@ -2,7 +2,10 @@ use serde::Serialize;
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
 
-use crate::{data::buildpack::BuildpackToml, platform::Platform, write_toml_file, TomlFileError};
+use crate::{
+    data::buildpack::BuildpackToml, mode::LifecycleMode, platform::Platform, target::Target,
+    write_toml_file, TomlFileError,
+};
 
 /// Context for a buildpack's test phase execution.
 pub struct TestContext<P: Platform, BM> {
@@ -10,7 +13,9 @@ pub struct TestContext<P: Platform, BM> {
     pub app_dir: PathBuf,
     pub buildpack_dir: PathBuf,
     pub stack_id: String,
+    pub target: Target,
     pub platform: P,
+    pub lifecycle_mode: Option<LifecycleMode>,
     pub buildpack_descriptor: BuildpackToml<BM>,
 }
 
@@ -0,0 +1,176 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+
+use tempfile::TempDir;
+
+use crate::build::BuildContext;
+use crate::data::buildpack::BuildpackToml;
+use crate::data::buildpack_plan::BuildpackPlan;
+use crate::generic::{GenericMetadata, GenericPlatform};
+use crate::layer_pruning::LayerUsage;
+use crate::platform::Platform;
+use crate::target::Target;
+use crate::transfer::DownloadCache;
+
+/// Creates a [`BuildContext`] for the `heroku-20` stack with all directories inside `tmpdir`.
+///
+/// Only the layers directory is created.
+pub(crate) fn build_context(tmpdir: &TempDir) -> BuildContext<GenericPlatform, GenericMetadata> {
+    let layers_dir = tmpdir.path().join("layers");
+    fs::create_dir_all(&layers_dir).unwrap();
+
+    BuildContext {
+        layers_dir,
+        app_dir: tmpdir.path().join("app"),
+        buildpack_dir: tmpdir.path().join("buildpack"),
+        stack_id: String::from("heroku-20"),
+        target: Target::from_stack_id("heroku-20"),
+        platform: GenericPlatform::from_path(tmpdir.path().join("platform")).unwrap(),
+        lifecycle_mode: None,
+        buildpack_plan: BuildpackPlan { entries: vec![] },
+        buildpack_descriptor: toml::from_str::<BuildpackToml<GenericMetadata>>(
+            r#"
+api = "0.6"
+
+[buildpack]
+id = "libcnb/test"
+version = "0.0.1"
+name = "Test"
+
+[[stacks]]
+id = "heroku-20"
+"#,
+        )
+        .unwrap(),
+        project_descriptor: None,
+        layer_usage: LayerUsage::default(),
+        download_cache: DownloadCache::new(tmpdir.path().join("layers")),
+        transfer_client: Mutex::default(),
+    }
+}
+
+/// Creates a `.tar.xz` archive with the given files.
+pub(crate) fn tar_xz(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(xz::write::XzEncoder::new(Vec::new(), 6));
+    for (path, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, path, contents.as_bytes())
+            .unwrap();
+    }
+
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+/// A request received by [`serve`].
+pub(crate) struct TestRequest {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl TestRequest {
+    /// Returns the value of the header with the given lowercase name.
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Serves HTTP requests on a random local port until the test process exits.
+///
+/// The handler receives the request and returns the status code and body of the response. The
+/// path of proxied requests is the absolute URL. Returns the base URL of the server, without a
+/// trailing slash.
+pub(crate) fn serve(handler: impl Fn(&TestRequest) -> (u16, Vec<u8>) + Send + 'static) -> String {
+    serve_with_headers(move |request| {
+        let (status, body) = handler(request);
+        (status, Vec::new(), body)
+    })
+}
+
+/// Like [`serve`], but the handler returns additional response headers as well.
+pub(crate) fn serve_with_headers(
+    handler: impl Fn(&TestRequest) -> (u16, Vec<(String, String)>, Vec<u8>) + Send + 'static,
+) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+
+            let request = match read_request(&stream) {
+                Ok(request) => request,
+                Err(_) => continue,
+            };
+
+            let (status, headers, body) = handler(&request);
+            let headers = headers
+                .iter()
+                .map(|(name, value)| format!("{}: {}\r\n", name, value))
+                .collect::<Vec<_>>()
+                .concat();
+
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {} Status\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+                status,
+                body.len(),
+                headers
+            )
+            .and_then(|()| stream.write_all(&body));
+        }
+    });
+
+    url
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<TestRequest> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split(' ');
+    let method = String::from(parts.next().unwrap_or("GET"));
+    let path = String::from(parts.next().unwrap_or("/"));
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? <= 2 {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), String::from(value.trim())));
+        }
+    }
+
+    let mut request = TestRequest {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    };
+
+    let content_length = request
+        .header("content-length")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0);
+    reader.take(content_length).read_to_end(&mut request.body)?;
+
+    Ok(request)
+}
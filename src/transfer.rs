@@ -1,20 +1,123 @@
+//! Downloading, extracting and uploading of files
+//!
+//! Use [`Download`] or [`get_and_extract_verified`] to download dependencies. Both verify the
+//! checksum of the download before anything is extracted.
+//!
+//! All transfers go through a [`TransferClient`], which retries failed requests and applies
+//! timeouts, proxies, credentials and CA bundles. Use [`ClientConfig::from_platform`] to configure
+//! it from the platform environment and service bindings.
+
 use reqwest::blocking::Response;
 use reqwest::IntoUrl;
 use sha2::Digest;
-use std::fs::File;
 use std::io;
-use std::path::PathBuf;
-use tar::Archive;
-use xz::read::XzDecoder;
-use xz::write::XzEncoder;
+use std::path::{Path, PathBuf};
+
+pub use archive::{extract_archive, ArchiveFormat, EntriesOutsidePrefix, ExtractOptions};
+pub use archiver::Archiver;
+pub use cache::{DownloadCache, DownloadCacheStats, DOWNLOAD_CACHE_LAYER_NAME};
+pub use checksum::{Checksum, ChecksumAlgorithm, ChecksumError};
+pub use client::{
+    ClientConfig, Credentials, TransferClient, CA_CERTIFICATES_BINDING_TYPE,
+    CREDENTIALS_BINDING_TYPE, DEPENDENCY_MIRROR_BINDING_TYPE, OFFLINE_DEPENDENCIES_DIR_NAME,
+};
+pub use download::Download;
+pub use mirror::ORIGINAL_HOST_PLACEHOLDER;
+pub use upload::{Upload, UploadReceipt};
+
+mod archive;
+mod archiver;
+mod cache;
+mod checksum;
+mod client;
+mod download;
+mod mirror;
+mod upload;
+
+/// An error that occurred while transferring a file.
+#[derive(thiserror::Error, Debug)]
+pub enum TransferError {
+    #[error("HTTP request failed: {0}")]
+    HttpError(#[from] reqwest::Error),
+
+    #[error("IO error while transferring file: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Checksum mismatch for {url}: expected {expected}, but got {actual}")]
+    ChecksumMismatch {
+        url: String,
+        expected: Checksum,
+        actual: Checksum,
+    },
+
+    #[error("Archive entry {0} is not inside the expected prefix")]
+    EntryOutsidePrefix(PathBuf),
+
+    #[error("Archive entry {0} points outside of the extraction directory")]
+    UnsafeEntryPath(PathBuf),
+
+    #[error("Archive link {path} points to {target} outside of the extraction directory")]
+    UnsafeLink { path: PathBuf, target: PathBuf },
+
+    #[error("Archive exceeds the maximum extracted size of {0} bytes")]
+    ArchiveTooLarge(u64),
+
+    #[error("Archive exceeds the maximum number of {0} entries")]
+    TooManyEntries(u64),
+
+    #[error("Unknown archive format")]
+    UnknownArchiveFormat,
+
+    #[error("Creating {0:?} archives is not supported")]
+    UnsupportedArchiveFormat(ArchiveFormat),
+
+    #[error("Invalid glob pattern: {0}")]
+    InvalidGlob(String),
+
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+
+    #[error("{url} is not available offline, it was expected at {path}")]
+    MissingOfflineDependency { url: String, path: PathBuf },
+
+    #[error("Invalid proxy URL: {0}")]
+    InvalidProxyUrl(String),
+
+    #[error(
+        "Service binding {0} has no `host` and either a `token` or a `username` and `password`"
+    )]
+    InvalidCredentialsBinding(PathBuf),
+
+    #[error("CA bundle {0} does not contain valid PEM certificates")]
+    InvalidCaBundle(PathBuf),
+
+    #[error("Upload to {url} failed with status {status}: {body}")]
+    UploadFailed {
+        url: String,
+        status: u16,
+        body: String,
+    },
+
+    #[error("Response of {0} has no ETag header")]
+    MissingETag(String),
+
+    #[error("File needs {parts} parts, but only {urls} part URLs were given")]
+    NotEnoughPartUrls { parts: u64, urls: usize },
+
+    #[error("Invalid HTTP header: {0}")]
+    InvalidHeader(String),
+
+    #[error("Zip archive error: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+}
 
 pub fn sha256(data: &[u8]) -> String {
     format!("{:x}", sha2::Sha256::digest(data))
 }
 
+/// Sends a `GET` request with the default [`TransferClient`] and returns the response body.
 pub fn get(uri: impl AsRef<str>) -> anyhow::Result<String> {
-    let response = reqwest::blocking::get(uri.as_ref())?;
-    Ok(response.text()?)
+    Ok(TransferClient::new()?.get(uri.as_ref())?)
 }
 
 pub fn get_and_extract(
@@ -22,58 +125,177 @@ pub fn get_and_extract(
     dst: impl AsRef<std::path::Path>,
     prefix: Option<&str>,
 ) -> anyhow::Result<String> {
-    let response = reqwest::blocking::get(uri.as_ref())?;
-    let content = io::Cursor::new(response.bytes()?);
-    let sha256 = sha256(content.get_ref());
-    let decompressor = XzDecoder::new(content);
-    let mut archive = Archive::new(decompressor);
-    let mut target_path = PathBuf::new();
-    target_path.push(&dst);
-    if let Some(str) = prefix {
-        for r in archive.entries()?.filter(|e| e.is_ok()) {
-            let mut entry = r?;
-            let relative_path = entry.path()?.strip_prefix(str)?.to_owned();
-            if relative_path.as_os_str() != "" {
-                entry.unpack(target_path.join(relative_path))?;
-            }
-        }
-    } else {
-        archive.unpack(dst)?;
+    let mut download = Download::new(uri.as_ref());
+    if let Some(prefix) = prefix {
+        download = download.prefix(prefix);
     }
 
-    Ok(sha256)
+    let checksum = download.extract_to(dst)?;
+    Ok(String::from(checksum.hex()))
 }
 
+/// Downloads an archive and extracts it into `dst` after verifying its checksum
+///
+/// Nothing is extracted if the checksum does not match. When `prefix` is given, only the archive
+/// entries inside the prefix are extracted, with the prefix stripped from their paths. See
+/// [`Download`] for more options.
+///
+/// # Example
+/// ```no_run
+/// use std::str::FromStr;
+/// use libcnb::transfer::{get_and_extract_verified, Checksum, TransferError};
+///
+/// let checksum = Checksum::from_str(
+///     "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+/// )
+/// .unwrap();
+///
+/// match get_and_extract_verified("https://example.com/ruby.tar.xz", "/layers/ruby", None, &checksum) {
+///     Ok(()) => {}
+///     Err(TransferError::ChecksumMismatch { expected, actual, .. }) => {
+///         eprintln!("Expected {}, but got {}", expected, actual)
+///     }
+///     Err(error) => eprintln!("{}", error),
+/// }
+/// ```
+pub fn get_and_extract_verified(
+    uri: impl AsRef<str>,
+    dst: impl AsRef<Path>,
+    prefix: Option<&str>,
+    checksum: &Checksum,
+) -> Result<(), TransferError> {
+    let mut download = Download::new(uri.as_ref()).checksum(checksum.clone());
+    if let Some(prefix) = prefix {
+        download = download.prefix(prefix);
+    }
+
+    download.extract_to(dst).map(|_| ())
+}
+
+/// Returns the actual checksum if it matches the expected one.
+pub(crate) fn verify_checksum(
+    uri: &str,
+    expected: &Checksum,
+    actual: Checksum,
+) -> Result<Checksum, TransferError> {
+    if actual == *expected {
+        Ok(actual)
+    } else {
+        Err(TransferError::ChecksumMismatch {
+            url: String::from(uri),
+            expected: expected.clone(),
+            actual,
+        })
+    }
+}
+
+/// Uploads a file with the default [`TransferClient`], see [`TransferClient::put`].
 pub fn put(file_path: &PathBuf, url: impl IntoUrl) -> anyhow::Result<Response, anyhow::Error> {
-    let client = reqwest::blocking::Client::new();
-    let file = File::open(file_path)?;
-    let response = client.put(url).body(file).send()?;
-    Ok(response)
+    Ok(TransferClient::new()?.put(file_path, url)?)
 }
 
+/// Archives a directory and uploads the archive with the default [`TransferClient`].
+///
+/// The archive is a reproducible `.tar.xz` of the whole directory, see [`Archiver`]. Use
+/// [`archive_and_put`] to configure the archive, e.g. to honour `.gitignore` files.
 pub fn compress_and_put(
     source_path: &PathBuf,
     url: impl IntoUrl,
 ) -> anyhow::Result<(), anyhow::Error> {
-    let tmpdir = tempfile::tempdir().unwrap();
-    let archive_file_path = tmpdir.path().join("source.tar.xz");
-    compress(source_path, &archive_file_path)?;
-    put(&archive_file_path, url)?;
-    Ok(())
+    archive_and_put(&Archiver::new(source_path), url)
 }
 
-fn compress(path: &PathBuf, file_path: &PathBuf) -> Result<(), anyhow::Error> {
-    let file = File::create(file_path)?;
-    let enc = XzEncoder::new(&file, 6);
-    let mut builder = tar::Builder::new(enc);
-    builder.append_dir_all(".", path)?;
+/// Creates the archive of an [`Archiver`] and uploads it with the default [`TransferClient`].
+///
+/// # Example
+/// ```no_run
+/// use libcnb::transfer::{archive_and_put, ArchiveFormat, Archiver};
+///
+/// archive_and_put(
+///     &Archiver::new("/workspace")
+///         .exclude("node_modules")
+///         .format(ArchiveFormat::TarGz),
+///     "https://example.com/upload/source.tar.gz",
+/// )
+/// .unwrap();
+/// ```
+pub fn archive_and_put(
+    archiver: &Archiver,
+    url: impl IntoUrl,
+) -> anyhow::Result<(), anyhow::Error> {
+    let tmpdir = tempfile::tempdir()?;
+    let archive_file_path = tmpdir.path().join("source");
+    archiver.create(&archive_file_path)?;
+    put(&archive_file_path, url)?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::Path;
+    use crate::test_support::tar_xz;
+    use std::fs::File;
+    use std::str::FromStr;
+    use tar::Archive;
+    use xz::read::XzDecoder;
+
+    #[test]
+    fn it_rejects_checksum_mismatch() {
+        let archive = tar_xz(&[("ruby/bin/ruby", "#!/bin/sh")]);
+        let expected = Checksum::from_str(
+            "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+        )
+        .unwrap();
+        let actual = ChecksumAlgorithm::Sha256.digest(&archive);
+
+        match verify_checksum("https://example.com/ruby.tar.xz", &expected, actual.clone()) {
+            Err(TransferError::ChecksumMismatch {
+                url,
+                expected: mismatch_expected,
+                actual: mismatch_actual,
+            }) => {
+                assert_eq!(url, "https://example.com/ruby.tar.xz");
+                assert_eq!(mismatch_expected, expected);
+                assert_eq!(mismatch_actual, actual);
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        let expected = ChecksumAlgorithm::Sha512.digest(&archive);
+        assert!(verify_checksum(
+            "https://example.com/ruby.tar.xz",
+            &expected,
+            ChecksumAlgorithm::Sha512.digest(&archive)
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn it_extracts_with_prefix() {
+        let archive = tar_xz(&[("ruby/bin/ruby", "#!/bin/sh"), ("ruby/README", "Ruby")]);
+        let tmpdir = tempfile::tempdir().unwrap();
+
+        extract_archive(
+            io::Cursor::new(&archive),
+            tmpdir.path(),
+            &ExtractOptions::new().prefix("ruby"),
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(tmpdir.path().join("bin/ruby")).unwrap(),
+            "#!/bin/sh"
+        );
+        assert!(tmpdir.path().join("README").exists());
+
+        assert!(matches!(
+            extract_archive(
+                io::Cursor::new(&archive),
+                tmpdir.path(),
+                &ExtractOptions::new().prefix("node")
+            ),
+            Err(TransferError::EntryOutsidePrefix(_))
+        ));
+    }
 
     #[test]
     fn it_compresses_directory() {
@@ -83,55 +305,24 @@ mod tests {
             .unwrap();
         let tmpdir = tempfile::tempdir().unwrap();
         let archive_file_path = tmpdir.path().join("source.tar.xz");
-        let result = compress(&app_dir, &archive_file_path);
-        match result {
-            Ok(_) => {
-                let tar_xz = File::open(archive_file_path).unwrap();
-                let tar = XzDecoder::new(tar_xz);
-                let mut archive = Archive::new(tar);
-                let mut iter = archive.entries().unwrap();
-                assert!(iter
-                    .next()
-                    .unwrap()
-                    .unwrap()
-                    .path()
-                    .unwrap()
-                    .as_os_str()
-                    .eq("./"));
-                assert!(iter
-                    .next()
-                    .unwrap()
-                    .unwrap()
-                    .path()
-                    .unwrap()
-                    .as_os_str()
-                    .eq("src"));
-                assert!(iter
-                    .next()
-                    .unwrap()
-                    .unwrap()
-                    .path()
-                    .unwrap()
-                    .as_os_str()
-                    .eq("src/Dummy.apex"));
-                assert!(iter
-                    .next()
-                    .unwrap()
-                    .unwrap()
-                    .path()
-                    .unwrap()
-                    .as_os_str()
-                    .eq("src/DummyTest.cls"));
-                assert!(iter
-                    .next()
-                    .unwrap()
-                    .unwrap()
-                    .path()
-                    .unwrap()
-                    .as_os_str()
-                    .eq("src/DummyFauxTest.cls"));
-            }
-            Err(e) => panic!("{:?}", e),
-        }
+        Archiver::new(&app_dir).create(&archive_file_path).unwrap();
+
+        let tar = XzDecoder::new(File::open(archive_file_path).unwrap());
+        let mut archive = Archive::new(tar);
+        let paths: Vec<PathBuf> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().into_owned())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("./"),
+                PathBuf::from("src"),
+                PathBuf::from("src/Dummy.apex"),
+                PathBuf::from("src/DummyFauxTest.cls"),
+                PathBuf::from("src/DummyTest.cls"),
+            ]
+        );
     }
 }
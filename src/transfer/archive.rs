@@ -0,0 +1,917 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use tar::{Archive, EntryType};
+
+use crate::transfer::TransferError;
+
+/// The number of bytes needed to detect the format of an archive, see [`ArchiveFormat::detect`].
+const MAGIC_BYTES_LEN: usize = 262;
+
+/// The maximum length of symbolic link targets in zip archives, `PATH_MAX` on Linux.
+const MAX_LINK_NAME_LEN: u64 = 4096;
+
+/// Archive formats supported for extraction.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    TarXz,
+    TarBz2,
+    TarZst,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Detects the archive format from the first bytes of an archive.
+    ///
+    /// Compressed archives are assumed to contain a tar archive. Detecting a plain tar archive
+    /// requires at least 262 bytes.
+    ///
+    /// # Examples
+    /// ```
+    /// use libcnb::transfer::ArchiveFormat;
+    ///
+    /// assert_eq!(ArchiveFormat::detect(&[0x1f, 0x8b, 0x08]), Some(ArchiveFormat::TarGz));
+    /// assert_eq!(ArchiveFormat::detect(b"PK\x03\x04"), Some(ArchiveFormat::Zip));
+    /// assert_eq!(ArchiveFormat::detect(b"#!/bin/sh"), None);
+    /// ```
+    pub fn detect(header: &[u8]) -> Option<ArchiveFormat> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(ArchiveFormat::TarXz)
+        } else if header.starts_with(b"BZh") {
+            Some(ArchiveFormat::TarBz2)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(ArchiveFormat::TarZst)
+        } else if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if header.get(257..262) == Some(b"ustar") {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Determines how archive entries outside of the [`ExtractOptions::prefix`] are handled.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EntriesOutsidePrefix {
+    /// Fail with [`TransferError::EntryOutsidePrefix`].
+    Error,
+
+    /// Do not extract entries outside of the prefix.
+    Skip,
+}
+
+/// Options for [`extract_archive`].
+///
+/// Extraction always rejects entries with absolute paths or `..` components and symbolic and
+/// hard links that point outside of the destination directory. The default limits on the total
+/// size and number of entries are generous enough for any language runtime, but protect builds
+/// from archives that would fill up the disk.
+///
+/// # Example
+/// ```
+/// use libcnb::transfer::{EntriesOutsidePrefix, ExtractOptions};
+///
+/// let options = ExtractOptions::new()
+///     .prefix("jdk-17.0.1")
+///     .entries_outside_prefix(EntriesOutsidePrefix::Skip)
+///     .max_size(1024 * 1024 * 1024)
+///     .max_entries(100_000);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    prefix: Option<PathBuf>,
+    entries_outside_prefix: EntriesOutsidePrefix,
+    format: Option<ArchiveFormat>,
+    max_size: u64,
+    max_entries: u64,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            prefix: None,
+            entries_outside_prefix: EntriesOutsidePrefix::Error,
+            format: None,
+            max_size: ExtractOptions::DEFAULT_MAX_SIZE,
+            max_entries: ExtractOptions::DEFAULT_MAX_ENTRIES,
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// The default limit for the total size of all extracted files: 16 GiB.
+    pub const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024 * 1024;
+
+    /// The default limit for the number of archive entries: one million.
+    pub const DEFAULT_MAX_ENTRIES: u64 = 1_000_000;
+
+    pub fn new() -> Self {
+        ExtractOptions::default()
+    }
+
+    /// Only extracts the archive entries inside `prefix`, with the prefix stripped from their
+    /// paths.
+    #[must_use]
+    pub fn prefix(mut self, prefix: impl Into<PathBuf>) -> Self {
+        self.prefix = Some(normalize_relative_path(&prefix.into()));
+        self
+    }
+
+    /// Sets how entries outside of the prefix are handled, [`EntriesOutsidePrefix::Error`] by
+    /// default.
+    #[must_use]
+    pub fn entries_outside_prefix(mut self, entries_outside_prefix: EntriesOutsidePrefix) -> Self {
+        self.entries_outside_prefix = entries_outside_prefix;
+        self
+    }
+
+    /// Sets the format of the archive instead of detecting it from its contents.
+    #[must_use]
+    pub fn format(mut self, format: ArchiveFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Sets the maximum total size of all extracted files in bytes.
+    #[must_use]
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets the maximum number of entries in the archive, including directories and links.
+    #[must_use]
+    pub fn max_entries(mut self, max_entries: u64) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+}
+
+/// Extracts an archive into `dst`
+///
+/// The format is detected from the contents unless it is set in the options, see
+/// [`ArchiveFormat::detect`]. Symbolic links and permissions of the archive entries are preserved.
+/// See [`ExtractOptions`] for the safety checks applied to the archive entries.
+///
+/// Zip archives cannot be read sequentially, they are buffered in an anonymous temporary file
+/// first.
+///
+/// # Example
+/// ```no_run
+/// use libcnb::transfer::{extract_archive, ExtractOptions};
+///
+/// let archive = std::fs::File::open("ruby-3.0.2.tgz").unwrap();
+/// extract_archive(archive, "/layers/ruby", &ExtractOptions::new().prefix("ruby-3.0.2")).unwrap();
+/// ```
+pub fn extract_archive(
+    content: impl Read,
+    dst: impl AsRef<Path>,
+    options: &ExtractOptions,
+) -> Result<(), TransferError> {
+    let mut content = content;
+
+    let mut header = Vec::with_capacity(MAGIC_BYTES_LEN);
+    (&mut content)
+        .take(MAGIC_BYTES_LEN as u64)
+        .read_to_end(&mut header)?;
+
+    let format = match options.format {
+        Some(format) => format,
+        None => ArchiveFormat::detect(&header).ok_or(TransferError::UnknownArchiveFormat)?,
+    };
+
+    // The header has been consumed already and is put in front of the remaining content again.
+    let content = Cursor::new(header).chain(content);
+    let mut extractor = Extractor::new(dst.as_ref(), options)?;
+
+    match format {
+        ArchiveFormat::Tar => extract_tar(content, &mut extractor),
+        ArchiveFormat::TarGz => extract_tar(flate2::read::GzDecoder::new(content), &mut extractor),
+        ArchiveFormat::TarXz => extract_tar(xz::read::XzDecoder::new(content), &mut extractor),
+        ArchiveFormat::TarBz2 => extract_tar(bzip2::read::BzDecoder::new(content), &mut extractor),
+        ArchiveFormat::TarZst => extract_tar(zstd::Decoder::new(content)?, &mut extractor),
+        ArchiveFormat::Zip => {
+            let mut file = tempfile::tempfile()?;
+            io::copy(&mut { content }, &mut file)?;
+            file.seek(SeekFrom::Start(0))?;
+
+            extract_zip(BufReader::new(file), &mut extractor)
+        }
+    }?;
+
+    extractor.finish()
+}
+
+fn extract_tar(content: impl Read, extractor: &mut Extractor) -> Result<(), TransferError> {
+    let mut archive = Archive::new(content);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+
+        // Pax global headers, devices, FIFOs and other special entries are never extracted. They
+        // are skipped before their paths are checked, `git archive` adds a pax global header
+        // outside of its prefix for example.
+        if !matches!(
+            entry_type,
+            EntryType::Directory
+                | EntryType::Symlink
+                | EntryType::Link
+                | EntryType::Regular
+                | EntryType::Continuous
+                | EntryType::GNUSparse
+        ) {
+            continue;
+        }
+
+        let path = entry.path()?.into_owned();
+        let relative_path = match extractor.relative_path(&path)? {
+            Some(relative_path) => relative_path,
+            None => continue,
+        };
+
+        match entry_type {
+            EntryType::Directory => extractor.directory(&relative_path)?,
+            EntryType::Symlink | EntryType::Link => {
+                let link_name = entry
+                    .link_name()?
+                    .map(|link_name| link_name.into_owned())
+                    .ok_or_else(|| TransferError::UnsafeEntryPath(path.clone()))?;
+
+                if entry_type == EntryType::Symlink {
+                    extractor.symlink(&relative_path, &link_name)?;
+                } else {
+                    extractor.hard_link(&relative_path, &link_name)?;
+                }
+            }
+            _ => {
+                let mode = entry.header().mode().ok();
+                let mtime = entry.header().mtime().ok();
+                extractor.file(&relative_path, &mut entry, mode, mtime)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_zip(content: impl Read + Seek, extractor: &mut Extractor) -> Result<(), TransferError> {
+    let mut archive = zip::ZipArchive::new(content)?;
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let path = PathBuf::from(entry.name());
+
+        let relative_path = match extractor.relative_path(&path)? {
+            Some(relative_path) => relative_path,
+            None => continue,
+        };
+
+        let mode = entry.unix_mode();
+        if entry.is_dir() {
+            extractor.directory(&relative_path)?;
+        } else if mode.map_or(false, |mode| mode & 0o170_000 == 0o120_000) {
+            // The link target is the content of the entry, its length is limited like any path.
+            let mut link_name = String::new();
+            (&mut entry)
+                .take(MAX_LINK_NAME_LEN + 1)
+                .read_to_string(&mut link_name)?;
+
+            if link_name.len() as u64 > MAX_LINK_NAME_LEN {
+                return Err(TransferError::UnsafeLink {
+                    path: relative_path,
+                    target: PathBuf::from(link_name),
+                });
+            }
+
+            extractor.symlink(&relative_path, Path::new(&link_name))?;
+        } else {
+            extractor.file(&relative_path, &mut entry, mode, None)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes archive entries into the destination directory, enforcing the safety checks and limits
+/// of the [`ExtractOptions`].
+struct Extractor<'a> {
+    /// The canonicalized destination directory.
+    dst: PathBuf,
+    options: &'a ExtractOptions,
+    entries: u64,
+    size: u64,
+    symlinks: Vec<PathBuf>,
+}
+
+impl<'a> Extractor<'a> {
+    fn new(dst: &Path, options: &'a ExtractOptions) -> Result<Self, TransferError> {
+        fs::create_dir_all(dst)?;
+
+        Ok(Extractor {
+            dst: fs::canonicalize(dst)?,
+            options,
+            entries: 0,
+            size: 0,
+            symlinks: Vec::new(),
+        })
+    }
+
+    /// Determines the path of an entry relative to the destination directory, `None` if the entry
+    /// should not be extracted.
+    fn relative_path(&mut self, path: &Path) -> Result<Option<PathBuf>, TransferError> {
+        self.entries += 1;
+        if self.entries > self.options.max_entries {
+            return Err(TransferError::TooManyEntries(self.options.max_entries));
+        }
+
+        if !is_safe_relative_path(path) {
+            return Err(TransferError::UnsafeEntryPath(path.to_path_buf()));
+        }
+
+        let path = normalize_relative_path(path);
+        let relative_path = match &self.options.prefix {
+            Some(prefix) => match path.strip_prefix(prefix) {
+                Ok(relative_path) => relative_path.to_path_buf(),
+                Err(_) => match self.options.entries_outside_prefix {
+                    EntriesOutsidePrefix::Error => {
+                        return Err(TransferError::EntryOutsidePrefix(path))
+                    }
+                    EntriesOutsidePrefix::Skip => return Ok(None),
+                },
+            },
+            None => path,
+        };
+
+        if relative_path.as_os_str().is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(relative_path))
+        }
+    }
+
+    fn directory(&self, relative_path: &Path) -> Result<(), TransferError> {
+        let target_path = self.target_path(relative_path)?;
+        fs::create_dir_all(target_path)?;
+        Ok(())
+    }
+
+    fn file(
+        &mut self,
+        relative_path: &Path,
+        content: &mut impl Read,
+        mode: Option<u32>,
+        mtime: Option<u64>,
+    ) -> Result<(), TransferError> {
+        let target_path = self.prepare_target_path(relative_path)?;
+
+        let remaining = self.options.max_size - self.size;
+        let written = io::copy(
+            &mut content.take(remaining + 1),
+            &mut File::create(&target_path)?,
+        )?;
+
+        if written > remaining {
+            return Err(TransferError::ArchiveTooLarge(self.options.max_size));
+        }
+        self.size += written;
+
+        #[cfg(target_family = "unix")]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&target_path, fs::Permissions::from_mode(mode & 0o777))?;
+        }
+
+        if let Some(mtime) = mtime {
+            filetime::set_file_mtime(
+                &target_path,
+                filetime::FileTime::from_unix_time(i64::try_from(mtime).unwrap_or(0), 0),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn symlink(&mut self, relative_path: &Path, link_name: &Path) -> Result<(), TransferError> {
+        let target_path = self.prepare_target_path(relative_path)?;
+
+        if !self.resolves_inside(&target_path, link_name) {
+            return Err(TransferError::UnsafeLink {
+                path: relative_path.to_path_buf(),
+                target: link_name.to_path_buf(),
+            });
+        }
+
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::symlink(link_name, &target_path)?;
+
+        self.symlinks.push(target_path);
+        Ok(())
+    }
+
+    fn hard_link(&mut self, relative_path: &Path, link_name: &Path) -> Result<(), TransferError> {
+        let unsafe_link = || TransferError::UnsafeLink {
+            path: relative_path.to_path_buf(),
+            target: link_name.to_path_buf(),
+        };
+
+        // Hard link targets are paths inside the archive, the prefix applies to them as well.
+        if !is_safe_relative_path(link_name) {
+            return Err(unsafe_link());
+        }
+
+        let link_name = normalize_relative_path(link_name);
+        let source_relative_path = match &self.options.prefix {
+            Some(prefix) => link_name.strip_prefix(prefix).map_err(|_| unsafe_link())?,
+            None => &link_name,
+        };
+
+        let source_path = self.target_path(source_relative_path)?;
+        let target_path = self.prepare_target_path(relative_path)?;
+        fs::hard_link(source_path, target_path)?;
+
+        Ok(())
+    }
+
+    /// Validates the target path of an entry and removes an existing file at that path.
+    ///
+    /// Existing files are removed instead of overwritten, they might be symbolic links.
+    fn prepare_target_path(&self, relative_path: &Path) -> Result<PathBuf, TransferError> {
+        let target_path = self.target_path(relative_path)?;
+
+        if let Some(parent) = target_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        match fs::symlink_metadata(&target_path) {
+            Ok(metadata) if !metadata.is_dir() => fs::remove_file(&target_path)?,
+            _ => {}
+        }
+
+        Ok(target_path)
+    }
+
+    /// Joins the relative path to the destination directory, ensuring that the nearest existing
+    /// ancestor does not resolve to a location outside of the destination directory through
+    /// symbolic links.
+    fn target_path(&self, relative_path: &Path) -> Result<PathBuf, TransferError> {
+        let target_path = self.dst.join(relative_path);
+
+        let existing_ancestor = target_path
+            .parent()
+            .into_iter()
+            .flat_map(Path::ancestors)
+            .find(|ancestor| ancestor.exists())
+            .map(fs::canonicalize)
+            .transpose()?;
+
+        match existing_ancestor {
+            Some(ancestor) if ancestor.starts_with(&self.dst) => Ok(target_path),
+            _ => Err(TransferError::UnsafeEntryPath(relative_path.to_path_buf())),
+        }
+    }
+
+    /// Checks whether a symbolic link at `path` pointing to `link_name` resolves to a location
+    /// inside the destination directory.
+    ///
+    /// Existing symbolic links along the way are resolved, dangling links are resolved lexically.
+    fn resolves_inside(&self, path: &Path, link_name: &Path) -> bool {
+        let mut resolved = match path.parent().map(fs::canonicalize) {
+            Some(Ok(parent)) => parent,
+            _ => return false,
+        };
+
+        for component in link_name.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if !resolved.pop() {
+                        return false;
+                    }
+                }
+                Component::Normal(name) => {
+                    resolved.push(name);
+
+                    let is_symlink = fs::symlink_metadata(&resolved)
+                        .map_or(false, |metadata| metadata.file_type().is_symlink());
+                    if is_symlink {
+                        if let Ok(canonical) = fs::canonicalize(&resolved) {
+                            resolved = canonical;
+                        }
+                    }
+                }
+                Component::RootDir | Component::Prefix(_) => return false,
+            }
+        }
+
+        resolved.starts_with(&self.dst)
+    }
+
+    /// Checks all extracted symbolic links again.
+    ///
+    /// Links are checked when they are created, but later entries can change what they resolve
+    /// to, for example by replacing a directory with a symbolic link.
+    fn finish(self) -> Result<(), TransferError> {
+        for path in &self.symlinks {
+            let link_name = fs::read_link(path)?;
+            if !self.resolves_inside(path, &link_name) {
+                fs::remove_file(path)?;
+
+                return Err(TransferError::UnsafeLink {
+                    path: path.strip_prefix(&self.dst).unwrap_or(path).to_path_buf(),
+                    target: link_name,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that the path is relative and has no `..` components.
+fn is_safe_relative_path(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Removes all `.` components from the path.
+fn normalize_relative_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tar_xz;
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn tar_with_executable_and_symlink() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(9);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "node/bin/node", &b"#!/bin/sh"[..])
+            .unwrap();
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        builder
+            .append_link(&mut header, "node/bin/nodejs", "node")
+            .unwrap();
+
+        builder.into_inner().unwrap()
+    }
+
+    fn assert_extracted(dst: &Path) {
+        let node = dst.join("bin/node");
+        assert_eq!(fs::read_to_string(&node).unwrap(), "#!/bin/sh");
+        assert_eq!(
+            fs::metadata(&node).unwrap().permissions().mode() & 0o111,
+            0o111
+        );
+        assert_eq!(
+            fs::read_link(dst.join("bin/nodejs")).unwrap(),
+            Path::new("node")
+        );
+    }
+
+    #[test]
+    fn it_detects_and_extracts_compressed_tars() {
+        let tar = tar_with_executable_and_symlink();
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&tar).unwrap();
+        let mut bz2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bz2.write_all(&tar).unwrap();
+        let mut xz = xz::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&tar).unwrap();
+
+        let archives = vec![
+            (ArchiveFormat::Tar, tar.clone()),
+            (ArchiveFormat::TarGz, gz.finish().unwrap()),
+            (ArchiveFormat::TarBz2, bz2.finish().unwrap()),
+            (ArchiveFormat::TarXz, xz.finish().unwrap()),
+            (
+                ArchiveFormat::TarZst,
+                zstd::encode_all(&tar[..], 0).unwrap(),
+            ),
+        ];
+
+        for (format, archive) in archives {
+            assert_eq!(ArchiveFormat::detect(&archive), Some(format));
+
+            let tmpdir = tempfile::tempdir().unwrap();
+            extract_archive(
+                &archive[..],
+                tmpdir.path(),
+                &ExtractOptions::new().prefix("node"),
+            )
+            .unwrap();
+            assert_extracted(tmpdir.path());
+        }
+    }
+
+    /// Sets the file type of a zip entry to symbolic link, the zip writer cannot create them.
+    fn mark_as_symlink(archive: &mut [u8], name: &str) {
+        let mut offset = 0;
+        while let Some(position) = archive[offset..]
+            .windows(4)
+            .position(|window| window == b"PK\x01\x02")
+        {
+            let header = offset + position;
+            let name_len = usize::from(u16::from_le_bytes([
+                archive[header + 28],
+                archive[header + 29],
+            ]));
+            if &archive[header + 46..header + 46 + name_len] == name.as_bytes() {
+                archive[header + 38..header + 42]
+                    .copy_from_slice(&(0o120_777_u32 << 16).to_le_bytes());
+                return;
+            }
+
+            offset = header + 4;
+        }
+
+        panic!("No central directory entry for {}", name);
+    }
+
+    #[test]
+    fn it_extracts_zip() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.add_directory("node/bin/", zip::write::FileOptions::default())
+            .unwrap();
+        zip.start_file(
+            "node/bin/node",
+            zip::write::FileOptions::default().unix_permissions(0o755),
+        )
+        .unwrap();
+        zip.write_all(b"#!/bin/sh").unwrap();
+        zip.start_file("node/bin/nodejs", zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(b"node").unwrap();
+        let mut archive = zip.finish().unwrap().into_inner();
+        mark_as_symlink(&mut archive, "node/bin/nodejs");
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        extract_archive(
+            &archive[..],
+            tmpdir.path(),
+            &ExtractOptions::new().prefix("node"),
+        )
+        .unwrap();
+        assert_extracted(tmpdir.path());
+    }
+
+    #[test]
+    fn it_uses_format_override() {
+        let archive = tar_xz(&[("README", "Node.js")]);
+        let tmpdir = tempfile::tempdir().unwrap();
+
+        assert!(extract_archive(
+            &archive[..],
+            tmpdir.path(),
+            &ExtractOptions::new().format(ArchiveFormat::TarGz)
+        )
+        .is_err());
+
+        extract_archive(
+            &archive[..],
+            tmpdir.path(),
+            &ExtractOptions::new().format(ArchiveFormat::TarXz),
+        )
+        .unwrap();
+        assert!(tmpdir.path().join("README").exists());
+
+        assert!(matches!(
+            extract_archive(
+                &b"not an archive"[..],
+                tmpdir.path(),
+                &ExtractOptions::new()
+            ),
+            Err(TransferError::UnknownArchiveFormat)
+        ));
+    }
+
+    /// Builds a tar archive without the path validation of [`tar::Builder`], entries are either
+    /// regular files with contents or links with a target.
+    fn raw_tar(entries: &[(tar::EntryType, &str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        for (entry_type, path, value) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o644);
+
+            let data = if entry_type.is_file() {
+                value.as_bytes()
+            } else {
+                header.as_old_mut().linkname[..value.len()].copy_from_slice(value.as_bytes());
+                &[]
+            };
+
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn it_rejects_path_traversal() {
+        for path in ["../escaped", "bin/../../escaped", "/tmp/escaped"] {
+            let tmpdir = tempfile::tempdir().unwrap();
+            let dst = tmpdir.path().join("dst");
+            let archive = raw_tar(&[(tar::EntryType::Regular, path, "escaped")]);
+
+            assert!(matches!(
+                extract_archive(&archive[..], &dst, &ExtractOptions::new()),
+                Err(TransferError::UnsafeEntryPath(_))
+            ));
+            assert!(!tmpdir.path().join("escaped").exists());
+        }
+    }
+
+    #[test]
+    fn it_rejects_symlinks_escaping_destination() {
+        let escaping = [
+            ("absolute", "/etc/passwd"),
+            ("parent", "../outside"),
+            ("nested", "bin/../../outside"),
+        ];
+
+        for (name, target) in escaping {
+            let tmpdir = tempfile::tempdir().unwrap();
+            let archive = raw_tar(&[(tar::EntryType::Symlink, name, target)]);
+
+            assert!(
+                matches!(
+                    extract_archive(&archive[..], tmpdir.path(), &ExtractOptions::new()),
+                    Err(TransferError::UnsafeLink { .. })
+                ),
+                "{} was extracted",
+                target
+            );
+        }
+
+        // A file written through a symbolic link to a directory outside the destination.
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dst = tmpdir.path().join("dst");
+        let archive = raw_tar(&[
+            (tar::EntryType::Symlink, "lib", ".."),
+            (tar::EntryType::Regular, "lib/escaped", "escaped"),
+        ]);
+        assert!(extract_archive(&archive[..], &dst, &ExtractOptions::new()).is_err());
+        assert!(!tmpdir.path().join("escaped").exists());
+
+        // Symbolic links inside the destination are fine, even through other symbolic links.
+        let tmpdir = tempfile::tempdir().unwrap();
+        let archive = raw_tar(&[
+            (tar::EntryType::Regular, "lib/libnode.so.17", "ELF"),
+            (tar::EntryType::Symlink, "lib64", "lib"),
+            (
+                tar::EntryType::Symlink,
+                "bin/libnode.so",
+                "../lib64/libnode.so.17",
+            ),
+            (
+                tar::EntryType::Link,
+                "bin/libnode.hard",
+                "lib/libnode.so.17",
+            ),
+        ]);
+        extract_archive(&archive[..], tmpdir.path(), &ExtractOptions::new()).unwrap();
+        assert_eq!(
+            fs::read_to_string(tmpdir.path().join("bin/libnode.so")).unwrap(),
+            "ELF"
+        );
+        assert_eq!(
+            fs::read_to_string(tmpdir.path().join("bin/libnode.hard")).unwrap(),
+            "ELF"
+        );
+    }
+
+    #[test]
+    fn it_rejects_hard_links_escaping_destination() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let archive = raw_tar(&[(tar::EntryType::Link, "passwd", "../../etc/passwd")]);
+
+        assert!(matches!(
+            extract_archive(&archive[..], tmpdir.path(), &ExtractOptions::new()),
+            Err(TransferError::UnsafeLink { .. })
+        ));
+        assert!(!tmpdir.path().join("passwd").exists());
+    }
+
+    #[test]
+    fn it_skips_entries_outside_prefix() {
+        let archive = tar_xz(&[("node/README", "Node.js"), ("pax_global_header", "")]);
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            extract_archive(
+                &archive[..],
+                tmpdir.path(),
+                &ExtractOptions::new().prefix("node")
+            ),
+            Err(TransferError::EntryOutsidePrefix(_))
+        ));
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        extract_archive(
+            &archive[..],
+            tmpdir.path(),
+            &ExtractOptions::new()
+                .prefix("./node")
+                .entries_outside_prefix(EntriesOutsidePrefix::Skip),
+        )
+        .unwrap();
+        assert!(tmpdir.path().join("README").exists());
+        assert!(!tmpdir.path().join("pax_global_header").exists());
+    }
+
+    #[test]
+    fn it_ignores_special_entries_before_checking_the_prefix() {
+        // Like `git archive --prefix=node/`, with a FIFO that is never extracted either.
+        let archive = raw_tar(&[
+            (tar::EntryType::XGlobalHeader, "pax_global_header", ""),
+            (tar::EntryType::Fifo, "fifo", ""),
+            (tar::EntryType::Regular, "node/README", "Node.js"),
+        ]);
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        extract_archive(
+            &archive[..],
+            tmpdir.path(),
+            &ExtractOptions::new().prefix("node").max_entries(1),
+        )
+        .unwrap();
+        assert!(tmpdir.path().join("README").exists());
+        assert!(!tmpdir.path().join("fifo").exists());
+    }
+
+    #[test]
+    fn it_rejects_overlong_zip_symlinks() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("link", zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all("a/".repeat(4096).as_bytes()).unwrap();
+        let mut archive = zip.finish().unwrap().into_inner();
+        mark_as_symlink(&mut archive, "link");
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            extract_archive(&archive[..], tmpdir.path(), &ExtractOptions::new()),
+            Err(TransferError::UnsafeLink { .. })
+        ));
+        assert!(!tmpdir.path().join("link").exists());
+    }
+
+    #[test]
+    fn it_enforces_limits() {
+        let archive = tar_xz(&[("a", "1234"), ("b", "5678"), ("c", "90")]);
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            extract_archive(
+                &archive[..],
+                tmpdir.path(),
+                &ExtractOptions::new().max_size(9)
+            ),
+            Err(TransferError::ArchiveTooLarge(9))
+        ));
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            extract_archive(
+                &archive[..],
+                tmpdir.path(),
+                &ExtractOptions::new().max_entries(2)
+            ),
+            Err(TransferError::TooManyEntries(2))
+        ));
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        extract_archive(
+            &archive[..],
+            tmpdir.path(),
+            &ExtractOptions::new().max_size(10).max_entries(3),
+        )
+        .unwrap();
+    }
+}
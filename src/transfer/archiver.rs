@@ -0,0 +1,596 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use glob::{MatchOptions, Pattern};
+
+use crate::layer_normalization::resolve_source_date_epoch;
+use crate::transfer::{ArchiveFormat, TransferError};
+
+/// Glob matching options: `*` does not match `/`, `**` matches any number of directories.
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Creates reproducible archives of a directory.
+///
+/// Entries are sorted by path, their modification times are normalized and their owner is
+/// `root`. Files are archived with mode `0755` if they are executable and `0644` otherwise,
+/// directories with mode `0755`. Symbolic links are archived as links. Archiving the same
+/// directory contents twice yields byte-identical archives.
+///
+/// Globs are matched against paths relative to the source directory, with `/` as separator.
+/// `*` does not match `/`, `**` matches any number of directories.
+///
+/// # Example
+/// ```no_run
+/// use libcnb::transfer::{ArchiveFormat, Archiver};
+///
+/// Archiver::new("/workspace")
+///     .exclude("target")
+///     .exclude("**/*.log")
+///     .respect_gitignore(true)
+///     .format(ArchiveFormat::TarGz)
+///     .level(9)
+///     .create("/tmp/source.tar.gz")
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Archiver {
+    source_dir: PathBuf,
+    includes: Vec<String>,
+    excludes: Vec<String>,
+    respect_gitignore: bool,
+    format: ArchiveFormat,
+    level: Option<u32>,
+    mtime: Option<SystemTime>,
+}
+
+impl Archiver {
+    pub fn new(source_dir: impl Into<PathBuf>) -> Self {
+        Archiver {
+            source_dir: source_dir.into(),
+            includes: Vec::new(),
+            excludes: Vec::new(),
+            respect_gitignore: false,
+            format: ArchiveFormat::TarXz,
+            level: None,
+            mtime: None,
+        }
+    }
+
+    /// Only archives files matching at least one include glob. Directories are archived if
+    /// they contain an included file. Without include globs, all files are archived.
+    #[must_use]
+    pub fn include(mut self, glob: impl Into<String>) -> Self {
+        self.includes.push(glob.into());
+        self
+    }
+
+    /// Never archives files and directories matching the glob, including the contents of
+    /// matching directories.
+    #[must_use]
+    pub fn exclude(mut self, glob: impl Into<String>) -> Self {
+        self.excludes.push(glob.into());
+        self
+    }
+
+    /// Excludes the `.git` directory and all files ignored by `.gitignore` files in the source
+    /// directory and its subdirectories.
+    ///
+    /// Blank lines, comments, negation with `!`, anchoring with a leading `/` and directory-only
+    /// patterns with a trailing `/` are supported. Global and repository-wide exclude files
+    /// of git are not read.
+    #[must_use]
+    pub fn respect_gitignore(mut self, respect_gitignore: bool) -> Self {
+        self.respect_gitignore = respect_gitignore;
+        self
+    }
+
+    /// Sets the format of the archive, [`ArchiveFormat::TarXz`] by default.
+    ///
+    /// Zip archives are not supported.
+    #[must_use]
+    pub fn format(mut self, format: ArchiveFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the compression level. Uses the default level of the format when not set: 6 for
+    /// gzip, xz and bzip2 and 3 for zstd.
+    #[must_use]
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Sets the modification time of all entries.
+    ///
+    /// When not set, the timestamp (in seconds since the Unix epoch) is read from the
+    /// `SOURCE_DATE_EPOCH` environment variable, falling back to
+    /// [`DEFAULT_SOURCE_DATE_EPOCH`](crate::layer_normalization::DEFAULT_SOURCE_DATE_EPOCH).
+    #[must_use]
+    pub fn mtime(mut self, mtime: SystemTime) -> Self {
+        self.mtime = Some(mtime);
+        self
+    }
+
+    /// The paths of all entries that will be archived, relative to the source directory and in
+    /// archive order.
+    pub fn entries(&self) -> Result<Vec<PathBuf>, TransferError> {
+        let includes = compile_globs(&self.includes)?;
+        let excludes = compile_globs(&self.excludes)?;
+
+        let mut entries = BTreeSet::new();
+        self.collect_entries(
+            Path::new(""),
+            &includes,
+            &excludes,
+            &mut Vec::new(),
+            &mut entries,
+        )?;
+
+        Ok(entries.into_iter().collect())
+    }
+
+    /// Writes the archive into a new file at `path`.
+    pub fn create(&self, path: impl AsRef<Path>) -> Result<(), TransferError> {
+        let mut file = File::create(path)?;
+        self.write_to(&mut file)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Writes the archive into `writer`.
+    pub fn write_to(&self, writer: impl Write) -> Result<(), TransferError> {
+        let entries = self.entries()?;
+
+        match self.format {
+            ArchiveFormat::Tar => {
+                self.write_tar(&entries, writer)?;
+            }
+            ArchiveFormat::TarGz => {
+                let level = flate2::Compression::new(self.level.unwrap_or(6));
+                let encoder = flate2::write::GzEncoder::new(writer, level);
+                self.write_tar(&entries, encoder)?.finish()?;
+            }
+            ArchiveFormat::TarXz => {
+                let encoder = xz::write::XzEncoder::new(writer, self.level.unwrap_or(6));
+                self.write_tar(&entries, encoder)?.finish()?;
+            }
+            ArchiveFormat::TarBz2 => {
+                let level = bzip2::Compression::new(self.level.unwrap_or(6));
+                let encoder = bzip2::write::BzEncoder::new(writer, level);
+                self.write_tar(&entries, encoder)?.finish()?;
+            }
+            ArchiveFormat::TarZst => {
+                let level = i32::try_from(self.level.unwrap_or(3)).unwrap_or(i32::MAX);
+                let encoder = zstd::Encoder::new(writer, level)?;
+                self.write_tar(&entries, encoder)?.finish()?;
+            }
+            ArchiveFormat::Zip => return Err(TransferError::UnsupportedArchiveFormat(self.format)),
+        }
+
+        Ok(())
+    }
+
+    fn write_tar<W: Write>(&self, entries: &[PathBuf], writer: W) -> io::Result<W> {
+        let mtime = self
+            .mtime
+            .unwrap_or_else(|| resolve_source_date_epoch(None))
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        let new_header = |entry_type: tar::EntryType, mode: u32, size: u64| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_mode(mode);
+            header.set_size(size);
+            header.set_mtime(mtime);
+            header.set_uid(0);
+            header.set_gid(0);
+            header
+        };
+
+        let mut builder = tar::Builder::new(writer);
+
+        let mut header = new_header(tar::EntryType::Directory, 0o755, 0);
+        builder.append_data(&mut header, "./", io::empty())?;
+
+        for entry in entries {
+            let path = self.source_dir.join(entry);
+            let metadata = fs::symlink_metadata(&path)?;
+
+            if metadata.file_type().is_symlink() {
+                let mut header = new_header(tar::EntryType::Symlink, 0o777, 0);
+                builder.append_link(&mut header, entry, fs::read_link(&path)?)?;
+            } else if metadata.is_dir() {
+                let mut header = new_header(tar::EntryType::Directory, 0o755, 0);
+                builder.append_data(&mut header, entry, io::empty())?;
+            } else {
+                let mode = if is_executable(&metadata) {
+                    0o755
+                } else {
+                    0o644
+                };
+                let mut header = new_header(tar::EntryType::Regular, mode, metadata.len());
+                builder.append_data(&mut header, entry, File::open(&path)?)?;
+            }
+        }
+
+        builder.into_inner()
+    }
+
+    /// Collects the archived entries of the directory at `relative_dir`. Returns whether any
+    /// entry was collected.
+    fn collect_entries(
+        &self,
+        relative_dir: &Path,
+        includes: &[Pattern],
+        excludes: &[Pattern],
+        gitignores: &mut Vec<GitIgnore>,
+        entries: &mut BTreeSet<PathBuf>,
+    ) -> Result<bool, TransferError> {
+        let dir = self.source_dir.join(relative_dir);
+
+        let pushed_gitignore = if self.respect_gitignore {
+            match GitIgnore::read(&dir, relative_dir)? {
+                Some(gitignore) => {
+                    gitignores.push(gitignore);
+                    true
+                }
+                None => false,
+            }
+        } else {
+            false
+        };
+
+        let mut collected = false;
+        for dir_entry in fs::read_dir(&dir)? {
+            let dir_entry = dir_entry?;
+            let relative_path = relative_dir.join(dir_entry.file_name());
+            let is_dir = dir_entry.file_type()?.is_dir();
+
+            let skipped = excludes
+                .iter()
+                .any(|glob| glob.matches_path_with(&relative_path, MATCH_OPTIONS))
+                || (self.respect_gitignore
+                    && (relative_path.file_name() == Some(".git".as_ref())
+                        || is_ignored(gitignores, &relative_path, is_dir)));
+            if skipped {
+                continue;
+            }
+
+            if is_dir {
+                let has_entries =
+                    self.collect_entries(&relative_path, includes, excludes, gitignores, entries)?;
+
+                // Empty directories are kept unless only included files are archived.
+                if has_entries || includes.is_empty() {
+                    entries.insert(relative_path);
+                    collected = true;
+                }
+            } else if includes.is_empty()
+                || includes
+                    .iter()
+                    .any(|glob| glob.matches_path_with(&relative_path, MATCH_OPTIONS))
+            {
+                entries.insert(relative_path);
+                collected = true;
+            }
+        }
+
+        if pushed_gitignore {
+            gitignores.pop();
+        }
+
+        Ok(collected)
+    }
+}
+
+/// The rules of a single `.gitignore` file.
+#[derive(Debug)]
+struct GitIgnore {
+    /// The directory of the `.gitignore` file, relative to the source directory.
+    base: PathBuf,
+    rules: Vec<GitIgnoreRule>,
+}
+
+#[derive(Debug)]
+struct GitIgnoreRule {
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+    /// Whether the pattern is matched against the whole relative path instead of the file name.
+    anchored: bool,
+}
+
+impl GitIgnore {
+    fn read(dir: &Path, base: &Path) -> Result<Option<GitIgnore>, TransferError> {
+        let contents = match fs::read_to_string(dir.join(".gitignore")) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut rules = Vec::new();
+        for line in contents.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let line = line.trim_start_matches('/');
+
+            let pattern =
+                Pattern::new(line).map_err(|_| TransferError::InvalidGlob(String::from(line)))?;
+
+            rules.push(GitIgnoreRule {
+                pattern,
+                negated,
+                dir_only,
+                anchored,
+            });
+        }
+
+        Ok(Some(GitIgnore {
+            base: base.to_path_buf(),
+            rules,
+        }))
+    }
+
+    /// Returns whether the path is ignored or explicitly not ignored by this file, `None` if no
+    /// rule matches.
+    fn matches(&self, relative_path: &Path, is_dir: bool) -> Option<bool> {
+        let path = relative_path.strip_prefix(&self.base).ok()?;
+
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                if rule.dir_only && !is_dir {
+                    return false;
+                }
+
+                if rule.anchored {
+                    rule.pattern.matches_path_with(path, MATCH_OPTIONS)
+                } else {
+                    path.file_name().map_or(false, |name| {
+                        rule.pattern
+                            .matches_path_with(Path::new(name), MATCH_OPTIONS)
+                    })
+                }
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// Deeper `.gitignore` files take precedence over the ones in parent directories.
+fn is_ignored(gitignores: &[GitIgnore], relative_path: &Path, is_dir: bool) -> bool {
+    gitignores
+        .iter()
+        .rev()
+        .find_map(|gitignore| gitignore.matches(relative_path, is_dir))
+        .unwrap_or(false)
+}
+
+fn compile_globs(globs: &[String]) -> Result<Vec<Pattern>, TransferError> {
+    globs
+        .iter()
+        .map(|glob| Pattern::new(glob).map_err(|_| TransferError::InvalidGlob(glob.clone())))
+        .collect()
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn source_dir() -> tempfile::TempDir {
+        let tmpdir = tempfile::tempdir().unwrap();
+        for (path, contents) in [
+            ("src/main.rs", "fn main() {}"),
+            ("src/lib.rs", ""),
+            ("target/debug/app", "ELF"),
+            (".git/HEAD", "ref: refs/heads/main"),
+            ("logs/build.log", "log"),
+            ("logs/keep.log", "log"),
+            ("docs/README.md", "docs"),
+            (
+                ".gitignore",
+                "/target\n*.log\n!keep.log\n# comment\n\ndocs/\n",
+            ),
+        ] {
+            let path = tmpdir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        fs::create_dir_all(tmpdir.path().join("empty")).unwrap();
+        tmpdir
+    }
+
+    fn paths(entries: &[PathBuf]) -> Vec<&str> {
+        entries.iter().map(|path| path.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn it_filters_entries() {
+        let tmpdir = source_dir();
+
+        let entries = Archiver::new(tmpdir.path()).entries().unwrap();
+        assert!(paths(&entries).contains(&".git/HEAD"));
+        assert!(paths(&entries).contains(&"target/debug/app"));
+        assert!(paths(&entries).contains(&"logs/build.log"));
+
+        let entries = Archiver::new(tmpdir.path())
+            .respect_gitignore(true)
+            .entries()
+            .unwrap();
+        assert_eq!(
+            paths(&entries),
+            vec![
+                ".gitignore",
+                "empty",
+                "logs",
+                "logs/keep.log",
+                "src",
+                "src/lib.rs",
+                "src/main.rs"
+            ]
+        );
+
+        let entries = Archiver::new(tmpdir.path())
+            .include("src/*.rs")
+            .include("**/*.md")
+            .exclude("src/lib.rs")
+            .entries()
+            .unwrap();
+        assert_eq!(
+            paths(&entries),
+            vec!["docs", "docs/README.md", "src", "src/main.rs"]
+        );
+
+        assert!(matches!(
+            Archiver::new(tmpdir.path()).exclude("[").entries(),
+            Err(TransferError::InvalidGlob(_))
+        ));
+    }
+
+    #[test]
+    fn it_honors_nested_gitignore_files() {
+        let tmpdir = source_dir();
+        fs::write(tmpdir.path().join("src/.gitignore"), "lib.rs\n").unwrap();
+        fs::write(tmpdir.path().join("logs/.gitignore"), "!build.log\n").unwrap();
+
+        let entries = Archiver::new(tmpdir.path())
+            .respect_gitignore(true)
+            .entries()
+            .unwrap();
+        assert_eq!(
+            paths(&entries),
+            vec![
+                ".gitignore",
+                "empty",
+                "logs",
+                "logs/.gitignore",
+                "logs/build.log",
+                "logs/keep.log",
+                "src",
+                "src/.gitignore",
+                "src/main.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_creates_reproducible_archives() {
+        let tmpdir = source_dir();
+        let archiver = Archiver::new(tmpdir.path()).format(ArchiveFormat::Tar);
+
+        let mut first = Vec::new();
+        archiver.write_to(&mut first).unwrap();
+
+        // Neither modification times nor permissions of the source leak into the archive.
+        filetime::set_file_mtime(
+            tmpdir.path().join("src/main.rs"),
+            filetime::FileTime::from_unix_time(1_234_567_890, 0),
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(
+                tmpdir.path().join("src/lib.rs"),
+                fs::Permissions::from_mode(0o600),
+            )
+            .unwrap();
+        }
+
+        let mut second = Vec::new();
+        archiver.write_to(&mut second).unwrap();
+        assert_eq!(first, second);
+
+        let mut archive = tar::Archive::new(&first[..]);
+        for entry in archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            let header = entry.header();
+            assert_eq!(
+                header.mtime().unwrap(),
+                resolve_source_date_epoch(None)
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+            );
+            assert_eq!(header.uid().unwrap(), 0);
+            assert!(matches!(header.mode().unwrap(), 0o644 | 0o755));
+        }
+    }
+
+    #[test]
+    fn it_compresses_with_selected_format() {
+        let tmpdir = source_dir();
+
+        for format in [
+            ArchiveFormat::Tar,
+            ArchiveFormat::TarGz,
+            ArchiveFormat::TarXz,
+            ArchiveFormat::TarBz2,
+            ArchiveFormat::TarZst,
+        ] {
+            let mut archive = Vec::new();
+            Archiver::new(tmpdir.path().join("src"))
+                .format(format)
+                .level(1)
+                .write_to(&mut archive)
+                .unwrap();
+            assert_eq!(ArchiveFormat::detect(&archive), Some(format));
+
+            let dst = tempfile::tempdir().unwrap();
+            crate::transfer::extract_archive(
+                &archive[..],
+                dst.path(),
+                &crate::transfer::ExtractOptions::new(),
+            )
+            .unwrap();
+
+            let mut main = String::new();
+            File::open(dst.path().join("main.rs"))
+                .unwrap()
+                .read_to_string(&mut main)
+                .unwrap();
+            assert_eq!(main, "fn main() {}");
+        }
+
+        assert!(matches!(
+            Archiver::new(tmpdir.path())
+                .format(ArchiveFormat::Zip)
+                .write_to(io::sink()),
+            Err(TransferError::UnsupportedArchiveFormat(ArchiveFormat::Zip))
+        ));
+    }
+}
@@ -0,0 +1,408 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use sha2::Digest;
+
+use crate::transfer::Checksum;
+
+/// The name of the cache-only layer holding the [`DownloadCache`].
+pub const DOWNLOAD_CACHE_LAYER_NAME: &str = "libcnb-download-cache";
+
+/// The content metadata of the download cache layer, it is only restored for the next build.
+const LAYER_CONTENT_METADATA: &str = "[types]\ncache = true\n";
+
+/// The file name prefix of downloads that are being added to the cache.
+const STAGED_ENTRY_PREFIX: &str = ".staged-";
+
+/// Staged files that were not modified for this long were left behind by an interrupted build.
+const STALE_STAGED_ENTRY_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// A cache of verified downloads, shared across builds in the [`DOWNLOAD_CACHE_LAYER_NAME`] layer.
+///
+/// Entries are keyed by the URL and the expected checksum of a download. Only downloads with an
+/// expected checksum are cached, see [`Download::cache`](crate::transfer::Download::cache).
+/// Cached files are verified again before they are used, corrupted entries are removed and
+/// downloaded again.
+///
+/// When the total size of the cache exceeds [`DownloadCache::max_size`], the least recently used
+/// entries are evicted. libcnb reports the [`DownloadCacheStats`] at the end of the build.
+///
+/// Clones share the same state, all methods take `&self`.
+///
+/// # Example
+/// ```no_run
+/// use libcnb::{Error, GenericBuildContext, Result};
+/// use std::str::FromStr;
+/// use libcnb::transfer::{Checksum, TransferError};
+///
+/// fn build(context: GenericBuildContext) -> Result<(), TransferError> {
+///     context.download_cache.set_max_size(512 * 1024 * 1024);
+///
+///     context
+///         .download("https://nodejs.org/dist/v16.13.1/node-v16.13.1-linux-x64.tar.gz")
+///         .map_err(Error::BuildpackError)?
+///         .checksum(
+///             Checksum::from_str(
+///                 "sha256:a3721f87cecc0b52b0be8587c20776ac7305db413751db02c55aa2bffac15198",
+///             )
+///             .unwrap(),
+///         )
+///         .prefix("node-v16.13.1-linux-x64")
+///         .extract_to(context.layer_path("node"))
+///         .map_err(Error::BuildpackError)?;
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DownloadCache {
+    layer_dir: PathBuf,
+    state: Arc<Mutex<DownloadCacheState>>,
+}
+
+#[derive(Debug)]
+struct DownloadCacheState {
+    max_size: u64,
+    stats: DownloadCacheStats,
+}
+
+/// Statistics of a [`DownloadCache`] for the current build.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct DownloadCacheStats {
+    /// Downloads served from the cache.
+    pub hits: u64,
+
+    /// Downloads that were not cached, or whose cached file was corrupted.
+    pub misses: u64,
+
+    /// Bytes served from the cache.
+    pub cached_bytes: u64,
+
+    /// Bytes downloaded and added to the cache.
+    pub downloaded_bytes: u64,
+
+    /// Entries evicted because the cache exceeded its maximum size.
+    pub evictions: u64,
+}
+
+impl DownloadCache {
+    /// The default maximum size of the cache: 2 GiB.
+    pub const DEFAULT_MAX_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+
+    /// Creates a cache in the [`DOWNLOAD_CACHE_LAYER_NAME`] layer inside `layers_dir`.
+    ///
+    /// The layer is only created when the first entry is added.
+    pub fn new(layers_dir: impl AsRef<Path>) -> Self {
+        DownloadCache {
+            layer_dir: layers_dir.as_ref().join(DOWNLOAD_CACHE_LAYER_NAME),
+            state: Arc::new(Mutex::new(DownloadCacheState {
+                max_size: DownloadCache::DEFAULT_MAX_SIZE,
+                stats: DownloadCacheStats::default(),
+            })),
+        }
+    }
+
+    pub fn layer_dir(&self) -> &Path {
+        &self.layer_dir
+    }
+
+    /// Sets the maximum total size of all entries in bytes, [`DownloadCache::DEFAULT_MAX_SIZE`]
+    /// by default.
+    pub fn set_max_size(&self, max_size: u64) {
+        self.state().max_size = max_size;
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.state().max_size
+    }
+
+    pub fn stats(&self) -> DownloadCacheStats {
+        self.state().stats
+    }
+
+    /// Returns the verified cached file for the URL and checksum, positioned at the start.
+    pub(crate) fn lookup(&self, url: &str, checksum: &Checksum) -> io::Result<Option<File>> {
+        let path = self.entry_path(url, checksum);
+
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                self.state().stats.misses += 1;
+                return Ok(None);
+            }
+            Err(error) => return Err(error),
+        };
+
+        let mut hasher = checksum.algorithm().hasher();
+        let mut buffer = vec![0; 64 * 1024];
+        let mut len = 0;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+
+            hasher.update(&buffer[..read]);
+            len += read as u64;
+        }
+
+        if hasher.finish() != *checksum {
+            fs::remove_file(&path)?;
+            self.state().stats.misses += 1;
+            return Ok(None);
+        }
+
+        // The modification time records the last use for eviction.
+        filetime::set_file_mtime(&path, filetime::FileTime::now())?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut state = self.state();
+        state.stats.hits += 1;
+        state.stats.cached_bytes += len;
+
+        Ok(Some(file))
+    }
+
+    /// Adds a verified download to the cache and evicts the least recently used entries if the
+    /// cache is too large afterwards.
+    pub(crate) fn store(
+        &self,
+        url: &str,
+        checksum: &Checksum,
+        content: &mut File,
+    ) -> io::Result<()> {
+        fs::create_dir_all(&self.layer_dir)?;
+        fs::write(
+            self.layer_dir.with_extension("toml"),
+            LAYER_CONTENT_METADATA,
+        )?;
+
+        // Entries appear atomically, a concurrent lookup never sees a partially written file.
+        let mut staged = tempfile::Builder::new()
+            .prefix(STAGED_ENTRY_PREFIX)
+            .tempfile_in(&self.layer_dir)?;
+        content.seek(SeekFrom::Start(0))?;
+        let len = io::copy(content, staged.as_file_mut())?;
+        content.seek(SeekFrom::Start(0))?;
+        staged
+            .persist(self.entry_path(url, checksum))
+            .map_err(|error| error.error)?;
+
+        self.state().stats.downloaded_bytes += len;
+        self.evict()
+    }
+
+    /// Removes the least recently used entries until the cache fits into its maximum size.
+    ///
+    /// Files of downloads that are being added to the cache do not count towards its size. Those
+    /// left behind by an interrupted build are removed.
+    pub fn evict(&self) -> io::Result<()> {
+        let dir_entries = match fs::read_dir(&self.layer_dir) {
+            Ok(dir_entries) => dir_entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
+
+        let mut entries = Vec::new();
+        for entry in dir_entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let modified = metadata.modified()?;
+
+            if entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(STAGED_ENTRY_PREFIX)
+            {
+                let stale = modified
+                    .elapsed()
+                    .map_or(false, |age| age >= STALE_STAGED_ENTRY_AGE);
+
+                if stale {
+                    remove_file_if_exists(entry.path())?;
+                }
+            } else {
+                entries.push((modified, metadata.len(), entry.path()));
+            }
+        }
+
+        let max_size = self.max_size();
+        let mut size: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        entries.sort();
+
+        for (_, len, path) in entries {
+            if size <= max_size {
+                break;
+            }
+
+            fs::remove_file(path)?;
+            size -= len;
+            self.state().stats.evictions += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Prints the statistics of this build, if the cache was used.
+    pub fn report(&self) {
+        let stats = self.stats();
+        if stats.hits + stats.misses > 0 {
+            eprintln!(
+                "Download cache: {} hits ({} bytes), {} misses ({} bytes downloaded), {} evictions",
+                stats.hits,
+                stats.cached_bytes,
+                stats.misses,
+                stats.downloaded_bytes,
+                stats.evictions
+            );
+        }
+    }
+
+    fn entry_path(&self, url: &str, checksum: &Checksum) -> PathBuf {
+        let key = sha2::Sha256::digest(format!("{}\n{}", url, checksum).as_bytes());
+        self.layer_dir.join(format!("{:x}", key))
+    }
+
+    fn state(&self) -> MutexGuard<'_, DownloadCacheState> {
+        // The state cannot be left inconsistent by a panicking thread, all updates are atomic.
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Removes a file, a concurrent build might have removed it already.
+fn remove_file_if_exists(path: impl AsRef<Path>) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer::ChecksumAlgorithm;
+    use std::io::Write;
+
+    fn content(data: &[u8]) -> File {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(data).unwrap();
+        file
+    }
+
+    #[test]
+    fn it_stores_and_verifies_entries() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let cache = DownloadCache::new(tmpdir.path());
+        let url = "https://nodejs.org/dist/node.tar.gz";
+        let checksum = ChecksumAlgorithm::Sha256.digest(b"archive");
+
+        assert!(cache.lookup(url, &checksum).unwrap().is_none());
+        cache
+            .store(url, &checksum, &mut content(b"archive"))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(tmpdir.path().join("libcnb-download-cache.toml")).unwrap(),
+            LAYER_CONTENT_METADATA
+        );
+
+        let mut cached = String::new();
+        cache
+            .lookup(url, &checksum)
+            .unwrap()
+            .unwrap()
+            .read_to_string(&mut cached)
+            .unwrap();
+        assert_eq!(cached, "archive");
+
+        // Entries are keyed by the URL as well.
+        assert!(cache
+            .lookup("https://example.com/node.tar.gz", &checksum)
+            .unwrap()
+            .is_none());
+
+        // Corrupted entries are removed.
+        fs::write(cache.entry_path(url, &checksum), "corrupted").unwrap();
+        assert!(cache.lookup(url, &checksum).unwrap().is_none());
+        assert!(!cache.entry_path(url, &checksum).exists());
+
+        assert_eq!(
+            cache.stats(),
+            DownloadCacheStats {
+                hits: 1,
+                misses: 3,
+                cached_bytes: 7,
+                downloaded_bytes: 7,
+                evictions: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn it_evicts_least_recently_used_entries() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let cache = DownloadCache::new(tmpdir.path());
+        cache.set_max_size(20);
+
+        let entries: Vec<(String, Checksum)> = (0..3)
+            .map(|index| {
+                let data = format!("archive {}", index);
+                let url = format!("https://example.com/{}.tar.gz", index);
+                let checksum = ChecksumAlgorithm::Sha256.digest(data.as_bytes());
+
+                cache
+                    .store(&url, &checksum, &mut content(data.as_bytes()))
+                    .unwrap();
+                filetime::set_file_mtime(
+                    cache.entry_path(&url, &checksum),
+                    filetime::FileTime::from_unix_time(1_000_000 + index, 0),
+                )
+                .unwrap();
+
+                (url, checksum)
+            })
+            .collect();
+
+        // Each entry has 9 bytes, only two fit into the cache.
+        assert_eq!(cache.stats().evictions, 1);
+        assert!(!cache.entry_path(&entries[0].0, &entries[0].1).exists());
+
+        // Using an entry makes it the most recently used one.
+        cache.lookup(&entries[1].0, &entries[1].1).unwrap().unwrap();
+        cache.set_max_size(10);
+        cache.evict().unwrap();
+
+        assert!(cache.entry_path(&entries[1].0, &entries[1].1).exists());
+        assert!(!cache.entry_path(&entries[2].0, &entries[2].1).exists());
+        assert_eq!(cache.stats().evictions, 2);
+    }
+
+    #[test]
+    fn it_ignores_and_removes_stale_staged_entries() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let cache = DownloadCache::new(tmpdir.path());
+        cache.set_max_size(10);
+        fs::create_dir_all(cache.layer_dir()).unwrap();
+
+        let in_progress = cache.layer_dir().join(".staged-in-progress");
+        fs::write(&in_progress, "a download being added").unwrap();
+        let stale = cache.layer_dir().join(".staged-stale");
+        fs::write(&stale, "an interrupted download").unwrap();
+        filetime::set_file_mtime(&stale, filetime::FileTime::from_unix_time(1_000_000, 0)).unwrap();
+
+        let url = "https://nodejs.org/dist/node.tar.gz";
+        let checksum = ChecksumAlgorithm::Sha256.digest(b"archive");
+        cache
+            .store(url, &checksum, &mut content(b"archive"))
+            .unwrap();
+
+        assert_eq!(cache.stats().evictions, 0);
+        assert!(cache.entry_path(url, &checksum).exists());
+        assert!(in_progress.exists());
+        assert!(!stale.exists());
+    }
+}
@@ -0,0 +1,243 @@
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter, Write};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+
+/// Hash algorithms supported for verifying downloads.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl ChecksumAlgorithm {
+    /// Computes the checksum of the given data with this algorithm.
+    pub fn digest(self, data: &[u8]) -> Checksum {
+        let hex = match self {
+            ChecksumAlgorithm::Sha256 => format!("{:x}", sha2::Sha256::digest(data)),
+            ChecksumAlgorithm::Sha512 => format!("{:x}", sha2::Sha512::digest(data)),
+        };
+
+        Checksum {
+            algorithm: self,
+            hex,
+        }
+    }
+
+    pub(crate) fn hasher(self) -> ChecksumHasher {
+        match self {
+            ChecksumAlgorithm::Sha256 => ChecksumHasher::Sha256(sha2::Sha256::new()),
+            ChecksumAlgorithm::Sha512 => ChecksumHasher::Sha512(sha2::Sha512::new()),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    fn hex_len(self) -> usize {
+        match self {
+            ChecksumAlgorithm::Sha256 => 64,
+            ChecksumAlgorithm::Sha512 => 128,
+        }
+    }
+}
+
+/// Computes a [`Checksum`] incrementally.
+pub(crate) enum ChecksumHasher {
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+}
+
+impl ChecksumHasher {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            ChecksumHasher::Sha256(hasher) => hasher.update(data),
+            ChecksumHasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Checksum {
+        let (algorithm, hex) = match self {
+            ChecksumHasher::Sha256(hasher) => (
+                ChecksumAlgorithm::Sha256,
+                format!("{:x}", hasher.finalize()),
+            ),
+            ChecksumHasher::Sha512(hasher) => (
+                ChecksumAlgorithm::Sha512,
+                format!("{:x}", hasher.finalize()),
+            ),
+        };
+
+        Checksum { algorithm, hex }
+    }
+}
+
+/// The expected or actual checksum of a download, in the form `<algorithm>:<hex digest>`.
+///
+/// Use [`std::str::FromStr`] to create a new instance of this struct. The hex digest is
+/// normalized to lowercase.
+///
+/// # Examples
+/// ```
+/// use std::str::FromStr;
+/// use libcnb::transfer::{Checksum, ChecksumAlgorithm};
+///
+/// let checksum = Checksum::from_str(
+///     "sha256:2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824",
+/// )
+/// .unwrap();
+///
+/// assert_eq!(checksum.algorithm(), ChecksumAlgorithm::Sha256);
+/// assert!(checksum.matches(b"hello"));
+/// assert_eq!(
+///     checksum.to_string(),
+///     "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+/// );
+///
+/// assert!(Checksum::from_str("md5:5d41402abc4b2a76b9719d911017c592").is_err());
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct Checksum {
+    algorithm: ChecksumAlgorithm,
+    hex: String,
+}
+
+impl Checksum {
+    pub fn algorithm(&self) -> ChecksumAlgorithm {
+        self.algorithm
+    }
+
+    /// The lowercase hex digest, without the algorithm prefix.
+    pub fn hex(&self) -> &str {
+        &self.hex
+    }
+
+    pub(crate) fn from_digest(algorithm: ChecksumAlgorithm, digest: &[u8]) -> Checksum {
+        Checksum {
+            algorithm,
+            hex: digest.iter().fold(String::new(), |mut hex, byte| {
+                let _ = write!(hex, "{:02x}", byte);
+                hex
+            }),
+        }
+    }
+
+    /// Checks whether the given data has this checksum.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.algorithm.digest(data) == *self
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = ChecksumError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (name, hex) = value
+            .split_once(':')
+            .ok_or_else(|| ChecksumError::InvalidChecksum(String::from(value)))?;
+
+        let algorithm = match name {
+            "sha256" => ChecksumAlgorithm::Sha256,
+            "sha512" => ChecksumAlgorithm::Sha512,
+            _ => return Err(ChecksumError::UnsupportedAlgorithm(String::from(name))),
+        };
+
+        if hex.len() == algorithm.hex_len() && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            Ok(Checksum {
+                algorithm,
+                hex: hex.to_ascii_lowercase(),
+            })
+        } else {
+            Err(ChecksumError::InvalidChecksum(String::from(value)))
+        }
+    }
+}
+
+impl TryFrom<String> for Checksum {
+    type Error = ChecksumError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Checksum::from_str(&value)
+    }
+}
+
+impl From<Checksum> for String {
+    fn from(checksum: Checksum) -> Self {
+        checksum.to_string()
+    }
+}
+
+impl Display for Checksum {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        write!(formatter, "{}:{}", self.algorithm.name(), self.hex)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ChecksumError {
+    #[error("Found `{0}` but value MUST be in the form `<algorithm>:<hex digest>` with a digest of the algorithm's length.")]
+    InvalidChecksum(String),
+
+    #[error(
+        "Unsupported checksum algorithm `{0}`, supported algorithms are `sha256` and `sha512`."
+    )]
+    UnsupportedAlgorithm(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_computes_sha512() {
+        let checksum = ChecksumAlgorithm::Sha512.digest(b"hello");
+
+        assert_eq!(checksum.algorithm(), ChecksumAlgorithm::Sha512);
+        assert_eq!(checksum.hex(), "9b71d224bd62f3785d96d46ad3ea3d73319bfbc2890caadae2dff72519673ca72323c3d99ba5c11d7c7acc6e14b8c5da0c4663475c2e5c3adef46f73bcdec043");
+        assert_eq!(Checksum::from_str(&checksum.to_string()).unwrap(), checksum);
+    }
+
+    #[test]
+    fn it_computes_checksums_incrementally() {
+        for algorithm in [ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Sha512] {
+            let mut hasher = algorithm.hasher();
+            hasher.update(b"hel");
+            hasher.update(b"lo");
+
+            assert_eq!(hasher.finish(), algorithm.digest(b"hello"));
+        }
+    }
+
+    #[test]
+    fn it_rejects_invalid_checksums() {
+        assert!(Checksum::from_str("2cf24dba5fb0a30e26e83b2ac5b9e29e").is_err());
+        assert!(Checksum::from_str("sha256:2cf24dba").is_err());
+        assert!(Checksum::from_str(
+            "sha256:zzf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_deserializes_from_string() {
+        #[derive(Deserialize)]
+        struct Dependency {
+            checksum: Checksum,
+        }
+
+        let dependency: Dependency = toml::from_str(
+            r#"checksum = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824""#,
+        )
+        .unwrap();
+        assert!(dependency.checksum.matches(b"hello"));
+
+        assert!(toml::from_str::<Dependency>(r#"checksum = "sha256:abc""#).is_err());
+    }
+}
@@ -0,0 +1,897 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::{Body, Client, Response};
+use reqwest::header::HeaderMap;
+use reqwest::{Certificate, IntoUrl, Method, Proxy, StatusCode, Url};
+
+use crate::build::BuildContext;
+use crate::platform::{Platform, PlatformEnv};
+use crate::transfer::mirror::{Source, UrlResolver};
+use crate::transfer::{Download, TransferError, Upload};
+
+/// The binding type of service bindings with credentials for a host, see
+/// [`ClientConfig::from_platform`].
+pub const CREDENTIALS_BINDING_TYPE: &str = "http-credentials";
+
+/// The binding type of service bindings with additional CA certificates, see
+/// [`ClientConfig::from_platform`].
+pub const CA_CERTIFICATES_BINDING_TYPE: &str = "ca-certificates";
+
+/// The binding type of service bindings with dependency mirrors, see
+/// [`ClientConfig::from_platform`].
+pub const DEPENDENCY_MIRROR_BINDING_TYPE: &str = "dependency-mirror";
+
+/// The directory inside the buildpack directory with dependencies for offline builds, see
+/// [`ClientConfig::from_build_context`].
+pub const OFFLINE_DEPENDENCIES_DIR_NAME: &str = "dependencies";
+
+/// Credentials sent to a host with every request.
+///
+/// The `Debug` output does not contain the token or password.
+#[derive(Clone, Eq, PartialEq)]
+pub enum Credentials {
+    Bearer(String),
+    Basic { username: String, password: String },
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const REDACTED: &str = "<redacted>";
+
+        match self {
+            Credentials::Bearer(_) => formatter.debug_tuple("Bearer").field(&REDACTED).finish(),
+            Credentials::Basic { username, .. } => formatter
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &REDACTED)
+                .finish(),
+        }
+    }
+}
+
+/// Configuration of a [`TransferClient`].
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+/// use libcnb::transfer::{ClientConfig, Credentials};
+///
+/// let client = ClientConfig::new()
+///     .retries(5)
+///     .connect_timeout(Duration::from_secs(5))
+///     .https_proxy("http://proxy.example.com:3128")
+///     .no_proxy("localhost,.internal.example.com")
+///     .credentials("artifacts.example.com", Credentials::Bearer(String::from("s3cr3t")))
+///     .ca_bundle("/etc/ssl/certs/corporate.pem")
+///     .build()
+///     .unwrap();
+///
+/// let checksum = client
+///     .download("https://artifacts.example.com/jdk.tar.gz")
+///     .extract_to("/layers/jdk")
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    retries: u32,
+    retry_backoff: Duration,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    proxies: Option<Proxies>,
+    credentials: Vec<(String, Credentials)>,
+    ca_bundles: Vec<PathBuf>,
+    resolver: UrlResolver,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Proxies {
+    http: Option<String>,
+    https: Option<String>,
+    no_proxy: Option<String>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            retries: 3,
+            retry_backoff: Duration::from_secs(1),
+            connect_timeout: Duration::from_secs(30),
+            read_timeout: Duration::from_secs(30),
+            proxies: None,
+            credentials: Vec::new(),
+            ca_bundles: Vec::new(),
+            resolver: UrlResolver::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn new() -> Self {
+        ClientConfig::default()
+    }
+
+    /// Reads the configuration from the platform.
+    ///
+    /// The following environment variables are read from the platform environment, falling back
+    /// to the process environment:
+    ///
+    /// - `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY`, or their lowercase variants.
+    /// - `SSL_CERT_FILE`, a CA bundle that is trusted in addition to the system certificates.
+    /// - `BP_DEPENDENCY_MIRROR`, the mirror for all hosts, see [`ClientConfig::default_mirror`].
+    /// - `SERVICE_BINDING_ROOT`, the directory containing the service bindings.
+    ///
+    /// Service bindings of type [`CREDENTIALS_BINDING_TYPE`] provide credentials for the host in
+    /// their `host` entry. They either have a `token` entry for bearer authentication or
+    /// `username` and `password` entries for basic authentication. Every entry of bindings of
+    /// type [`CA_CERTIFICATES_BINDING_TYPE`] is a trusted CA bundle. Bindings of type
+    /// [`DEPENDENCY_MIRROR_BINDING_TYPE`] have an entry with the mirror URL for every mirrored
+    /// host, the `default` entry mirrors all hosts.
+    pub fn from_platform(env: &PlatformEnv) -> Result<Self, TransferError> {
+        let mut config = ClientConfig::new();
+
+        let proxies = Proxies {
+            http: platform_var(env, "HTTP_PROXY"),
+            https: platform_var(env, "HTTPS_PROXY"),
+            no_proxy: platform_var(env, "NO_PROXY"),
+        };
+        if proxies.http.is_some() || proxies.https.is_some() {
+            config.proxies = Some(proxies);
+        }
+
+        if let Some(ca_bundle) = platform_var(env, "SSL_CERT_FILE") {
+            config.ca_bundles.push(PathBuf::from(ca_bundle));
+        }
+
+        if let Some(mirror) = platform_var(env, "BP_DEPENDENCY_MIRROR") {
+            config = config.default_mirror(mirror);
+        }
+
+        if let Some(bindings_dir) = platform_var(env, "SERVICE_BINDING_ROOT") {
+            config = config.bindings(bindings_dir)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Reads the configuration from the platform, see [`ClientConfig::from_platform`].
+    ///
+    /// Buildpacks that bundle their dependencies in the [`OFFLINE_DEPENDENCIES_DIR_NAME`]
+    /// directory inside the buildpack directory are offline, see [`ClientConfig::offline`].
+    pub fn from_build_context<P: Platform, BM>(
+        context: &BuildContext<P, BM>,
+    ) -> Result<Self, TransferError> {
+        let config = ClientConfig::from_platform(context.platform.env())?;
+
+        let offline_dir = context.buildpack_dir.join(OFFLINE_DEPENDENCIES_DIR_NAME);
+        if offline_dir.is_dir() {
+            Ok(config.offline(offline_dir))
+        } else {
+            Ok(config)
+        }
+    }
+
+    /// Sets how often failed requests are retried, 3 by default.
+    ///
+    /// Requests are retried on connection errors, timeouts, server errors (5xx) and the status
+    /// codes `408 Request Timeout` and `429 Too Many Requests`.
+    #[must_use]
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets the delay before the first retry, 1 second by default. The delay doubles with every
+    /// further retry.
+    #[must_use]
+    pub fn retry_backoff(mut self, retry_backoff: Duration) -> Self {
+        self.retry_backoff = retry_backoff;
+        self
+    }
+
+    /// Sets the timeout for establishing a connection, 30 seconds by default.
+    #[must_use]
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Sets the timeout for sending the whole request body and receiving the response headers,
+    /// and for every read of the response body, 30 seconds by default.
+    ///
+    /// Uploads extend it by the time it takes to send the file, see [`Upload::timeout`].
+    #[must_use]
+    pub fn read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// Sends all `http` requests through the given proxy.
+    ///
+    /// Setting any proxy disables the proxies from the process environment.
+    #[must_use]
+    pub fn http_proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxies.get_or_insert_with(Proxies::default).http = Some(url.into());
+        self
+    }
+
+    /// Sends all `https` requests through the given proxy.
+    ///
+    /// Setting any proxy disables the proxies from the process environment.
+    #[must_use]
+    pub fn https_proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxies.get_or_insert_with(Proxies::default).https = Some(url.into());
+        self
+    }
+
+    /// Sets the hosts that are never reached through a proxy, as a comma separated list in the
+    /// format of the `NO_PROXY` environment variable.
+    ///
+    /// Entries match the host and all of its subdomains, `*` matches all hosts.
+    #[must_use]
+    pub fn no_proxy(mut self, no_proxy: impl Into<String>) -> Self {
+        self.proxies.get_or_insert_with(Proxies::default).no_proxy = Some(no_proxy.into());
+        self
+    }
+
+    /// Sends the credentials with all requests to `host`.
+    #[must_use]
+    pub fn credentials(mut self, host: impl Into<String>, credentials: Credentials) -> Self {
+        self.credentials.push((host.into(), credentials));
+        self
+    }
+
+    /// Trusts the certificates in the given PEM file in addition to the system certificates.
+    #[must_use]
+    pub fn ca_bundle(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ca_bundles.push(path.into());
+        self
+    }
+
+    /// Downloads from `host` from the mirror instead.
+    ///
+    /// The path and query of the original URL are appended to the mirror URL. Mirrors can be
+    /// `file://` URLs to read dependencies from a local directory.
+    #[must_use]
+    pub fn mirror(mut self, host: impl Into<String>, mirror_url: impl Into<String>) -> Self {
+        self.resolver.mirrors.push((host.into(), mirror_url.into()));
+        self
+    }
+
+    /// Downloads from all hosts without a [`ClientConfig::mirror`] from this mirror instead.
+    ///
+    /// The placeholder [`ORIGINAL_HOST_PLACEHOLDER`](crate::transfer::ORIGINAL_HOST_PLACEHOLDER)
+    /// in the mirror URL is replaced with the host of the original URL.
+    #[must_use]
+    pub fn default_mirror(mut self, mirror_url: impl Into<String>) -> Self {
+        self.resolver.default_mirror = Some(mirror_url.into());
+        self
+    }
+
+    /// Resolves all downloads from the directory instead of the network, ignoring mirrors.
+    ///
+    /// Dependencies are expected at `<dir>/<host>/<path>`, the download of
+    /// `https://nodejs.org/dist/node.tar.gz` is read from `<dir>/nodejs.org/dist/node.tar.gz`.
+    /// Downloads of dependencies that are not in the directory fail with
+    /// [`TransferError::MissingOfflineDependency`].
+    #[must_use]
+    pub fn offline(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.resolver.offline_dir = Some(dir.into());
+        self
+    }
+
+    /// Reads credentials, CA bundles and mirrors from the service bindings in `service_binding_root`, see
+    /// [`ClientConfig::from_platform`].
+    pub fn bindings(
+        mut self,
+        service_binding_root: impl AsRef<Path>,
+    ) -> Result<Self, TransferError> {
+        let entries = match fs::read_dir(service_binding_root) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(self),
+            Err(error) => return Err(error.into()),
+        };
+
+        for entry in entries {
+            let binding_dir = entry?.path();
+            if !binding_dir.is_dir() {
+                continue;
+            }
+
+            match read_binding_entry(&binding_dir, "type")?.as_deref() {
+                Some(CREDENTIALS_BINDING_TYPE) => {
+                    let (host, credentials) = read_credentials_binding(&binding_dir)?;
+                    self = self.credentials(host, credentials);
+                }
+                Some(CA_CERTIFICATES_BINDING_TYPE) => {
+                    for entry in fs::read_dir(&binding_dir)? {
+                        let path = entry?.path();
+                        let is_metadata = path
+                            .file_name()
+                            .map_or(false, |name| name == "type" || name == "provider");
+
+                        if path.is_file() && !is_metadata {
+                            self = self.ca_bundle(path);
+                        }
+                    }
+                }
+                Some(DEPENDENCY_MIRROR_BINDING_TYPE) => self.resolver.add_binding(&binding_dir)?,
+                _ => {}
+            }
+        }
+
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<TransferClient, TransferError> {
+        let mut builder = Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.read_timeout);
+
+        if let Some(proxies) = &self.proxies {
+            builder = builder.no_proxy().proxy(proxies.to_proxy()?);
+        }
+
+        for ca_bundle in &self.ca_bundles {
+            for certificate in read_ca_bundle(ca_bundle)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        Ok(TransferClient {
+            client: builder.build()?,
+            read_timeout: self.read_timeout,
+            retries: self.retries,
+            retry_backoff: self.retry_backoff,
+            credentials: Arc::new(self.credentials),
+            resolver: Arc::new(self.resolver),
+        })
+    }
+}
+
+impl Proxies {
+    fn to_proxy(&self) -> Result<Proxy, TransferError> {
+        let parse = |url: &Option<String>| {
+            url.as_deref()
+                .map(|url| {
+                    Url::parse(url).map_err(|_| TransferError::InvalidProxyUrl(String::from(url)))
+                })
+                .transpose()
+        };
+
+        let http = parse(&self.http)?;
+        let https = parse(&self.https)?;
+        let no_proxy = self.no_proxy.clone().unwrap_or_default();
+
+        Ok(Proxy::custom(move |url| {
+            if url
+                .host_str()
+                .map_or(false, |host| matches_no_proxy(&no_proxy, host))
+            {
+                None
+            } else if url.scheme() == "https" {
+                https.clone()
+            } else {
+                http.clone()
+            }
+        }))
+    }
+}
+
+/// A HTTP client for all transfers, with retries, timeouts, proxies and credentials.
+///
+/// Clones share the same connection pool. [`TransferClient::new`] uses the default
+/// [`ClientConfig`] and the proxies from the process environment.
+#[derive(Debug, Clone)]
+pub struct TransferClient {
+    client: Client,
+    read_timeout: Duration,
+    retries: u32,
+    retry_backoff: Duration,
+    credentials: Arc<Vec<(String, Credentials)>>,
+    resolver: Arc<UrlResolver>,
+}
+
+impl TransferClient {
+    /// Creates a client with the default [`ClientConfig`].
+    ///
+    /// Fails if the client cannot be created, for example when the TLS backend cannot be
+    /// initialized.
+    pub fn new() -> Result<Self, TransferError> {
+        ClientConfig::default().build()
+    }
+
+    /// Reads the contents of a URL as text.
+    ///
+    /// Mirrors and offline mode apply, `file://` URLs are read from the file system. Fails with
+    /// [`TransferError::HttpError`] for error status codes.
+    pub fn get(&self, url: impl AsRef<str>) -> Result<String, TransferError> {
+        let mut text = String::new();
+        self.open(url.as_ref())?.0.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    /// Opens a URL for reading after applying mirrors and offline mode. Returns the contents and
+    /// their length, if known.
+    pub(crate) fn open(&self, url: &str) -> Result<(Box<dyn Read>, Option<u64>), TransferError> {
+        match self.resolver.resolve(url)? {
+            Source::File(path) => {
+                let file = fs::File::open(path)?;
+                let len = file.metadata()?.len();
+                Ok((Box::new(file), Some(len)))
+            }
+            Source::Http(url) => {
+                let response = self
+                    .send(Method::GET, url, &HeaderMap::new(), None, || Ok(None))?
+                    .error_for_status()?;
+                let len = response.content_length();
+                Ok((Box::new(response), len))
+            }
+        }
+    }
+
+    /// Creates a [`Download`] that uses this client.
+    pub fn download(&self, url: impl Into<String>) -> Download {
+        Download::new(url).client(self.clone())
+    }
+
+    /// Creates an [`Upload`] of a file that uses this client.
+    pub fn upload(&self, file_path: impl Into<PathBuf>, url: impl Into<String>) -> Upload {
+        Upload::new(file_path, url).client(self.clone())
+    }
+
+    /// Uploads a file with a `PUT` request and returns the response.
+    ///
+    /// The file is opened again for every retry. Fails with [`TransferError::UploadFailed`] for
+    /// error status codes. See [`Upload`] for content types, checksum headers and multipart
+    /// uploads.
+    pub fn put(
+        &self,
+        file_path: impl AsRef<Path>,
+        url: impl IntoUrl,
+    ) -> Result<Response, TransferError> {
+        let url = url.into_url()?;
+        self.upload(file_path.as_ref(), url.as_str()).send_request()
+    }
+
+    /// Sends a request, retrying on connection errors, timeouts, server errors and the status
+    /// codes `408 Request Timeout` and `429 Too Many Requests`.
+    ///
+    /// The body is created again for every attempt. The response of the last attempt is returned
+    /// even if it has an error status code. The `timeout` overrides the read timeout of the client
+    /// for this request.
+    pub(crate) fn send(
+        &self,
+        method: Method,
+        url: impl IntoUrl,
+        headers: &HeaderMap,
+        timeout: Option<Duration>,
+        body: impl Fn() -> io::Result<Option<Body>>,
+    ) -> Result<Response, TransferError> {
+        // `IntoUrl` can only be converted by reqwest itself.
+        let url = self.client.request(Method::GET, url).build()?.url().clone();
+
+        let mut attempt = 0;
+        loop {
+            let mut request = self
+                .client
+                .request(method.clone(), url.clone())
+                .headers(headers.clone());
+            match self.credentials_for(&url) {
+                Some(Credentials::Bearer(token)) => request = request.bearer_auth(token),
+                Some(Credentials::Basic { username, password }) => {
+                    request = request.basic_auth(username, Some(password));
+                }
+                None => {}
+            }
+
+            if let Some(timeout) = timeout {
+                request = request.timeout(timeout);
+            }
+
+            if let Some(body) = body()? {
+                request = request.body(body);
+            }
+
+            let result = request.send();
+            let retry = match &result {
+                Ok(response) => {
+                    let status = response.status();
+                    status.is_server_error()
+                        || status == StatusCode::REQUEST_TIMEOUT
+                        || status == StatusCode::TOO_MANY_REQUESTS
+                }
+                Err(error) => error.is_connect() || error.is_timeout(),
+            };
+
+            if !retry || attempt >= self.retries {
+                return Ok(result?);
+            }
+
+            thread::sleep(self.retry_backoff * 2_u32.saturating_pow(attempt.min(16)));
+            attempt += 1;
+        }
+    }
+
+    /// The timeout for receiving response headers and reading the response body.
+    pub(crate) fn read_timeout(&self) -> Duration {
+        self.read_timeout
+    }
+
+    fn credentials_for(&self, url: &Url) -> Option<&Credentials> {
+        let host = url.host_str()?;
+
+        self.credentials
+            .iter()
+            .find(|(credentials_host, _)| credentials_host.eq_ignore_ascii_case(host))
+            .map(|(_, credentials)| credentials)
+    }
+}
+
+/// Reads a variable from the platform environment, falling back to the process environment.
+/// Uppercase names take precedence over lowercase ones, empty values are ignored.
+fn platform_var(env: &PlatformEnv, name: &str) -> Option<String> {
+    let names = [String::from(name), name.to_lowercase()];
+
+    names
+        .iter()
+        .map(|name| env.var(name).ok())
+        .chain(names.iter().map(|name| std::env::var(name).ok()))
+        .flatten()
+        .map(|value| String::from(value.trim()))
+        .find(|value| !value.is_empty())
+}
+
+fn matches_no_proxy(no_proxy: &str, host: &str) -> bool {
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().trim_start_matches('.'))
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            entry == "*"
+                || host.eq_ignore_ascii_case(entry)
+                || host
+                    .to_lowercase()
+                    .ends_with(&format!(".{}", entry.to_lowercase()))
+        })
+}
+
+fn read_binding_entry(binding_dir: &Path, name: &str) -> io::Result<Option<String>> {
+    match fs::read_to_string(binding_dir.join(name)) {
+        Ok(value) => Ok(Some(String::from(value.trim()))),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+fn read_credentials_binding(binding_dir: &Path) -> Result<(String, Credentials), TransferError> {
+    let invalid = || TransferError::InvalidCredentialsBinding(binding_dir.to_path_buf());
+
+    let host = read_binding_entry(binding_dir, "host")?.ok_or_else(invalid)?;
+    let token = read_binding_entry(binding_dir, "token")?;
+    let username = read_binding_entry(binding_dir, "username")?;
+    let password = read_binding_entry(binding_dir, "password")?;
+
+    let credentials = match (token, username, password) {
+        (Some(token), None, None) => Credentials::Bearer(token),
+        (None, Some(username), Some(password)) => Credentials::Basic { username, password },
+        _ => return Err(invalid()),
+    };
+
+    Ok((host, credentials))
+}
+
+fn read_ca_bundle(path: &Path) -> Result<Vec<Certificate>, TransferError> {
+    const END_MARKER: &str = "-----END CERTIFICATE-----";
+
+    let pem = fs::read_to_string(path)?;
+    let certificates = pem
+        .split_inclusive(END_MARKER)
+        .filter(|block| block.contains("-----BEGIN CERTIFICATE-----"))
+        .map(|block| Certificate::from_pem(block.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| TransferError::InvalidCaBundle(path.to_path_buf()))?;
+
+    if certificates.is_empty() {
+        Err(TransferError::InvalidCaBundle(path.to_path_buf()))
+    } else {
+        Ok(certificates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::serve;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn fast_retries() -> ClientConfig {
+        ClientConfig::new().retry_backoff(Duration::from_millis(1))
+    }
+
+    #[test]
+    fn it_retries_server_errors() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let server_requests = Arc::clone(&requests);
+        let url = serve(move |_| {
+            if server_requests.fetch_add(1, Ordering::SeqCst) < 2 {
+                (503, Vec::new())
+            } else {
+                (200, b"17.0.1".to_vec())
+            }
+        });
+
+        let client = fast_retries().retries(2).build().unwrap();
+        assert_eq!(client.get(format!("{}/release", url)).unwrap(), "17.0.1");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        requests.store(0, Ordering::SeqCst);
+        let client = fast_retries().retries(1).build().unwrap();
+        assert!(matches!(
+            client.get(format!("{}/release", url)),
+            Err(TransferError::HttpError(error)) if error.status().map(|status| status.as_u16()) == Some(503)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn it_does_not_retry_client_errors() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let server_requests = Arc::clone(&requests);
+        let url = serve(move |_| {
+            server_requests.fetch_add(1, Ordering::SeqCst);
+            (404, Vec::new())
+        });
+
+        let client = fast_retries().build().unwrap();
+        assert!(client.get(format!("{}/missing", url)).is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn it_times_out_and_retries() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let server_requests = Arc::clone(&requests);
+        let url = serve(move |_| {
+            if server_requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                thread::sleep(Duration::from_millis(500));
+            }
+            (200, b"ok".to_vec())
+        });
+
+        let client = fast_retries()
+            .retries(0)
+            .read_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        assert!(matches!(
+            client.get(format!("{}/slow", url)),
+            Err(TransferError::HttpError(error)) if error.is_timeout()
+        ));
+
+        // The test server handles one request at a time, the retry waits for the slow one.
+        requests.store(0, Ordering::SeqCst);
+        let client = ClientConfig::new()
+            .retries(1)
+            .retry_backoff(Duration::from_millis(800))
+            .read_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        assert_eq!(client.get(format!("{}/slow", url)).unwrap(), "ok");
+    }
+
+    #[test]
+    fn it_sends_credentials_only_to_their_host() {
+        let url = serve(|request| {
+            let authorization = request.header("authorization").unwrap_or("none");
+            (200, authorization.as_bytes().to_vec())
+        });
+
+        let config = ClientConfig::new()
+            .credentials("127.0.0.1", Credentials::Bearer(String::from("s3cr3t")));
+        assert!(!format!("{:?}", config).contains("s3cr3t"));
+
+        let client = config.build().unwrap();
+        assert_eq!(client.get(&url).unwrap(), "Bearer s3cr3t");
+        assert!(!format!("{:?}", client).contains("s3cr3t"));
+
+        let client = ClientConfig::new()
+            .credentials(
+                "127.0.0.1",
+                Credentials::Basic {
+                    username: String::from("user"),
+                    password: String::from("pass"),
+                },
+            )
+            .build()
+            .unwrap();
+        assert_eq!(client.get(&url).unwrap(), "Basic dXNlcjpwYXNz");
+        assert!(!format!("{:?}", client).contains("\"pass\""));
+
+        let client = ClientConfig::new()
+            .credentials("example.com", Credentials::Bearer(String::from("s3cr3t")))
+            .build()
+            .unwrap();
+        assert_eq!(client.get(&url).unwrap(), "none");
+    }
+
+    #[test]
+    fn it_reopens_uploaded_file_for_retries() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let server_requests = Arc::clone(&requests);
+        let url = serve(move |request| {
+            assert_eq!(request.method, "PUT");
+            if server_requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                (502, Vec::new())
+            } else {
+                (200, request.body.clone())
+            }
+        });
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("source.tar.xz");
+        fs::write(&path, "archive").unwrap();
+
+        let response = fast_retries()
+            .build()
+            .unwrap()
+            .put(&path, format!("{}/upload", url))
+            .unwrap();
+        assert_eq!(response.text().unwrap(), "archive");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn it_uses_proxies() {
+        let proxy_url = serve(|request| (200, format!("proxied {}", request.path).into_bytes()));
+        let url = serve(|_| (200, b"direct".to_vec()));
+
+        let client = ClientConfig::new().http_proxy(&proxy_url).build().unwrap();
+        assert_eq!(
+            client.get(format!("{}/file", url)).unwrap(),
+            format!("proxied {}/file", url)
+        );
+
+        let client = ClientConfig::new()
+            .http_proxy(&proxy_url)
+            .no_proxy("localhost, 127.0.0.1")
+            .build()
+            .unwrap();
+        assert_eq!(client.get(format!("{}/file", url)).unwrap(), "direct");
+
+        assert!(matches!(
+            ClientConfig::new().https_proxy("not a url").build(),
+            Err(TransferError::InvalidProxyUrl(_))
+        ));
+    }
+
+    #[test]
+    fn it_downloads_from_mirrors() {
+        let url = serve(|request| (200, request.path.clone().into_bytes()));
+
+        let client = ClientConfig::new()
+            .default_mirror(format!("{}/{{originalHost}}", url))
+            .build()
+            .unwrap();
+        assert_eq!(
+            client.get("https://nodejs.org/dist/index.json").unwrap(),
+            "/nodejs.org/dist/index.json"
+        );
+    }
+
+    #[test]
+    fn it_goes_offline_with_bundled_dependencies() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let context = crate::test_support::build_context(&tmpdir);
+
+        let config = ClientConfig::from_build_context(&context).unwrap();
+        assert_eq!(config.resolver.offline_dir, None);
+
+        let dependency = context
+            .buildpack_dir
+            .join("dependencies/nodejs.org/dist/index.json");
+        fs::create_dir_all(dependency.parent().unwrap()).unwrap();
+        fs::write(&dependency, "[]").unwrap();
+
+        let client = ClientConfig::from_build_context(&context)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            client.get("https://nodejs.org/dist/index.json").unwrap(),
+            "[]"
+        );
+        assert!(matches!(
+            client.get("https://nodejs.org/dist/other.json"),
+            Err(TransferError::MissingOfflineDependency { .. })
+        ));
+    }
+
+    #[test]
+    fn it_matches_no_proxy_entries() {
+        assert!(matches_no_proxy("example.com", "example.com"));
+        assert!(matches_no_proxy("example.com", "cdn.Example.com"));
+        assert!(matches_no_proxy(
+            "localhost, .example.com",
+            "cdn.example.com"
+        ));
+        assert!(matches_no_proxy("*", "example.org"));
+        assert!(!matches_no_proxy("example.com", "notexample.com"));
+        assert!(!matches_no_proxy("", "example.com"));
+    }
+
+    #[test]
+    fn it_reads_configuration_from_platform() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let env_dir = tmpdir.path().join("platform/env");
+        let bindings_dir = tmpdir.path().join("bindings");
+        fs::create_dir_all(&env_dir).unwrap();
+        fs::write(env_dir.join("HTTPS_PROXY"), "http://proxy.example.com:3128").unwrap();
+        fs::write(env_dir.join("no_proxy"), "localhost").unwrap();
+        fs::write(
+            env_dir.join("SERVICE_BINDING_ROOT"),
+            bindings_dir.to_str().unwrap(),
+        )
+        .unwrap();
+
+        let bearer_dir = bindings_dir.join("artifacts");
+        fs::create_dir_all(&bearer_dir).unwrap();
+        fs::write(bearer_dir.join("type"), "http-credentials\n").unwrap();
+        fs::write(bearer_dir.join("host"), "artifacts.example.com").unwrap();
+        fs::write(bearer_dir.join("token"), "s3cr3t\n").unwrap();
+
+        let ca_dir = bindings_dir.join("corporate-ca");
+        fs::create_dir_all(&ca_dir).unwrap();
+        fs::write(ca_dir.join("type"), "ca-certificates").unwrap();
+        fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/certificates/test-ca.pem"),
+            ca_dir.join("corporate.pem"),
+        )
+        .unwrap();
+
+        let env = PlatformEnv::from_path(tmpdir.path().join("platform")).unwrap();
+        let config = ClientConfig::from_platform(&env).unwrap();
+
+        let proxies = config.proxies.clone().unwrap();
+        assert_eq!(
+            proxies.https.as_deref(),
+            Some("http://proxy.example.com:3128")
+        );
+        assert_eq!(proxies.no_proxy.as_deref(), Some("localhost"));
+        assert_eq!(
+            config.credentials,
+            vec![(
+                String::from("artifacts.example.com"),
+                Credentials::Bearer(String::from("s3cr3t"))
+            )]
+        );
+        assert!(config.ca_bundles.contains(&ca_dir.join("corporate.pem")));
+        config.build().unwrap();
+
+        let invalid_dir = bindings_dir.join("invalid");
+        fs::create_dir_all(&invalid_dir).unwrap();
+        fs::write(invalid_dir.join("type"), "http-credentials").unwrap();
+        fs::write(invalid_dir.join("host"), "example.com").unwrap();
+        assert!(matches!(
+            ClientConfig::from_platform(&env),
+            Err(TransferError::InvalidCredentialsBinding(_))
+        ));
+    }
+
+    #[test]
+    fn it_rejects_invalid_ca_bundles() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("ca.pem");
+        fs::write(&path, "not a certificate").unwrap();
+
+        assert!(matches!(
+            ClientConfig::new().ca_bundle(&path).build(),
+            Err(TransferError::InvalidCaBundle(_))
+        ));
+    }
+}
@@ -0,0 +1,379 @@
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::transfer::checksum::{ChecksumAlgorithm, ChecksumHasher};
+use crate::transfer::{
+    extract_archive, verify_checksum, ArchiveFormat, Checksum, DownloadCache, ExtractOptions,
+    TransferClient, TransferError,
+};
+
+type ProgressCallback = Box<dyn Fn(u64, Option<u64>)>;
+
+/// A download of an archive that is extracted into a directory
+///
+/// All formats of [`extract_archive`] are supported, the format is detected from the contents
+/// unless it is set explicitly.
+///
+/// The archive is streamed from the server, memory usage does not depend on the size of the
+/// archive. Without an expected checksum, the archive is extracted while it is downloaded. With
+/// an expected checksum, the archive is downloaded into an anonymous temporary file first and
+/// only extracted once its checksum has been verified.
+///
+/// # Example
+/// ```no_run
+/// use std::str::FromStr;
+/// use libcnb::transfer::{Checksum, Download};
+///
+/// Download::new("https://example.com/jdk.tar.xz")
+///     .checksum(
+///         Checksum::from_str(
+///             "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+///         )
+///         .unwrap(),
+///     )
+///     .prefix("jdk")
+///     .on_progress(|downloaded, total| match total {
+///         Some(total) => println!("Downloaded {} of {} bytes", downloaded, total),
+///         None => println!("Downloaded {} bytes", downloaded),
+///     })
+///     .extract_to("/layers/jdk")
+///     .unwrap();
+/// ```
+pub struct Download {
+    url: String,
+    checksum: Option<Checksum>,
+    options: ExtractOptions,
+    progress: Option<ProgressCallback>,
+    client: Option<TransferClient>,
+    cache: Option<DownloadCache>,
+}
+
+impl Download {
+    pub fn new(url: impl Into<String>) -> Self {
+        Download {
+            url: url.into(),
+            checksum: None,
+            options: ExtractOptions::default(),
+            progress: None,
+            client: None,
+            cache: None,
+        }
+    }
+
+    /// Sets the expected checksum of the archive.
+    ///
+    /// Nothing is extracted when the checksum of the downloaded archive does not match, the
+    /// download fails with [`TransferError::ChecksumMismatch`] instead.
+    #[must_use]
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    /// Only extracts the archive entries inside `prefix`, with the prefix stripped from their
+    /// paths.
+    #[must_use]
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.options = self.options.prefix(prefix.into());
+        self
+    }
+
+    /// Sets the format of the archive instead of detecting it from its contents.
+    #[must_use]
+    pub fn format(mut self, format: ArchiveFormat) -> Self {
+        self.options = self.options.format(format);
+        self
+    }
+
+    /// Replaces all options for extracting the archive, including the prefix and format.
+    #[must_use]
+    pub fn extract_options(mut self, options: ExtractOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Sets a callback that is called with the number of bytes downloaded so far and the total
+    /// size of the download, if known, whenever data was received.
+    #[must_use]
+    pub fn on_progress(mut self, callback: impl Fn(u64, Option<u64>) + 'static) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Sets the client used for the download, [`TransferClient::new`] otherwise.
+    #[must_use]
+    pub fn client(mut self, client: TransferClient) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Looks up the archive in the cache before downloading it, and adds it to the cache after
+    /// it was downloaded.
+    ///
+    /// Only downloads with an expected [`Download::checksum`] are cached. No progress is reported
+    /// for archives from the cache.
+    #[must_use]
+    pub fn cache(mut self, cache: DownloadCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Downloads the archive and extracts it into `dst`.
+    ///
+    /// Returns the checksum of the downloaded archive. It uses the algorithm of the expected
+    /// checksum, or SHA-256 when no checksum is expected.
+    pub fn extract_to(self, dst: impl AsRef<Path>) -> Result<Checksum, TransferError> {
+        if let (Some(cache), Some(expected)) = (&self.cache, &self.checksum) {
+            if let Some(file) = cache.lookup(&self.url, expected)? {
+                extract_archive(BufReader::new(file), dst, &self.options)?;
+                return Ok(expected.clone());
+            }
+        }
+
+        let client = match &self.client {
+            Some(client) => client.clone(),
+            None => TransferClient::new()?,
+        };
+        let (content, total) = client.open(&self.url)?;
+
+        let progress = ProgressReader {
+            inner: content,
+            downloaded: 0,
+            total,
+            callback: self.progress.as_deref(),
+        };
+
+        let algorithm = self
+            .checksum
+            .as_ref()
+            .map_or(ChecksumAlgorithm::Sha256, Checksum::algorithm);
+
+        let mut reader = HashingReader {
+            inner: progress,
+            hasher: algorithm.hasher(),
+        };
+
+        match &self.checksum {
+            Some(expected) => {
+                let mut file = tempfile::tempfile()?;
+                io::copy(&mut reader, &mut file)?;
+
+                let actual = verify_checksum(&self.url, expected, reader.hasher.finish())?;
+
+                if let Some(cache) = &self.cache {
+                    cache.store(&self.url, expected, &mut file)?;
+                }
+
+                file.seek(SeekFrom::Start(0))?;
+                extract_archive(BufReader::new(file), dst, &self.options)?;
+
+                Ok(actual)
+            }
+            None => {
+                extract_archive(&mut reader, dst, &self.options)?;
+
+                // Trailing data after the end of the archive is part of the checksum as well.
+                io::copy(&mut reader, &mut io::sink())?;
+
+                Ok(reader.hasher.finish())
+            }
+        }
+    }
+}
+
+/// Reports the number of bytes read from the inner reader to a progress callback.
+struct ProgressReader<'a, R> {
+    inner: R,
+    downloaded: u64,
+    total: Option<u64>,
+    callback: Option<&'a dyn Fn(u64, Option<u64>)>,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        if read > 0 {
+            self.downloaded += read as u64;
+            if let Some(callback) = self.callback {
+                callback(self.downloaded, self.total);
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+/// Computes the checksum of all bytes read from the inner reader.
+struct HashingReader<R> {
+    inner: R,
+    hasher: ChecksumHasher,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{serve, tar_xz};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn it_streams_and_reports_progress() {
+        // Pseudo-random contents, much larger than any buffer used while streaming.
+        let mut state = 0x2545_f491_u32;
+        let contents: Vec<u8> = (0..8 * 1024 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state.to_le_bytes()[0]
+            })
+            .collect();
+
+        // Uncompressed, so the extracted size can be compared with the downloaded size.
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "jdk/lib/modules", contents.as_slice())
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+        let archive_len = archive.len() as u64;
+        let url = serve(move |_| (200, archive.clone()));
+
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let tmpdir = tempfile::tempdir().unwrap();
+        let extracted_path = tmpdir.path().join("lib/modules");
+        let progress_reports = Rc::clone(&reports);
+
+        Download::new(format!("{}/jdk.tar", url))
+            .prefix("jdk")
+            .on_progress(move |downloaded, total| {
+                // Bytes handed to the extractor that have not been written to disk yet.
+                let extracted = std::fs::metadata(&extracted_path).map_or(0, |m| m.len());
+                progress_reports.borrow_mut().push((
+                    downloaded,
+                    total,
+                    downloaded.saturating_sub(extracted),
+                ));
+            })
+            .extract_to(tmpdir.path())
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read(tmpdir.path().join("lib/modules")).unwrap(),
+            contents
+        );
+
+        let reports = reports.borrow();
+        assert!(reports.len() > 1);
+        assert!(reports.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(
+            reports.last().map(|report| (report.0, report.1)),
+            Some((archive_len, Some(archive_len)))
+        );
+
+        // Data is written as it is read, only a few small read buffers are in flight at a time.
+        let peak_buffered = reports.iter().map(|report| report.2).max().unwrap();
+        assert!(
+            peak_buffered <= 64 * 1024,
+            "{} bytes buffered",
+            peak_buffered
+        );
+    }
+
+    #[test]
+    fn it_verifies_checksum_before_extracting() {
+        let archive = tar_xz(&[("release", "17.0.1")]);
+        let expected = ChecksumAlgorithm::Sha512.digest(&archive);
+        let url = serve(move |_| (200, archive.clone()));
+        let tmpdir = tempfile::tempdir().unwrap();
+
+        let checksum = Download::new(format!("{}/jdk.tar.xz", url))
+            .checksum(expected.clone())
+            .extract_to(tmpdir.path().join("valid"))
+            .unwrap();
+        assert_eq!(checksum, expected);
+        assert!(tmpdir.path().join("valid/release").exists());
+
+        let wrong = ChecksumAlgorithm::Sha512.digest(b"something else");
+        match Download::new(format!("{}/jdk.tar.xz", url))
+            .checksum(wrong.clone())
+            .extract_to(tmpdir.path().join("invalid"))
+        {
+            Err(TransferError::ChecksumMismatch {
+                expected, actual, ..
+            }) => {
+                assert_eq!(expected, wrong);
+                assert_eq!(actual, checksum);
+            }
+            result => panic!("Unexpected result: {:?}", result.map(|_| ())),
+        }
+        assert!(!tmpdir.path().join("invalid").exists());
+    }
+
+    #[test]
+    fn it_extracts_file_urls() {
+        let archive = tar_xz(&[("release", "17.0.1")]);
+        let tmpdir = tempfile::tempdir().unwrap();
+        let archive_path = tmpdir.path().join("jdk.tar.xz");
+        std::fs::write(&archive_path, &archive).unwrap();
+
+        Download::new(format!("file://{}", archive_path.display()))
+            .checksum(ChecksumAlgorithm::Sha256.digest(&archive))
+            .extract_to(tmpdir.path().join("jdk"))
+            .unwrap();
+        assert!(tmpdir.path().join("jdk/release").exists());
+    }
+
+    #[test]
+    fn it_uses_cached_downloads() {
+        let archive = tar_xz(&[("release", "17.0.1")]);
+        let checksum = ChecksumAlgorithm::Sha256.digest(&archive);
+        let requests = Arc::new(AtomicUsize::new(0));
+        let server_requests = Arc::clone(&requests);
+        let url = serve(move |_| {
+            server_requests.fetch_add(1, Ordering::SeqCst);
+            (200, archive.clone())
+        });
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let cache = DownloadCache::new(tmpdir.path().join("layers"));
+
+        for dst in ["first", "second"] {
+            Download::new(format!("{}/jdk.tar.xz", url))
+                .checksum(checksum.clone())
+                .cache(cache.clone())
+                .extract_to(tmpdir.path().join(dst))
+                .unwrap();
+            assert!(tmpdir.path().join(dst).join("release").exists());
+        }
+
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.stats().hits, 1);
+        assert_eq!(cache.stats().misses, 1);
+    }
+
+    #[test]
+    fn it_fails_on_http_errors() {
+        let url = serve(|_| (404, Vec::new()));
+        let tmpdir = tempfile::tempdir().unwrap();
+
+        assert!(matches!(
+            Download::new(format!("{}/missing.tar.xz", url)).extract_to(tmpdir.path()),
+            Err(TransferError::HttpError(_))
+        ));
+    }
+}
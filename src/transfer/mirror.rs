@@ -0,0 +1,216 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use reqwest::Url;
+
+use crate::transfer::TransferError;
+
+/// The placeholder in mirror URLs that is replaced with the host of the original URL.
+pub const ORIGINAL_HOST_PLACEHOLDER: &str = "{originalHost}";
+
+/// Where a download is read from after applying mirrors and offline mode.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum Source {
+    File(PathBuf),
+    Http(Url),
+}
+
+/// Rewrites download URLs to mirrors or to the offline dependencies directory.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UrlResolver {
+    pub(crate) default_mirror: Option<String>,
+    pub(crate) mirrors: Vec<(String, String)>,
+    pub(crate) offline_dir: Option<PathBuf>,
+}
+
+impl UrlResolver {
+    pub(crate) fn resolve(&self, url: &str) -> Result<Source, TransferError> {
+        let parsed = Url::parse(url).map_err(|_| TransferError::InvalidUrl(String::from(url)))?;
+
+        if parsed.scheme() == "file" {
+            return parsed
+                .to_file_path()
+                .map(Source::File)
+                .map_err(|()| TransferError::InvalidUrl(String::from(url)));
+        }
+
+        let host = parsed.host_str().unwrap_or_default();
+
+        if let Some(offline_dir) = &self.offline_dir {
+            let path = parsed
+                .path_segments()
+                .into_iter()
+                .flatten()
+                .fold(offline_dir.join(host), |path, segment| path.join(segment));
+
+            return if path.is_file() {
+                Ok(Source::File(path))
+            } else {
+                Err(TransferError::MissingOfflineDependency {
+                    url: String::from(url),
+                    path,
+                })
+            };
+        }
+
+        let mirror = self
+            .mirrors
+            .iter()
+            .find(|(mirror_host, _)| mirror_host.eq_ignore_ascii_case(host))
+            .map(|(_, mirror)| mirror)
+            .or(self.default_mirror.as_ref());
+
+        match mirror {
+            Some(mirror) => {
+                let mut mirrored = format!(
+                    "{}{}",
+                    mirror
+                        .replace(ORIGINAL_HOST_PLACEHOLDER, host)
+                        .trim_end_matches('/'),
+                    parsed.path()
+                );
+                if let Some(query) = parsed.query() {
+                    mirrored = format!("{}?{}", mirrored, query);
+                }
+
+                // Mirrors can point to local directories as well.
+                UrlResolver::default().resolve(&mirrored)
+            }
+            None => Ok(Source::Http(parsed)),
+        }
+    }
+
+    /// Adds the mirrors of a `dependency-mirror` service binding. Every entry is named after
+    /// the host it mirrors, the `default` entry mirrors all other hosts.
+    pub(crate) fn add_binding(&mut self, binding_dir: &Path) -> io::Result<()> {
+        for entry in fs::read_dir(binding_dir)? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some("type" | "provider") | None => continue,
+                Some(name) => String::from(name),
+            };
+
+            let mirror = String::from(fs::read_to_string(&path)?.trim());
+            if name == "default" {
+                self.default_mirror = Some(mirror);
+            } else {
+                self.mirrors.push((name, mirror));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_rewrites_urls_to_mirrors() {
+        let resolver = UrlResolver {
+            default_mirror: Some(String::from("https://mirror.example.com/{originalHost}/")),
+            mirrors: vec![(
+                String::from("nodejs.org"),
+                String::from("https://node-mirror.example.com"),
+            )],
+            offline_dir: None,
+        };
+
+        assert_eq!(
+            resolver
+                .resolve("https://nodejs.org/dist/v16.13.1/node.tar.gz")
+                .unwrap(),
+            Source::Http(
+                Url::parse("https://node-mirror.example.com/dist/v16.13.1/node.tar.gz").unwrap()
+            )
+        );
+        assert_eq!(
+            resolver
+                .resolve("https://cache.ruby-lang.org/pub/ruby.tar.gz?arch=x64")
+                .unwrap(),
+            Source::Http(
+                Url::parse(
+                    "https://mirror.example.com/cache.ruby-lang.org/pub/ruby.tar.gz?arch=x64"
+                )
+                .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn it_resolves_file_urls_and_file_mirrors() {
+        let resolver = UrlResolver {
+            default_mirror: Some(String::from("file:///mirror/{originalHost}")),
+            ..UrlResolver::default()
+        };
+
+        assert_eq!(
+            resolver.resolve("file:///tmp/ruby.tar.gz").unwrap(),
+            Source::File(PathBuf::from("/tmp/ruby.tar.gz"))
+        );
+        assert_eq!(
+            resolver
+                .resolve("https://nodejs.org/dist/node.tar.gz")
+                .unwrap(),
+            Source::File(PathBuf::from("/mirror/nodejs.org/dist/node.tar.gz"))
+        );
+    }
+
+    #[test]
+    fn it_resolves_only_bundled_dependencies_offline() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let bundled = tmpdir.path().join("nodejs.org/dist/node.tar.gz");
+        fs::create_dir_all(bundled.parent().unwrap()).unwrap();
+        fs::write(&bundled, "archive").unwrap();
+
+        let resolver = UrlResolver {
+            default_mirror: Some(String::from("https://mirror.example.com")),
+            offline_dir: Some(tmpdir.path().to_path_buf()),
+            ..UrlResolver::default()
+        };
+
+        assert_eq!(
+            resolver
+                .resolve("https://nodejs.org/dist/node.tar.gz")
+                .unwrap(),
+            Source::File(bundled)
+        );
+
+        match resolver.resolve("https://nodejs.org/dist/missing.tar.gz") {
+            Err(TransferError::MissingOfflineDependency { url, path }) => {
+                assert_eq!(url, "https://nodejs.org/dist/missing.tar.gz");
+                assert_eq!(path, tmpdir.path().join("nodejs.org/dist/missing.tar.gz"));
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn it_reads_mirror_bindings() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        fs::write(tmpdir.path().join("type"), "dependency-mirror").unwrap();
+        fs::write(
+            tmpdir.path().join("default"),
+            "https://mirror.example.com/{originalHost}\n",
+        )
+        .unwrap();
+        fs::write(tmpdir.path().join("nodejs.org"), "file:///mirror/node").unwrap();
+
+        let mut resolver = UrlResolver::default();
+        resolver.add_binding(tmpdir.path()).unwrap();
+
+        assert_eq!(
+            resolver.default_mirror.as_deref(),
+            Some("https://mirror.example.com/{originalHost}")
+        );
+        assert_eq!(
+            resolver.mirrors,
+            vec![(
+                String::from("nodejs.org"),
+                String::from("file:///mirror/node")
+            )]
+        );
+    }
+}
@@ -0,0 +1,805 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::Engine;
+use md5::Md5;
+use reqwest::blocking::{Body, Response};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, ETAG};
+use reqwest::{Method, Url};
+use sha2::Digest;
+
+use crate::transfer::checksum::ChecksumAlgorithm;
+use crate::transfer::{Checksum, TransferClient, TransferError};
+
+/// The number of characters of an error response body kept in [`TransferError::UploadFailed`].
+const MAX_ERROR_BODY_LEN: usize = 1024;
+
+/// The lowest upload rate the default upload timeout allows for, in bytes per second.
+const MIN_UPLOAD_BYTES_PER_SECOND: u64 = 64 * 1024;
+
+/// An upload of a file with a `PUT` request
+///
+/// The upload fails with [`TransferError::UploadFailed`] unless the server responds with a
+/// success status code. Failed requests are retried like all requests of the
+/// [`TransferClient`], the file is read again for every attempt.
+///
+/// The `Content-Type` header is derived from the file name unless it is set explicitly. Enable
+/// the `Content-MD5` and `x-amz-checksum-sha256` headers with [`Upload::checksum_headers`] to let
+/// the server verify the upload. They are off by default since presigned URLs must be signed
+/// with them.
+///
+/// # Example
+/// ```no_run
+/// use libcnb::transfer::Upload;
+///
+/// let receipt = Upload::new("/workspace/app.tar.gz", "https://example.com/artifacts/app.tar.gz")
+///     .header("x-amz-meta-buildpack", "heroku/ruby")
+///     .send()
+///     .unwrap();
+///
+/// println!("Uploaded {} bytes with checksum {}", receipt.size, receipt.checksum);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Upload {
+    file_path: PathBuf,
+    url: String,
+    content_type: Option<String>,
+    headers: Vec<(String, String)>,
+    checksum_headers: bool,
+    timeout: Option<Duration>,
+    multipart: Option<Multipart>,
+    client: Option<TransferClient>,
+}
+
+#[derive(Debug, Clone)]
+struct Multipart {
+    part_urls: Vec<String>,
+    part_size: u64,
+}
+
+/// The result of a successful [`Upload`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UploadReceipt {
+    /// The size of the uploaded file in bytes.
+    pub size: u64,
+
+    /// The SHA-256 checksum of the uploaded file.
+    pub checksum: Checksum,
+
+    /// The `ETag` header of the last response, if the server sent one.
+    pub etag: Option<String>,
+}
+
+impl Upload {
+    pub fn new(file_path: impl Into<PathBuf>, url: impl Into<String>) -> Self {
+        Upload {
+            file_path: file_path.into(),
+            url: url.into(),
+            content_type: None,
+            headers: Vec::new(),
+            checksum_headers: false,
+            timeout: None,
+            multipart: None,
+            client: None,
+        }
+    }
+
+    /// Sets the `Content-Type` header instead of deriving it from the file name.
+    #[must_use]
+    pub fn content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.content_type = Some(content_type.into());
+        self
+    }
+
+    /// Adds a header to the upload requests.
+    #[must_use]
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets whether the `Content-MD5` and `x-amz-checksum-sha256` headers are sent, `false` by
+    /// default.
+    #[must_use]
+    pub fn checksum_headers(mut self, checksum_headers: bool) -> Self {
+        self.checksum_headers = checksum_headers;
+        self
+    }
+
+    /// Sets the timeout of every upload request, for sending the request body and receiving the
+    /// response.
+    ///
+    /// By default, the read timeout of the client is extended by the time it takes to send the
+    /// request body at 64 KiB per second.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Uploads the file in parts to presigned URLs, for files too large for a single request.
+    ///
+    /// Part `n` (starting at 1) with `part_size` bytes is uploaded to `part_urls[n - 1]`, the last
+    /// part may be smaller. Every part response must have an `ETag` header. The upload is then
+    /// completed with a `POST` request to the URL of the upload, with a
+    /// `CompleteMultipartUpload` document listing the parts and their `ETag`s as the body, as
+    /// expected by S3 compatible storage. Parts are sent without a `Content-Type` header.
+    ///
+    /// Fails with [`TransferError::NotEnoughPartUrls`] if the file needs more parts than there
+    /// are URLs. Unused URLs are ignored.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use libcnb::transfer::Upload;
+    ///
+    /// // Presigned by the artifact store, e.g. for `UploadPart` and `CompleteMultipartUpload`.
+    /// let part_urls: Vec<String> = (1..=3)
+    ///     .map(|part| format!("https://example.com/app.tar.gz?partNumber={}&uploadId=42", part))
+    ///     .collect();
+    ///
+    /// Upload::new("/workspace/app.tar.gz", "https://example.com/app.tar.gz?uploadId=42")
+    ///     .multipart(part_urls, 64 * 1024 * 1024)
+    ///     .send()
+    ///     .unwrap();
+    /// ```
+    #[must_use]
+    pub fn multipart(mut self, part_urls: Vec<String>, part_size: u64) -> Self {
+        self.multipart = Some(Multipart {
+            part_urls,
+            part_size: part_size.max(1),
+        });
+        self
+    }
+
+    /// Uses the given client instead of the default [`TransferClient`].
+    #[must_use]
+    pub fn client(mut self, client: TransferClient) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Uploads the file.
+    pub fn send(self) -> Result<UploadReceipt, TransferError> {
+        if let Some(multipart) = &self.multipart {
+            return self.send_multipart(multipart);
+        }
+
+        let (response, digests) = self.put(&self.url, 0, None, |_| {})?;
+        Ok(UploadReceipt {
+            size: digests.len,
+            checksum: Checksum::from_digest(ChecksumAlgorithm::Sha256, &digests.sha256),
+            etag: etag(&response),
+        })
+    }
+
+    /// Uploads multiple files one after another, stopping at the first failure.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use libcnb::transfer::Upload;
+    ///
+    /// let receipts = Upload::send_all(vec![
+    ///     Upload::new("/workspace/app.tar.gz", "https://example.com/artifacts/app.tar.gz"),
+    ///     Upload::new("/workspace/sbom.json", "https://example.com/artifacts/sbom.json"),
+    /// ])
+    /// .unwrap();
+    /// ```
+    pub fn send_all(
+        uploads: impl IntoIterator<Item = Upload>,
+    ) -> Result<Vec<UploadReceipt>, TransferError> {
+        uploads.into_iter().map(Upload::send).collect()
+    }
+
+    /// Uploads the whole file with a single request and returns the response.
+    ///
+    /// Only the custom headers are sent, neither a `Content-Type` nor checksum headers.
+    pub(crate) fn send_request(self) -> Result<Response, TransferError> {
+        let len = self.file_path.metadata()?.len();
+        let client = self.transfer_client()?;
+        let file_path = &self.file_path;
+        let response = client.send(
+            Method::PUT,
+            &self.url,
+            &self.custom_headers()?,
+            Some(self.timeout_for(&client, len)),
+            || Ok(Some(Body::sized(File::open(file_path)?.take(len), len))),
+        )?;
+
+        check_status(response)
+    }
+
+    fn send_multipart(&self, multipart: &Multipart) -> Result<UploadReceipt, TransferError> {
+        let len = self.file_path.metadata()?.len();
+
+        // Empty files are uploaded as a single empty part.
+        let parts = ((len + multipart.part_size - 1) / multipart.part_size).max(1);
+        if parts > multipart.part_urls.len() as u64 {
+            return Err(TransferError::NotEnoughPartUrls {
+                parts,
+                urls: multipart.part_urls.len(),
+            });
+        }
+
+        let mut file_hasher = ChecksumAlgorithm::Sha256.hasher();
+        let mut completion = String::from("<CompleteMultipartUpload>");
+        for (index, part_url) in (0..parts).zip(&multipart.part_urls) {
+            let offset = index * multipart.part_size;
+            let part_len = multipart.part_size.min(len - offset);
+
+            let (response, _) = self.put(part_url, offset, Some(part_len), |data| {
+                file_hasher.update(data);
+            })?;
+            let part_etag = etag(&response)
+                .ok_or_else(|| TransferError::MissingETag(redact(response.url())))?;
+
+            let _ = write!(
+                completion,
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                index + 1,
+                escape_xml(&part_etag)
+            );
+        }
+        completion.push_str("</CompleteMultipartUpload>");
+
+        let client = self.transfer_client()?;
+        let mut headers = self.custom_headers()?;
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/xml"));
+        // Completing the upload takes longer for larger files.
+        let response = check_status(client.send(
+            Method::POST,
+            &self.url,
+            &headers,
+            Some(self.timeout_for(&client, len)),
+            || Ok(Some(Body::from(completion.clone()))),
+        )?)?;
+
+        // S3 reports failures of long running completions in the body of a `200 OK` response.
+        let etag = etag(&response);
+        let url = response.url().clone();
+        let body = response.text()?;
+        if body.contains("<Error>") {
+            return Err(TransferError::UploadFailed {
+                url: redact(&url),
+                status: 200,
+                body: truncate(body),
+            });
+        }
+
+        Ok(UploadReceipt {
+            size: len,
+            checksum: file_hasher.finish(),
+            etag,
+        })
+    }
+
+    /// Uploads `len` bytes starting at `offset`, or the whole file, to `url`. The uploaded data
+    /// is passed to `inspect` once, before the first attempt.
+    fn put(
+        &self,
+        url: &str,
+        offset: u64,
+        len: Option<u64>,
+        inspect: impl FnMut(&[u8]),
+    ) -> Result<(Response, Digests), TransferError> {
+        let len = match len {
+            Some(len) => len,
+            None => self.file_path.metadata()?.len(),
+        };
+        let digests = Digests::read(&self.file_path, offset, len, inspect)?;
+
+        let mut headers = self.custom_headers()?;
+        if self.multipart.is_none() {
+            let content_type = self
+                .content_type
+                .clone()
+                .unwrap_or_else(|| String::from(content_type_for(&self.file_path)));
+            headers.insert(CONTENT_TYPE, header_value(&content_type)?);
+        }
+        if self.checksum_headers {
+            headers.insert("content-md5", header_value(&base64(&digests.md5))?);
+            headers.insert(
+                "x-amz-checksum-sha256",
+                header_value(&base64(&digests.sha256))?,
+            );
+        }
+
+        let client = self.transfer_client()?;
+        let file_path = &self.file_path;
+        let timeout = self.timeout_for(&client, len);
+        let response = client.send(Method::PUT, url, &headers, Some(timeout), || {
+            let mut file = File::open(file_path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            Ok(Some(Body::sized(file.take(len), len)))
+        })?;
+
+        Ok((check_status(response)?, digests))
+    }
+
+    /// The timeout of a request with a body of `len` bytes.
+    fn timeout_for(&self, client: &TransferClient, len: u64) -> Duration {
+        self.timeout.unwrap_or_else(|| {
+            client.read_timeout() + Duration::from_secs(len / MIN_UPLOAD_BYTES_PER_SECOND)
+        })
+    }
+
+    fn transfer_client(&self) -> Result<TransferClient, TransferError> {
+        match &self.client {
+            Some(client) => Ok(client.clone()),
+            None => TransferClient::new(),
+        }
+    }
+
+    fn custom_headers(&self) -> Result<HeaderMap, TransferError> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| TransferError::InvalidHeader(name.clone()))?;
+            headers.append(name, header_value(value)?);
+        }
+
+        Ok(headers)
+    }
+}
+
+/// The checksums of an uploaded file or part.
+struct Digests {
+    len: u64,
+    md5: [u8; 16],
+    sha256: Vec<u8>,
+}
+
+impl Digests {
+    fn read(
+        path: &Path,
+        offset: u64,
+        len: u64,
+        mut inspect: impl FnMut(&[u8]),
+    ) -> io::Result<Digests> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut content = file.take(len);
+
+        let mut md5 = Md5::new();
+        let mut sha256 = sha2::Sha256::new();
+        let mut buffer = vec![0; 64 * 1024];
+        let mut read_len = 0;
+        loop {
+            let read = content.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+
+            md5.update(&buffer[..read]);
+            sha256.update(&buffer[..read]);
+            inspect(&buffer[..read]);
+            read_len += read as u64;
+        }
+
+        if read_len != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} changed while it was uploaded", path.display()),
+            ));
+        }
+
+        Ok(Digests {
+            len,
+            md5: md5.finalize().into(),
+            sha256: sha256.finalize().to_vec(),
+        })
+    }
+}
+
+fn check_status(response: Response) -> Result<Response, TransferError> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    let url = redact(response.url());
+    let status = response.status().as_u16();
+    let body = response.text().unwrap_or_default();
+
+    Err(TransferError::UploadFailed {
+        url,
+        status,
+        body: truncate(body),
+    })
+}
+
+fn etag(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(String::from)
+}
+
+/// Removes the query from URLs, it contains the signature of presigned URLs.
+fn redact(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_query(None);
+    url.set_fragment(None);
+    String::from(url)
+}
+
+fn truncate(body: String) -> String {
+    match body.char_indices().nth(MAX_ERROR_BODY_LEN) {
+        Some((index, _)) => format!("{}...", &body[..index]),
+        None => body,
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, TransferError> {
+    HeaderValue::from_str(value).map_err(|_| TransferError::InvalidHeader(String::from(value)))
+}
+
+fn base64(data: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(data)
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Derives the content type from the file name, `application/octet-stream` for unknown types.
+fn content_type_for(path: &Path) -> &'static str {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    [
+        (".tar.gz", "application/gzip"),
+        (".tgz", "application/gzip"),
+        (".gz", "application/gzip"),
+        (".tar.xz", "application/x-xz"),
+        (".xz", "application/x-xz"),
+        (".tar.bz2", "application/x-bzip2"),
+        (".bz2", "application/x-bzip2"),
+        (".tar.zst", "application/zstd"),
+        (".zst", "application/zstd"),
+        (".tar", "application/x-tar"),
+        (".zip", "application/zip"),
+        (".jar", "application/java-archive"),
+        (".json", "application/json"),
+        (".toml", "application/toml"),
+        (".txt", "text/plain"),
+    ]
+    .iter()
+    .find(|(extension, _)| name.ends_with(extension))
+    .map_or("application/octet-stream", |(_, content_type)| content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{serve, serve_with_headers};
+    use crate::transfer::ClientConfig;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// The method, path, selected headers and body of a request received by the test server.
+    type Recorded = (String, String, Vec<(String, String)>, Vec<u8>);
+
+    fn record(requests: &Arc<Mutex<Vec<Recorded>>>, request: &crate::test_support::TestRequest) {
+        let headers = request
+            .headers
+            .iter()
+            .filter(|(name, _)| name.starts_with("content-") || name.starts_with("x-"))
+            .cloned()
+            .collect();
+
+        requests.lock().unwrap().push((
+            request.method.clone(),
+            request.path.clone(),
+            headers,
+            request.body.clone(),
+        ));
+    }
+
+    fn header<'a>(recorded: &'a Recorded, name: &str) -> Option<&'a str> {
+        recorded
+            .2
+            .iter()
+            .find(|(header_name, _)| header_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn client() -> TransferClient {
+        ClientConfig::new()
+            .retry_backoff(Duration::from_millis(1))
+            .build()
+            .unwrap()
+    }
+
+    fn file(tmpdir: &tempfile::TempDir, name: &str, contents: &str) -> PathBuf {
+        let path = tmpdir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn it_uploads_with_content_type_and_checksum_headers() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let server_requests = Arc::clone(&requests);
+        let url = serve_with_headers(move |request| {
+            record(&server_requests, request);
+            (
+                200,
+                vec![(String::from("ETag"), String::from("\"3a7d\""))],
+                Vec::new(),
+            )
+        });
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let receipt = client()
+            .upload(
+                file(&tmpdir, "app.tar.gz", "archive"),
+                format!("{}/app", url),
+            )
+            .header("x-amz-meta-buildpack", "heroku/ruby")
+            .checksum_headers(true)
+            .send()
+            .unwrap();
+
+        assert_eq!(
+            receipt,
+            UploadReceipt {
+                size: 7,
+                checksum: Checksum::from_str(
+                    "sha256:0eb3e36bfb24dcd9bb1d1bece1531216b59539a8fde17ee80224af0653c92aa3"
+                )
+                .unwrap(),
+                etag: Some(String::from("\"3a7d\"")),
+            }
+        );
+
+        let requests = requests.lock().unwrap();
+        let request = &requests[0];
+        assert_eq!(request.0, "PUT");
+        assert_eq!(request.1, "/app");
+        assert_eq!(request.3, b"archive");
+        assert_eq!(header(request, "content-type"), Some("application/gzip"));
+        assert_eq!(
+            header(request, "content-md5"),
+            Some("iI0O42GvNgNzbzITHnsgog==")
+        );
+        assert_eq!(
+            header(request, "x-amz-checksum-sha256"),
+            Some("DrPja/sk3Nm7HRvs4VMSFrWVOaj94X7oAiSvBlPJKqM=")
+        );
+        assert_eq!(header(request, "x-amz-meta-buildpack"), Some("heroku/ruby"));
+    }
+
+    #[test]
+    fn it_fails_on_error_status_without_leaking_signatures() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let server_requests = Arc::clone(&requests);
+        let url = serve(move |request| {
+            record(&server_requests, request);
+            (403, b"<Error><Code>AccessDenied</Code></Error>".to_vec())
+        });
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = file(&tmpdir, "app.bin", "archive");
+
+        match client()
+            .upload(&path, format!("{}/app.bin?X-Amz-Signature=s3cr3t", url))
+            .content_type("application/vnd.cnb")
+            .checksum_headers(false)
+            .send()
+        {
+            Err(TransferError::UploadFailed {
+                url: failed_url,
+                status,
+                body,
+            }) => {
+                assert_eq!(failed_url, format!("{}/app.bin", url));
+                assert_eq!(status, 403);
+                assert_eq!(body, "<Error><Code>AccessDenied</Code></Error>");
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        {
+            let requests = requests.lock().unwrap();
+            assert_eq!(
+                header(&requests[0], "content-type"),
+                Some("application/vnd.cnb")
+            );
+            assert_eq!(header(&requests[0], "content-md5"), None);
+            assert_eq!(header(&requests[0], "x-amz-checksum-sha256"), None);
+        }
+
+        // Client errors are not retried.
+        assert!(matches!(
+            client().put(&path, format!("{}/app.bin", url)),
+            Err(TransferError::UploadFailed { status: 403, .. })
+        ));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(header(&requests[1], "content-type"), None);
+        assert_eq!(header(&requests[1], "content-md5"), None);
+    }
+
+    #[test]
+    fn it_scales_the_upload_timeout_to_the_file_size() {
+        let url = serve(|_| {
+            thread::sleep(Duration::from_millis(500));
+            (200, Vec::new())
+        });
+
+        let client = ClientConfig::new()
+            .retries(0)
+            .read_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("app.tar.gz");
+        std::fs::write(&path, vec![0; 128 * 1024]).unwrap();
+
+        assert!(client.put(&path, format!("{}/app", url)).is_ok());
+        assert!(client.upload(&path, format!("{}/app", url)).send().is_ok());
+
+        assert!(matches!(
+            client
+                .upload(&path, format!("{}/app", url))
+                .timeout(Duration::from_millis(100))
+                .send(),
+            Err(TransferError::HttpError(error)) if error.is_timeout()
+        ));
+    }
+
+    #[test]
+    fn it_uploads_multiple_files() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let server_requests = Arc::clone(&requests);
+        let url = serve(move |request| {
+            record(&server_requests, request);
+            (201, Vec::new())
+        });
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let receipts = Upload::send_all(vec![
+            client().upload(
+                file(&tmpdir, "app.tar.xz", "archive"),
+                format!("{}/app.tar.xz", url),
+            ),
+            client().upload(
+                file(&tmpdir, "sbom.json", "{}"),
+                format!("{}/sbom.json", url),
+            ),
+        ])
+        .unwrap();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[1].size, 2);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0].1, "/app.tar.xz");
+        assert_eq!(
+            header(&requests[0], "content-type"),
+            Some("application/x-xz")
+        );
+        assert_eq!(requests[1].1, "/sbom.json");
+        assert_eq!(
+            header(&requests[1], "content-type"),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn it_uploads_in_parts() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let server_requests = Arc::clone(&requests);
+        let url = serve_with_headers(move |request| {
+            record(&server_requests, request);
+            let etag = format!("\"etag-{}\"", request.path.rsplit('=').next().unwrap());
+            (200, vec![(String::from("ETag"), etag)], Vec::new())
+        });
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let part_urls = (1..=4)
+            .map(|part| format!("{}/app?partNumber={}", url, part))
+            .collect();
+
+        let receipt = client()
+            .upload(
+                file(&tmpdir, "app.tar.gz", "0123456789abcdefghij"),
+                format!("{}/app?uploadId=42", url),
+            )
+            .multipart(part_urls, 8)
+            .checksum_headers(true)
+            .send()
+            .unwrap();
+        assert_eq!(receipt.size, 20);
+        assert_eq!(
+            receipt.checksum.hex(),
+            "6bc14bdc4517a7a682c6910de2e2946eb8e1ecd04090728fef6d092a7ceb62c5"
+        );
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 4);
+        for (request, (part, body, md5)) in requests.iter().zip([
+            (1, "01234567", "Lp7DF+GXgZNY+8Q6/KfYNw=="),
+            (2, "89abcdef", "PWyUbqDan0nuTgF6ZnMvUQ=="),
+            (3, "ghij", "0J6TMBTUxpyk4kmPF2C98w=="),
+        ]) {
+            assert_eq!(request.0, "PUT");
+            assert_eq!(request.1, format!("/app?partNumber={}", part));
+            assert_eq!(request.3, body.as_bytes());
+            assert_eq!(header(request, "content-md5"), Some(md5));
+        }
+
+        assert_eq!(requests[3].0, "POST");
+        assert_eq!(requests[3].1, "/app?uploadId=42");
+        assert_eq!(
+            header(&requests[3], "content-type"),
+            Some("application/xml")
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&requests[3].3),
+            "<CompleteMultipartUpload>\
+             <Part><PartNumber>1</PartNumber><ETag>&quot;etag-1&quot;</ETag></Part>\
+             <Part><PartNumber>2</PartNumber><ETag>&quot;etag-2&quot;</ETag></Part>\
+             <Part><PartNumber>3</PartNumber><ETag>&quot;etag-3&quot;</ETag></Part>\
+             </CompleteMultipartUpload>"
+        );
+    }
+
+    #[test]
+    fn it_rejects_incomplete_multipart_uploads() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = file(&tmpdir, "app.tar.gz", "0123456789abcdefghij");
+
+        let url = serve(|_| (200, Vec::new()));
+        assert!(matches!(
+            client()
+                .upload(&path, format!("{}/app", url))
+                .multipart(vec![format!("{}/app?partNumber=1", url)], 8)
+                .send(),
+            Err(TransferError::NotEnoughPartUrls { parts: 3, urls: 1 })
+        ));
+
+        assert!(matches!(
+            client()
+                .upload(&path, format!("{}/app", url))
+                .multipart(vec![format!("{}/app?partNumber=1", url)], 32)
+                .send(),
+            Err(TransferError::MissingETag(part_url)) if part_url == format!("{}/app", url)
+        ));
+
+        // Completions can fail after the response status was sent.
+        let url = serve_with_headers(|request| {
+            if request.method == "POST" {
+                (
+                    200,
+                    Vec::new(),
+                    b"<Error><Code>InternalError</Code></Error>".to_vec(),
+                )
+            } else {
+                (
+                    200,
+                    vec![(String::from("ETag"), String::from("\"1\""))],
+                    Vec::new(),
+                )
+            }
+        });
+        assert!(matches!(
+            client()
+                .upload(&path, format!("{}/app", url))
+                .multipart(vec![format!("{}/app?partNumber=1", url)], 32)
+                .send(),
+            Err(TransferError::UploadFailed { status: 200, .. })
+        ));
+    }
+}